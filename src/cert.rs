@@ -0,0 +1,318 @@
+use std::io;
+
+use camino::Utf8PathBuf;
+
+use crate::date;
+use crate::kind;
+use crate::manifest;
+
+// Written inside each snapshot that holds certificates, so their expiry can be checked without
+// the passphrase
+pub const EXPIRY_FILENAME: &str = "cert-expiry.txt";
+
+const CERTIFICATE_LABEL: &str = "CERTIFICATE";
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_CONTEXT_0: u8 = 0xa0;
+const TAG_CONTEXT_1: u8 = 0xa1;
+
+// Minimal DER reader, covering only what is needed to walk certificates and private keys
+struct Der<'a>(&'a [u8]);
+impl<'a> Der<'a> {
+    fn peek_tag(&self) -> Option<u8> {
+        self.0.first().copied()
+    }
+
+    fn read(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.0.split_first()?;
+        let (&first, mut rest) = rest.split_first()?;
+
+        let len = if first < 0x80 {
+            first as usize
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return None;
+            }
+            let (bytes, tail) = rest.split_at(count);
+            rest = tail;
+            bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize)
+        };
+        if rest.len() < len {
+            return None;
+        }
+
+        let (content, tail) = rest.split_at(len);
+        self.0 = tail;
+        Some((tag, content))
+    }
+
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        self.read()
+            .filter(|(t, _)| *t == tag)
+            .map(|(_, content)| content)
+    }
+}
+
+fn parse_time(tag: u8, content: &[u8]) -> Option<i64> {
+    let text = std::str::from_utf8(content).ok()?.strip_suffix('Z')?;
+    let (year, rest) = match tag {
+        TAG_UTC_TIME => {
+            let (yy, rest) = text.split_at_checked(2)?;
+            let yy: i64 = yy.parse().ok()?;
+            // RFC 5280: two-digit years from 50 onwards belong to the 20th century
+            (if yy >= 50 { 1900 + yy } else { 2000 + yy }, rest)
+        }
+        TAG_GENERALIZED_TIME => {
+            let (yyyy, rest) = text.split_at_checked(4)?;
+            (yyyy.parse().ok()?, rest)
+        }
+        _ => return None,
+    };
+    if rest.len() != 10 || !rest.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let field = |i: usize| rest[i..i + 2].parse::<i64>().unwrap();
+
+    let days = date::days_from_civil(year, field(0), field(2));
+    Some(days * 86400 + field(4) * 3600 + field(6) * 60 + field(8))
+}
+
+struct Certificate<'a> {
+    not_after: i64,
+    // the subjectPublicKey BIT STRING, including its leading unused-bits byte
+    public_key: &'a [u8],
+}
+
+fn parse_certificate(der: &[u8]) -> Option<Certificate<'_>> {
+    let certificate = Der(der).expect(TAG_SEQUENCE)?;
+    let tbs = Der(certificate).expect(TAG_SEQUENCE)?;
+
+    let mut tbs = Der(tbs);
+    if tbs.peek_tag() == Some(TAG_CONTEXT_0) {
+        let _version = tbs.read()?;
+    }
+    let _serial = tbs.expect(TAG_INTEGER)?;
+    let _signature = tbs.expect(TAG_SEQUENCE)?;
+    let _issuer = tbs.expect(TAG_SEQUENCE)?;
+    let validity = tbs.expect(TAG_SEQUENCE)?;
+    let _subject = tbs.expect(TAG_SEQUENCE)?;
+    let spki = tbs.expect(TAG_SEQUENCE)?;
+
+    let mut validity = Der(validity);
+    let _not_before = validity.read()?;
+    let (tag, content) = validity.read()?;
+    let not_after = parse_time(tag, content)?;
+
+    let mut spki = Der(spki);
+    let _algorithm = spki.expect(TAG_SEQUENCE)?;
+    let public_key = spki.expect(TAG_BIT_STRING)?;
+
+    Some(Certificate {
+        not_after,
+        public_key,
+    })
+}
+
+// Secrets declared as `kind=pem-cert`, or with no kind and holding a PEM certificate
+pub fn is_certificate(secret: &manifest::Secret, content: &[u8]) -> bool {
+    match secret.kind {
+        Some(kind) => kind == kind::Kind::PemCert,
+        None => std::str::from_utf8(content)
+            .is_ok_and(|text| text.contains(&format!("-----BEGIN {CERTIFICATE_LABEL}-----"))),
+    }
+}
+
+// The earliest notAfter among the certificates of a PEM file (the leaf and any chain bundled with
+// it), as seconds since the epoch. None if the content holds no parsable certificate
+pub fn not_after(content: &[u8]) -> Option<i64> {
+    let text = std::str::from_utf8(content).ok()?;
+    kind::pem_blocks(text, CERTIFICATE_LABEL)?
+        .iter()
+        .map(|der| parse_certificate(der).map(|c| c.not_after))
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .min()
+}
+
+fn strip_leading_zeros(integer: &[u8]) -> &[u8] {
+    let zeros = integer.iter().take_while(|b| **b == 0).count();
+    &integer[zeros..]
+}
+
+// (modulus, public exponent) of a DER `RSAPublicKey` or `RSAPrivateKey`
+fn rsa_components(der: &[u8], private: bool) -> Option<(&[u8], &[u8])> {
+    let mut key = Der(Der(der).expect(TAG_SEQUENCE)?);
+    if private {
+        let _version = key.expect(TAG_INTEGER)?;
+    }
+    let modulus = key.expect(TAG_INTEGER)?;
+    let exponent = key.expect(TAG_INTEGER)?;
+
+    Some((strip_leading_zeros(modulus), strip_leading_zeros(exponent)))
+}
+
+enum PublicHalf<'a> {
+    Rsa(&'a [u8], &'a [u8]),
+    // the public point of an EC key, as a BIT STRING including its unused-bits byte
+    Ec(&'a [u8]),
+}
+
+fn ec_public_half(der: &[u8]) -> Option<PublicHalf<'_>> {
+    let mut key = Der(Der(der).expect(TAG_SEQUENCE)?);
+    let _version = key.expect(TAG_INTEGER)?;
+    let _private = key.expect(TAG_OCTET_STRING)?;
+    while let Some((tag, content)) = key.read() {
+        if tag == TAG_CONTEXT_1 {
+            return Der(content).expect(TAG_BIT_STRING).map(PublicHalf::Ec);
+        }
+    }
+
+    None
+}
+
+fn pkcs8_public_half(der: &[u8]) -> Option<PublicHalf<'_>> {
+    let mut key = Der(Der(der).expect(TAG_SEQUENCE)?);
+    let _version = key.expect(TAG_INTEGER)?;
+    let _algorithm = key.expect(TAG_SEQUENCE)?;
+    let inner = key.expect(TAG_OCTET_STRING)?;
+
+    rsa_components(inner, true)
+        .map(|(n, e)| PublicHalf::Rsa(n, e))
+        .or_else(|| ec_public_half(inner))
+}
+
+fn private_public_half<'a>(blocks: &'a [(&str, Vec<u8>)]) -> Option<PublicHalf<'a>> {
+    blocks.iter().find_map(|(label, der)| match *label {
+        "RSA PRIVATE KEY" => rsa_components(der, true).map(|(n, e)| PublicHalf::Rsa(n, e)),
+        "EC PRIVATE KEY" => ec_public_half(der),
+        "PRIVATE KEY" => pkcs8_public_half(der),
+        _ => None,
+    })
+}
+
+pub enum KeyMatch {
+    Match,
+    Mismatch,
+    // the key is encrypted, or of an algorithm whose public half can't be extracted without
+    // implementing it (e.g. Ed25519)
+    Unknown,
+}
+
+// Checks whether the leaf certificate of a PEM file was issued for the given PEM private key
+pub fn key_matches(certificate: &[u8], private_key: &[u8]) -> KeyMatch {
+    let (Ok(certificate), Ok(private_key)) = (
+        std::str::from_utf8(certificate),
+        std::str::from_utf8(private_key),
+    ) else {
+        return KeyMatch::Unknown;
+    };
+
+    let Some(leaf) = kind::pem_blocks(certificate, CERTIFICATE_LABEL)
+        .and_then(|blocks| blocks.into_iter().next())
+    else {
+        return KeyMatch::Unknown;
+    };
+    let Some(leaf) = parse_certificate(&leaf) else {
+        return KeyMatch::Unknown;
+    };
+
+    let blocks: Vec<(&str, Vec<u8>)> = ["RSA PRIVATE KEY", "EC PRIVATE KEY", "PRIVATE KEY"]
+        .into_iter()
+        .filter_map(|label| {
+            kind::pem_blocks(private_key, label)
+                .and_then(|blocks| blocks.into_iter().next())
+                .map(|der| (label, der))
+        })
+        .collect();
+
+    let matches = match private_public_half(&blocks) {
+        Some(PublicHalf::Rsa(n, e)) => leaf
+            .public_key
+            .split_first()
+            .and_then(|(_, der)| rsa_components(der, false))
+            .is_some_and(|(cert_n, cert_e)| cert_n == n && cert_e == e),
+        Some(PublicHalf::Ec(point)) => leaf.public_key == point,
+        None => return KeyMatch::Unknown,
+    };
+
+    if matches {
+        KeyMatch::Match
+    } else {
+        KeyMatch::Mismatch
+    }
+}
+
+// Private keys conventionally stored next to a certificate: `X.key` for `X.crt`/`X.pem`, and
+// certbot's `privkey.pem` for `cert.pem`/`fullchain.pem`
+pub fn key_candidates(certificate: &Utf8PathBuf) -> Vec<Utf8PathBuf> {
+    let mut candidates = vec![certificate.with_extension("key")];
+    if matches!(certificate.file_name(), Some("cert.pem" | "fullchain.pem")) {
+        candidates.push(certificate.with_file_name("privkey.pem"));
+    }
+    candidates
+}
+
+// The secrets listed next to a private key conventionally stored with them, as (certificate, key).
+// Whether they hold a certificate is only known from their content
+pub fn key_pairs(secrets: &[manifest::Secret]) -> Vec<(&manifest::Secret, &manifest::Secret)> {
+    secrets
+        .iter()
+        .filter_map(|certificate| {
+            key_candidates(&certificate.path)
+                .iter()
+                .find_map(|candidate| secrets.iter().find(|s| &s.path == candidate))
+                .map(|key| (certificate, key))
+        })
+        .collect()
+}
+
+pub fn warn_key_mismatches(mismatches: &[(&Utf8PathBuf, &Utf8PathBuf)]) {
+    if mismatches.is_empty() {
+        return;
+    }
+
+    println!("Warning: these certificates were not issued for the private key next to them:");
+    for (certificate, key) in mismatches {
+        println!("  - {certificate} (key '{key}')");
+    }
+    println!();
+}
+
+pub fn render_expiries(expiries: &[(Utf8PathBuf, i64)]) -> String {
+    expiries
+        .iter()
+        .map(|(path, not_after)| format!("{}  {path}\n", date::format_datetime(*not_after)))
+        .collect()
+}
+
+// Reads the expiries recorded in a snapshot. Snapshots that hold no certificate (or that were
+// taken before expiries were recorded) have no expiry file, which reads as no certificates
+pub fn read_expiries(snapshot: &Utf8PathBuf) -> io::Result<Vec<(Utf8PathBuf, i64)>> {
+    let path = snapshot.join(EXPIRY_FILENAME);
+    let content = match crate::snapshot::read_file(snapshot, &Utf8PathBuf::from(EXPIRY_FILENAME)) {
+        Ok(content) => String::from_utf8(content).map_err(io::Error::other)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("ill-formatted '{path}'"),
+        )
+    };
+    let mut expiries = Vec::new();
+    for line in content.lines() {
+        let (not_after, cert) = line.split_once("  ").ok_or_else(invalid)?;
+        let not_after = date::parse_datetime(not_after).ok_or_else(invalid)?;
+        expiries.push((Utf8PathBuf::from(cert), not_after));
+    }
+
+    Ok(expiries)
+}
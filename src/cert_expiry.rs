@@ -0,0 +1,213 @@
+use std::fs;
+
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use crate::cert;
+use crate::date;
+use crate::layout;
+use crate::manifest;
+use crate::snapshot;
+
+#[derive(Error, Debug)]
+pub enum CertExpiryError {
+    #[error("source path '{0}' does not exist")]
+    MissingSourcePath(Utf8PathBuf),
+    #[error("source path '{0}' is not a directory")]
+    SourceNotDir(Utf8PathBuf),
+
+    #[error("source '{0}' is neither a secrets directory, a snapshot nor a container of snapshots")]
+    UnknownSource(Utf8PathBuf),
+
+    #[error("'{0}' is not a valid warning window (expected e.g. 30d, 2w or 1m)")]
+    InvalidWindow(String),
+
+    #[error("failed to load manifest\n{0}")]
+    LoadManifest(manifest::ManifestError),
+
+    #[error("failed to read file at '{0}'\n{1}")]
+    Read(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to read the certificate expiries recorded in snapshot '{0}'\n{1}")]
+    ReadExpiries(Utf8PathBuf, std::io::Error),
+
+    #[error("snapshot '{0}' has a private layout, it records no certificate expiries")]
+    PrivateLayout(Utf8PathBuf),
+
+    #[error("failed to find the newest snapshot in container '{0}'\n{1}")]
+    FindNewest(Utf8PathBuf, std::io::Error),
+
+    #[error("{expired} certificates are expired and {expiring} expire soon")]
+    Expiring { expired: usize, expiring: usize },
+}
+impl CertExpiryError {
+    fn read(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::Read(path.clone(), e)
+    }
+
+    fn read_expiries(snapshot: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::ReadExpiries(snapshot.clone(), e)
+    }
+}
+
+#[derive(PartialEq, PartialOrd)]
+enum Status {
+    Expired,
+    Expiring,
+    Ok,
+}
+
+fn status(not_after: i64, now: i64, window: i64) -> Status {
+    if not_after <= now {
+        Status::Expired
+    } else if not_after <= now + window {
+        Status::Expiring
+    } else {
+        Status::Ok
+    }
+}
+
+pub fn parse_window(within: String) -> Result<i64, CertExpiryError> {
+    date::parse_days(&within)
+        .map(|days| days * 86400)
+        .ok_or(CertExpiryError::InvalidWindow(within))
+}
+
+// Prints a warning for the recorded certificates that are expired or expire within the window
+pub fn warn_expiring(expiries: &[(Utf8PathBuf, i64)], window: i64) {
+    let now = date::now();
+    let expiring: Vec<_> = expiries
+        .iter()
+        .filter(|(_, not_after)| status(*not_after, now, window) != Status::Ok)
+        .collect();
+    if expiring.is_empty() {
+        return;
+    }
+
+    println!("Warning: these certificates are expired or expire soon:");
+    for (path, not_after) in expiring {
+        let verb = if *not_after <= now {
+            "expired"
+        } else {
+            "expires"
+        };
+        println!("  - {path} ({verb} {})", date::format_date(*not_after));
+    }
+    println!();
+}
+
+// Reads the live certificates of a secrets directory. Certificates whose expiry can't be parsed are
+// returned with no expiry
+fn live_expiries(source: &Utf8PathBuf) -> Result<Vec<(Utf8PathBuf, Option<i64>)>, CertExpiryError> {
+    let secrets = manifest::load(source).map_err(CertExpiryError::LoadManifest)?;
+
+    let mut expiries = Vec::new();
+    // secrets read from a command are only known at export, their expiry is the one recorded there
+    for secret in secrets.iter().filter(|s| s.from_cmd.is_none()) {
+        let file = secret.location(source);
+        let content = fs::read(&file).map_err(CertExpiryError::read(&file))?;
+        if cert::is_certificate(secret, &content) {
+            expiries.push((secret.path.clone(), cert::not_after(&content)));
+        }
+    }
+
+    Ok(expiries)
+}
+
+fn recorded_expiries(
+    snapshot: &Utf8PathBuf,
+) -> Result<Vec<(Utf8PathBuf, Option<i64>)>, CertExpiryError> {
+    // which would tell the names of the certificates
+    if layout::is_private(snapshot) {
+        return Err(CertExpiryError::PrivateLayout(snapshot.clone()));
+    }
+
+    let expiries =
+        cert::read_expiries(snapshot).map_err(CertExpiryError::read_expiries(snapshot))?;
+
+    Ok(expiries
+        .into_iter()
+        .map(|(path, not_after)| (path, Some(not_after)))
+        .collect())
+}
+
+pub fn cert_expiry(source: String, within: String, strict: bool) -> Result<(), CertExpiryError> {
+    let source = {
+        let path = Utf8PathBuf::from(&source);
+        if !path.exists() {
+            return Err(CertExpiryError::MissingSourcePath(path));
+        } else if !path.is_dir() && !snapshot::is_tar(&path) {
+            return Err(CertExpiryError::SourceNotDir(path));
+        }
+        path
+    };
+
+    let window = parse_window(within)?;
+
+    // both secrets directories and snapshots hold a manifest, but only snapshots hold checksums
+    // of their whole content
+    let expiries = match snapshot::classify(&source) {
+        snapshot::SourceKind::Snapshot
+            if source.join("sha256sums.txt").exists() || snapshot::is_tar(&source) =>
+        {
+            println!("Certificates recorded in snapshot '{source}':");
+            recorded_expiries(&source)?
+        }
+        snapshot::SourceKind::Snapshot => {
+            println!("Certificates in secrets directory '{source}':");
+            live_expiries(&source)?
+        }
+        snapshot::SourceKind::Container => {
+            let newest = snapshot::newest(&source)
+                .map_err(|e| CertExpiryError::FindNewest(source.clone(), e))?
+                .ok_or(CertExpiryError::UnknownSource(source.clone()))?;
+            let newest = source.join(newest);
+            println!("Certificates recorded in the newest snapshot '{newest}':");
+            recorded_expiries(&newest)?
+        }
+        snapshot::SourceKind::Neither => return Err(CertExpiryError::UnknownSource(source)),
+    };
+    println!();
+
+    if expiries.is_empty() {
+        println!("No certificate found.");
+        return Ok(());
+    }
+
+    let now = date::now();
+    let mut rows: Vec<_> = expiries
+        .into_iter()
+        .map(|(path, not_after)| {
+            // a certificate that can't be parsed can't be trusted to be valid either
+            let status = not_after.map_or(Status::Expired, |n| status(n, now, window));
+            (status, not_after, path)
+        })
+        .collect();
+    rows.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap().then(a.1.cmp(&b.1)));
+
+    let width = rows.iter().map(|r| r.2.as_str().len()).max().unwrap_or(0);
+    for (status, not_after, path) in &rows {
+        let status = match status {
+            Status::Expired => "EXPIRED ",
+            Status::Expiring => "expiring",
+            Status::Ok => "ok      ",
+        };
+        let detail = match not_after {
+            Some(n) if *n <= now => format!("expired {}", date::format_date(*n)),
+            Some(n) => format!("expires {}", date::format_date(*n)),
+            None => "expiry could not be read".to_string(),
+        };
+        println!("{status}  {:width$}  {detail}", path.as_str());
+    }
+    println!();
+
+    let expired = rows.iter().filter(|r| r.0 == Status::Expired).count();
+    let expiring = rows.iter().filter(|r| r.0 == Status::Expiring).count();
+    if expired > 0 || (strict && expiring > 0) {
+        return Err(CertExpiryError::Expiring { expired, expiring });
+    }
+
+    println!("{expired} certificates expired, {expiring} expiring soon.");
+
+    Ok(())
+}
@@ -114,17 +114,23 @@ pub fn append_checksum(
     Ok(())
 }
 
-pub fn verify_file_checksum(file_path: &Utf8PathBuf) -> Result<(), ChecksumError> {
-    let re = regex::Regex::new(r"^([0-9a-fA-F]{64})  (.+)$").unwrap();
-
-    let sha_path = file_path.add_extension("sha256");
-
+// Reads the digest stored in a `.sha256` sidecar
+pub fn read_digest(sha_path: &Utf8PathBuf) -> Result<String, ChecksumError> {
     if !sha_path.exists() {
-        return Err(ChecksumError::MissingChecksum(sha_path));
+        return Err(ChecksumError::MissingChecksum(sha_path.clone()));
     }
 
     let sha_content =
-        fs::read_to_string(&sha_path).map_err(ChecksumError::read_checksum(&sha_path))?;
+        fs::read_to_string(sha_path).map_err(ChecksumError::read_checksum(sha_path))?;
+
+    parse_digest(&sha_content, sha_path)
+}
+
+// Parses the content of a `.sha256` sidecar read from elsewhere than a file of its own (such as a
+// snapshot archive), sha_path only naming it in errors
+pub fn parse_digest(sha_content: &str, sha_path: &Utf8PathBuf) -> Result<String, ChecksumError> {
+    let re = regex::Regex::new(r"^([0-9a-fA-F]{64})  (.+)$").unwrap();
+
     let sha_content = sha_content.trim();
 
     let caps = re
@@ -132,29 +138,78 @@ pub fn verify_file_checksum(file_path: &Utf8PathBuf) -> Result<(), ChecksumError
         .ok_or(ChecksumError::IllFormattedChecksum(sha_path.clone()))?;
     let (_, [digest, _]) = caps.extract();
 
+    Ok(digest.to_lowercase())
+}
+
+pub fn verify_file_checksum(file_path: &Utf8PathBuf) -> Result<(), ChecksumError> {
+    verify_checksum_at(file_path, &file_path.add_extension("sha256"))
+}
+
+// Checks a file against a sidecar stored anywhere, not necessarily next to it
+pub fn verify_checksum_at(
+    file_path: &Utf8PathBuf,
+    sha_path: &Utf8PathBuf,
+) -> Result<(), ChecksumError> {
     let file_content = fs::read(file_path).map_err(ChecksumError::read_source(file_path))?;
 
-    let actual_digest = sha256::digest(file_content);
+    verify_content(&file_content, file_path, sha_path)
+}
+
+// Checks content that was never written to disk against a sidecar, file_path only naming it in
+// errors
+pub fn verify_content(
+    content: &[u8],
+    file_path: &Utf8PathBuf,
+    sha_path: &Utf8PathBuf,
+) -> Result<(), ChecksumError> {
+    let digest = read_digest(sha_path)?;
+
+    verify_digest(content, &digest, file_path, sha_path)
+}
+
+// Checks content against a digest read from anywhere (such as the sealed checksums of a snapshot),
+// file_path and sha_path only naming them in errors
+pub fn verify_digest(
+    content: &[u8],
+    digest: &str,
+    file_path: &Utf8PathBuf,
+    sha_path: &Utf8PathBuf,
+) -> Result<(), ChecksumError> {
+    let actual_digest = sha256::digest(content);
 
     if actual_digest != digest {
-        return Err(ChecksumError::ChecksumMismatch(file_path.clone(), sha_path));
+        return Err(ChecksumError::ChecksumMismatch(
+            file_path.clone(),
+            sha_path.clone(),
+        ));
     }
 
     Ok(())
 }
 
 pub fn generate_file_checksum(file_path: &Utf8PathBuf) -> Result<(), ChecksumError> {
+    generate_checksum_at(file_path, &file_path.add_extension("sha256"))
+}
+
+// Writes the sidecar of a file, anywhere. A sidecar next to its file names it by its file name, one
+// stored elsewhere by its full path, so that `sha256sum -c` works from the sidecar's directory
+pub fn generate_checksum_at(
+    file_path: &Utf8PathBuf,
+    sha_path: &Utf8PathBuf,
+) -> Result<(), ChecksumError> {
     // TODO maybe unwrap here isn't ideal? But realistically, I am never going
     // to call this function on a path that doesn't have a filename, so come on
-    let filename = file_path.file_name().unwrap();
-    let sha_path = file_path.add_extension("sha256");
+    let filename = match sha_path.parent() == file_path.parent() {
+        true => file_path.file_name().unwrap(),
+        false => file_path.as_str(),
+    };
 
     let checksum = {
         let digest =
             sha256::digest(fs::read(file_path).map_err(ChecksumError::read_source(file_path))?);
         format!("{digest}  {filename}")
     };
-    fs::write(&sha_path, checksum + "\n").map_err(ChecksumError::write_checksum(&sha_path))?;
+    fs::write(sha_path, checksum + "\n").map_err(ChecksumError::write_checksum(sha_path))?;
 
     Ok(())
 }
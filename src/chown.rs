@@ -0,0 +1,114 @@
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    os::{
+        fd::AsRawFd,
+        unix::fs::{OpenOptionsExt, lchown},
+    },
+};
+
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use crate::chown_spec::ChownSpec;
+use crate::users;
+
+#[derive(Error, Debug)]
+pub enum ChownError {
+    #[error("user '{0}' does not exist on this host")]
+    UnknownUser(String),
+
+    #[error("group '{0}' does not exist on this host")]
+    UnknownGroup(String),
+
+    #[error("user '{0}' has no login group on this host")]
+    UnknownLoginGroup(String),
+
+    #[error(
+        "permission denied to give ownership to '{0}' (run as root, or use --skip-chown-chmod)"
+    )]
+    PermissionDenied(String),
+
+    #[error("failed to open file\n{0}")]
+    Open(io::Error),
+
+    #[error("failed to change owner\n{0}")]
+    Chown(io::Error),
+}
+
+// Numeric ids an owner spec resolves to. None leaves the user or group unchanged
+#[derive(Debug, Clone, Copy)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+// Resolves a name against the database, falling back to reading it as a numeric id (as chown does,
+// names win over ids that look the same)
+fn resolve_id(name: &str, lookup: impl Fn(&str) -> Option<u32>) -> Option<u32> {
+    lookup(name).or_else(|| {
+        users::is_numeric_id(name)
+            .then(|| name.parse().ok())
+            .flatten()
+    })
+}
+
+pub fn resolve(spec: &ChownSpec, db: &users::Database) -> Result<Owner, ChownError> {
+    let uid = spec
+        .user()
+        .map(|user| {
+            resolve_id(user, |n| db.uid(n)).ok_or(ChownError::UnknownUser(user.to_string()))
+        })
+        .transpose()?;
+
+    let gid = match (spec.group(), uid) {
+        (Some(group), _) => Some(
+            resolve_id(group, |n| db.gid(n)).ok_or(ChownError::UnknownGroup(group.to_string()))?,
+        ),
+        (None, Some(uid)) if spec.login_group() => Some(db.login_gid(uid).ok_or(
+            ChownError::UnknownLoginGroup(spec.user().unwrap().to_string()),
+        )?),
+        (None, _) => None,
+    };
+
+    Ok(Owner { uid, gid })
+}
+
+fn map_chown(spec: &ChownSpec) -> impl Fn(io::Error) -> ChownError {
+    |e| match e.kind() {
+        io::ErrorKind::PermissionDenied => ChownError::PermissionDenied(spec.as_str().to_string()),
+        _ => ChownError::Chown(e),
+    }
+}
+
+// Changes the owner of a file or of a symlink (not of its target). Regular files are changed
+// through a descriptor opened without following symlinks, so that a path swapped for a link after
+// being written can't redirect the change elsewhere. The descriptor is an O_PATH one, which needs
+// no read permission on the file (a 0200 secret can't be opened for reading by a non-root owner)
+pub fn chown(path: &Utf8PathBuf, spec: &ChownSpec, owner: Owner) -> Result<(), ChownError> {
+    let metadata = fs::symlink_metadata(path).map_err(ChownError::Open)?;
+    if metadata.is_symlink() {
+        return lchown(path, owner.uid, owner.gid).map_err(map_chown(spec));
+    }
+
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_PATH)
+        .open(path)
+        .map_err(ChownError::Open)?;
+    // fchown refuses O_PATH descriptors, fchownat with an empty path changes the one given
+    let result = unsafe {
+        libc::fchownat(
+            file.as_raw_fd(),
+            c"".as_ptr(),
+            owner.uid.unwrap_or(u32::MAX),
+            owner.gid.unwrap_or(u32::MAX),
+            libc::AT_EMPTY_PATH,
+        )
+    };
+    if result != 0 {
+        return Err(map_chown(spec)(io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
@@ -12,6 +12,23 @@ impl ChownSpec {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    pub fn user(&self) -> Option<&str> {
+        let user = self.0.split(':').next().unwrap_or("");
+        (!user.is_empty()).then_some(user)
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        self.0
+            .split_once(':')
+            .map(|(_, g)| g)
+            .filter(|g| !g.is_empty())
+    }
+
+    // `user:`, like chown, means the login group of the user
+    pub fn login_group(&self) -> bool {
+        self.user().is_some() && self.0.ends_with(':')
+    }
 }
 impl FromStr for ChownSpec {
     type Err = InvalidChownSpec;
@@ -1,5 +1,9 @@
 use clap::{Parser, Subcommand};
 
+use crate::hooks;
+use crate::init::Preset;
+use crate::snapshot;
+
 #[derive(Debug, Subcommand)]
 pub enum Command {
     /// Exports secrets to an export container
@@ -8,9 +12,61 @@ pub enum Command {
         #[clap(index = 1, value_name = "secrets-dir")]
         secrets_dir: String,
 
-        /// Path to the export container (a new timestamped snapshot is created inside it)
-        #[clap(index = 2, value_name = "export-dir")]
-        export_dir: String,
+        /// Path to the export container (a new timestamped snapshot is created inside it). Given several containers, the same snapshot is written to each of them
+        #[clap(index = 2, value_name = "export-dir", num_args = 1.., required = true)]
+        export_dir: Vec<String>,
+
+        /// Export secrets even if their content does not match the kind declared in the manifest
+        #[clap(long)]
+        skip_validation: bool,
+
+        /// Record the owner, mode and mtime of every secret in the snapshot, as if they were all annotated owner=auto mode=auto
+        #[clap(long)]
+        capture_metadata: bool,
+
+        /// Record the user.* extended attributes and the POSIX ACL of every secret in the snapshot
+        #[clap(long)]
+        capture_xattrs: bool,
+
+        /// Config holding the commands that from-cmd= annotations refer to, and the pre-export and post-export hooks [default: /etc/secs-man/config]
+        #[clap(long, value_name = "file")]
+        config: Option<String>,
+
+        /// Do not run the pre-export and post-export hooks
+        #[clap(long)]
+        no_hooks: bool,
+
+        /// Store the encrypted files once in the container, shared by every snapshot holding them, and reuse the ones of unchanged secrets (the container stays deduplicated for later exports)
+        #[clap(long)]
+        dedup: bool,
+
+        /// Write the snapshot as a directory, or as a single .tar archive of that directory (with an outer .sha256)
+        #[clap(long, value_name = "format", default_value = "dir")]
+        format: snapshot::Format,
+
+        /// Encrypt the checksums of the secrets into the snapshot's checksums.age instead of writing a plaintext .sha256 next to each secret, whose digest of a short secret could be brute-forced
+        #[clap(long)]
+        seal_checksums: bool,
+
+        /// Store the secrets under random names and the manifest as an encrypted index.age, so that the snapshot does not tell which secrets it holds (implies --seal-checksums)
+        #[clap(long)]
+        private_layout: bool,
+
+        /// Label recorded in the snapshot.json of the snapshot (e.g. before-wireguard-rotation), which import --label selects by
+        #[clap(long, value_name = "text")]
+        label: Option<String>,
+
+        /// Free-form note recorded in the snapshot.json of the snapshot
+        #[clap(long, value_name = "text")]
+        note: Option<String>,
+
+        /// When the snapshot can't be written to some of the containers, keep it in the others rather than removing it from all of them
+        #[clap(long)]
+        best_effort: bool,
+
+        /// List what would be encrypted and which missing sidecars would be generated, without writing anything nor asking for the passphrase
+        #[clap(long)]
+        dry_run: bool,
     },
 
     /// Verify the integrity of an existing export (already done when creating an export)
@@ -18,6 +74,26 @@ pub enum Command {
         /// Path to the export container (verifies every snapshot), or a specific snapshot inside it
         #[clap(index = 1, value_name = "export-dir")]
         export_dir: String,
+
+        /// Warn about the certificates of the (newest) snapshot expiring within this span (e.g. 30d, 2w, 1m)
+        #[clap(long, value_name = "span", default_value = "30d")]
+        cert_window: String,
+
+        /// Config holding the post-verify hook [default: /etc/secs-man/config]
+        #[clap(long, value_name = "file")]
+        config: Option<String>,
+
+        /// Do not run the post-verify hook
+        #[clap(long)]
+        no_hooks: bool,
+
+        /// Also decrypt every encrypted file in memory (asking for the passphrase) and check its plaintext against the checksum it was exported with
+        #[clap(long)]
+        decrypt: bool,
+
+        /// Only decrypt this many encrypted files, drawn at random across the snapshots
+        #[clap(long, value_name = "n", requires = "decrypt", value_parser = clap::value_parser!(u64).range(1..))]
+        sample: Option<u64>,
     },
 
     /// Imports secrets from an existing export
@@ -41,6 +117,276 @@ pub enum Command {
         /// Do not apply the manifest's owner/mode to restored files (leave them owned by the runner at 0600)
         #[clap(long)]
         skip_chown_chmod: bool,
+
+        /// Import secrets even if their content does not match the kind declared in the manifest
+        #[clap(long)]
+        skip_validation: bool,
+
+        /// Rename an owner before applying it, as from=to (e.g. alice=asmith, :staff=:wheel or 1000:1000=2001:2001). Can be repeated
+        #[clap(long, value_name = "from=to")]
+        map_owner: Vec<String>,
+
+        /// Read more owner renames from a file, one from=to per line
+        #[clap(long, value_name = "file")]
+        owner_map: Option<String>,
+
+        /// Write the secrets annotated with at= to these locations (with their owner and mode) rather than into the secrets directory, which only keeps their sidecars
+        #[clap(long)]
+        deploy: bool,
+
+        /// Config holding the commands that on-import= and to-cmd= annotations refer to, and the pre-import and post-import hooks [default: /etc/secs-man/config]
+        #[clap(long, value_name = "file")]
+        config: Option<String>,
+
+        /// Do not run the on-import commands of the secrets that changed, nor the pre-import and post-import hooks
+        #[clap(long, conflicts_with = "dry_run_hooks")]
+        no_hooks: bool,
+
+        /// List the on-import commands that would run instead of running them
+        #[clap(long)]
+        dry_run_hooks: bool,
+
+        /// What to do when an on-import command fails: stop and fail, or report it and carry on
+        #[clap(long, value_name = "policy", default_value = "abort")]
+        hook_failure: hooks::FailurePolicy,
+
+        /// Import the newest snapshot of the container with this label, rather than the newest one
+        #[clap(long, value_name = "text")]
+        label: Option<String>,
+
+        /// List the files and directories that would be created, the ones already identical, the ones that would conflict, and the chmod, chown and commands that would run, without writing or running anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Lists the secrets that are overdue or soon due for rotation, exiting with an error if any is overdue
+    RotationStatus {
+        /// Path to the secrets directory
+        #[clap(index = 1, value_name = "secrets-dir")]
+        secrets_dir: String,
+
+        /// Path to an export container, whose snapshots tell when each secret last changed. If omitted, the modification time of the sidecars is used instead
+        #[clap(index = 2, value_name = "container")]
+        container: Option<String>,
+
+        /// Report secrets due within this span as due soon (e.g. 14d, 2w, 1m)
+        #[clap(long, value_name = "span", default_value = "14d")]
+        within: String,
+
+        /// Exit with an error on secrets due soon too, not only on overdue ones
+        #[clap(long)]
+        strict: bool,
+    },
+
+    /// Lists the expiry of the certificates in a secrets directory or snapshot, exiting with an error if any is expired
+    CertExpiry {
+        /// Path to a secrets directory, a snapshot, or an export container (checks its newest snapshot)
+        #[clap(index = 1, value_name = "secrets-dir|snapshot")]
+        source: String,
+
+        /// Report certificates expiring within this span as expiring (e.g. 30d, 2w, 1m)
+        #[clap(long, value_name = "span", default_value = "30d")]
+        within: String,
+
+        /// Exit with an error on certificates expiring soon too, not only on expired ones
+        #[clap(long)]
+        strict: bool,
+    },
+
+    /// Lists the secrets added, removed and changed (content, owner or mode) between two snapshots, or a snapshot and a secrets directory (no passphrase needed)
+    Diff {
+        /// Path to the older side: a snapshot, an export container (its newest snapshot) or a secrets directory
+        #[clap(index = 1, value_name = "from")]
+        from: String,
+
+        /// Path to the newer side: a snapshot, an export container (its newest snapshot) or a secrets directory
+        #[clap(index = 2, value_name = "to")]
+        to: String,
+
+        /// Decrypt the secrets whose content changed and summarize how, without printing them
+        #[clap(long)]
+        content: bool,
+
+        /// Also print the changed lines of text secrets (prints secrets in plaintext)
+        #[clap(long, requires = "content")]
+        reveal: bool,
+    },
+
+    /// Removes the snapshots of a container that fall outside the retention rules, and the partial snapshots left by interrupted exports. The newest snapshot that passes verification and pinned snapshots are always kept
+    Prune {
+        /// Path to the export container
+        #[clap(index = 1, value_name = "container")]
+        container: String,
+
+        /// Keep the N newest snapshots
+        #[clap(long, value_name = "N")]
+        keep_last: Option<usize>,
+
+        /// Keep the newest snapshot of each of the D most recent days holding snapshots
+        #[clap(long, value_name = "D")]
+        keep_daily: Option<usize>,
+
+        /// Keep the newest snapshot of each of the W most recent weeks holding snapshots
+        #[clap(long, value_name = "W")]
+        keep_weekly: Option<usize>,
+
+        /// Keep the newest snapshot of each of the M most recent months holding snapshots
+        #[clap(long, value_name = "M")]
+        keep_monthly: Option<usize>,
+
+        /// Only remove snapshots older than this age (e.g. 90d, 12w, 6m, 1y). Alone, removes every snapshot older than it
+        #[clap(long, value_name = "age")]
+        older_than: Option<String>,
+
+        /// List what would be kept and removed, without removing anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Protects a snapshot from prune
+    Pin {
+        /// Path to the export container
+        #[clap(index = 1, value_name = "container")]
+        container: String,
+
+        /// Snapshot to protect (its name, or its path inside the container)
+        #[clap(index = 2, value_name = "snapshot")]
+        snapshot: String,
+    },
+
+    /// Lets prune remove a snapshot protected by pin again
+    Unpin {
+        /// Path to the export container
+        #[clap(index = 1, value_name = "container")]
+        container: String,
+
+        /// Snapshot to unprotect (its name, or its path inside the container)
+        #[clap(index = 2, value_name = "snapshot")]
+        snapshot: String,
+    },
+
+    /// Bootstraps a manifest (and the checksum sidecars) from an existing secrets directory
+    Init {
+        /// Path to the secrets directory to adopt
+        #[clap(index = 1, value_name = "secrets-dir")]
+        secrets_dir: String,
+
+        /// Only propose the files usually worth backing up for these kinds of directories, with their usual modes
+        #[clap(long, value_name = "preset", value_delimiter = ',')]
+        preset: Vec<Preset>,
+
+        /// Write the proposed manifest without asking for confirmation
+        #[clap(long)]
+        yes: bool,
+    },
+
+    /// Inspects and edits the manifest of a secrets directory, preserving its comments
+    Manifest {
+        #[clap(subcommand)]
+        command: ManifestCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ManifestCommand {
+    /// Adds secrets to the manifest, generating their checksum sidecars
+    Add {
+        /// Path to the secrets directory holding the manifest
+        #[clap(index = 1, value_name = "secrets-dir")]
+        secrets_dir: String,
+
+        /// Secrets to add (paths relative to the secrets directory)
+        #[clap(index = 2, value_name = "path", num_args = 0..)]
+        paths: Vec<String>,
+
+        /// Owner to annotate the new entries with (user, user:group, or :group)
+        #[clap(long)]
+        owner: Option<String>,
+
+        /// Mode to annotate the new entries with (3-4 octal digits, e.g. 0600)
+        #[clap(long)]
+        mode: Option<String>,
+
+        /// Kind to annotate the new entries with (e.g. ssh-private-key), validated at export and import
+        #[clap(long)]
+        kind: Option<String>,
+
+        /// Interactively offer to adopt every file that is not listed in the manifest
+        #[clap(long)]
+        unlisted: bool,
+    },
+
+    /// Removes secrets from the manifest (the files themselves are left untouched)
+    Remove {
+        /// Path to the secrets directory holding the manifest
+        #[clap(index = 1, value_name = "secrets-dir")]
+        secrets_dir: String,
+
+        /// Secrets to remove (paths relative to the secrets directory)
+        #[clap(index = 2, value_name = "path", num_args = 1..)]
+        paths: Vec<String>,
+    },
+
+    /// Changes the annotations of a secret already in the manifest
+    Set {
+        /// Path to the secrets directory holding the manifest
+        #[clap(index = 1, value_name = "secrets-dir")]
+        secrets_dir: String,
+
+        /// Secret to edit (path relative to the secrets directory)
+        #[clap(index = 2, value_name = "path")]
+        path: String,
+
+        /// New owner of the secret (user, user:group, or :group)
+        #[clap(long, conflicts_with = "no_owner")]
+        owner: Option<String>,
+
+        /// New mode of the secret (3-4 octal digits, e.g. 0600)
+        #[clap(long, conflicts_with = "no_mode")]
+        mode: Option<String>,
+
+        /// New kind of the secret (e.g. ssh-private-key)
+        #[clap(long, conflicts_with = "no_kind")]
+        kind: Option<String>,
+
+        /// Remove the owner annotation
+        #[clap(long)]
+        no_owner: bool,
+
+        /// Remove the mode annotation
+        #[clap(long)]
+        no_mode: bool,
+
+        /// Remove the kind annotation
+        #[clap(long)]
+        no_kind: bool,
+    },
+
+    /// Checks everything that would break or weaken an export or import (no passphrase needed)
+    #[clap(alias = "doctor")]
+    Lint {
+        /// Path to the secrets directory holding the manifest
+        #[clap(index = 1, value_name = "secrets-dir")]
+        secrets_dir: String,
+
+        /// Print the report as json
+        #[clap(long)]
+        json: bool,
+
+        /// Exit with an error on warnings too, not only on errors
+        #[clap(long)]
+        strict: bool,
+    },
+
+    /// Lists the secrets in the manifest
+    List {
+        /// Path to the secrets directory holding the manifest
+        #[clap(index = 1, value_name = "secrets-dir")]
+        secrets_dir: String,
+
+        /// Print the entries as json
+        #[clap(long)]
+        json: bool,
     },
 }
 
@@ -0,0 +1,199 @@
+use std::{
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+};
+
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use crate::hooks::Hook;
+
+// Host configuration, holding the commands secs-man may run. It is read from the host rather than
+// from the manifest of a snapshot, so that restoring a snapshot can't make secs-man run anything
+// that was not set up by the administrator of the host
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/secs-man/config";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read config at '{0}'\n{1}")]
+    Read(Utf8PathBuf, std::io::Error),
+
+    #[error(
+        "refusing to use config at '{0}', it is owned by uid {1} (only root or the user running secs-man are trusted)"
+    )]
+    UntrustedOwner(Utf8PathBuf, u32),
+
+    #[error("refusing to use config at '{0}', it is writable by group or others (mode {1:04o})")]
+    UntrustedMode(Utf8PathBuf, u32),
+
+    #[error(
+        "refusing to use config at '{0}', its directory '{1}' is owned by uid {2} (only root or the user running secs-man are trusted)"
+    )]
+    UntrustedDirOwner(Utf8PathBuf, Utf8PathBuf, u32),
+
+    #[error(
+        "refusing to use config at '{0}', its directory '{1}' is writable by group or others (mode {2:04o}), which lets them replace it"
+    )]
+    UntrustedDirMode(Utf8PathBuf, Utf8PathBuf, u32),
+
+    #[error("invalid config at '{0}', line {1}: {2}")]
+    Syntax(Utf8PathBuf, usize, String),
+}
+
+// Named shell commands, in the `[commands]` section as `name = command`, and the commands to run
+// at fixed points of export, import and verify-export, in the `[hooks]` section as `hook = command`
+#[derive(Default)]
+pub struct Config {
+    pub commands: Vec<(String, String)>,
+    pub hooks: Vec<(Hook, String)>,
+}
+impl Config {
+    pub fn command(&self, name: &str) -> Option<&str> {
+        self.commands
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, command)| command.as_str())
+    }
+
+    pub fn hook(&self, hook: Hook) -> Option<&str> {
+        self.hooks
+            .iter()
+            .find(|(h, _)| *h == hook)
+            .map(|(_, command)| command.as_str())
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+// The config and every directory above it must be owned by root or by the runner, and not be
+// writable by anyone else: whoever can write to one of the directories can swap the config for
+// their own
+fn check_trusted(path: &Utf8PathBuf) -> Result<(), ConfigError> {
+    let metadata = fs::metadata(path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+
+    let euid = unsafe { libc::geteuid() };
+    let trusted_owner = |metadata: &fs::Metadata| metadata.uid() == 0 || metadata.uid() == euid;
+    if !trusted_owner(&metadata) {
+        return Err(ConfigError::UntrustedOwner(path.clone(), metadata.uid()));
+    }
+    let mode = metadata.permissions().mode() & 0o7777;
+    if mode & 0o022 != 0 {
+        return Err(ConfigError::UntrustedMode(path.clone(), mode));
+    }
+
+    // through the directories the config actually is in, not the ones of a link to it
+    let canonical = path
+        .canonicalize_utf8()
+        .map_err(|e| ConfigError::Read(path.clone(), e))?;
+    for dir in canonical.ancestors().skip(1) {
+        let metadata = fs::metadata(dir).map_err(|e| ConfigError::Read(dir.to_path_buf(), e))?;
+        if !trusted_owner(&metadata) {
+            return Err(ConfigError::UntrustedDirOwner(
+                path.clone(),
+                dir.to_path_buf(),
+                metadata.uid(),
+            ));
+        }
+        // in a sticky directory (like /tmp) only the owner of a file can rename or delete it
+        let mode = metadata.permissions().mode() & 0o7777;
+        if mode & 0o022 != 0 && mode & 0o1000 == 0 {
+            return Err(ConfigError::UntrustedDirMode(
+                path.clone(),
+                dir.to_path_buf(),
+                mode,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn parse(path: &Utf8PathBuf, content: &str) -> Result<Config, ConfigError> {
+    let syntax = |line: usize, message: String| ConfigError::Syntax(path.clone(), line, message);
+
+    let mut config = Config::default();
+    let mut section: Option<&str> = None;
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            if !matches!(name, "commands" | "hooks") {
+                return Err(syntax(i + 1, format!("unknown section '[{name}]'")));
+            }
+            section = Some(name);
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(syntax(i + 1, "expected `name = value`".to_string()));
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if value.is_empty() {
+            return Err(syntax(i + 1, format!("'{key}' has an empty command")));
+        }
+
+        match section {
+            Some("commands") => {
+                if !is_valid_name(key) {
+                    return Err(syntax(
+                        i + 1,
+                        format!(
+                            "'{key}' is not a valid name (expected letters, digits, '-' and '_')"
+                        ),
+                    ));
+                }
+                if config.command(key).is_some() {
+                    return Err(syntax(i + 1, format!("command '{key}' is defined twice")));
+                }
+                config.commands.push((key.to_string(), value.to_string()));
+            }
+            Some(_) => {
+                let Some(hook) = Hook::from_name(key) else {
+                    let names: Vec<_> = Hook::ALL.iter().map(|h| h.as_str()).collect();
+                    return Err(syntax(
+                        i + 1,
+                        format!(
+                            "unknown hook '{key}' (expected one of {})",
+                            names.join(", ")
+                        ),
+                    ));
+                };
+                if config.hook(hook).is_some() {
+                    return Err(syntax(i + 1, format!("hook '{key}' is defined twice")));
+                }
+                config.hooks.push((hook, value.to_string()));
+            }
+            None => return Err(syntax(i + 1, format!("'{key}' is outside of any section"))),
+        }
+    }
+
+    Ok(config)
+}
+
+// Loads the config at the given path, or at the default path if none is given. A missing default
+// config is an empty one, while a config given explicitly has to exist
+pub fn load(path: Option<&str>) -> Result<Config, ConfigError> {
+    let path = match path {
+        Some(path) => Utf8PathBuf::from(path),
+        None => {
+            let path = Utf8PathBuf::from(DEFAULT_CONFIG_PATH);
+            if !path.exists() {
+                return Ok(Config::default());
+            }
+            path
+        }
+    };
+
+    check_trusted(&path)?;
+    let content = fs::read_to_string(&path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+
+    parse(&path, &content)
+}
@@ -0,0 +1,101 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Dependency-less conversion from "days since 1970" into (year, month, day), using
+// Hinnant's civil_from_days: https://howardhinnant.github.io/date_algorithms.html
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let y = yoe + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = if m <= 2 { y + 1 } else { y };
+
+    (y, m, d)
+}
+
+// Inverse of civil_from_days, from the same source
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+// Parses a `YYYY-MM-DD` date into seconds since the epoch (at midnight UTC)
+pub fn parse_date(value: &str) -> Option<i64> {
+    let mut fields = value.splitn(3, '-');
+    let year: i64 = fields.next().filter(|f| f.len() == 4)?.parse().ok()?;
+    let month: i64 = fields.next().filter(|f| f.len() == 2)?.parse().ok()?;
+    let day: i64 = fields.next().filter(|f| f.len() == 2)?.parse().ok()?;
+
+    let days = days_from_civil(year, month, day);
+    // reject dates such as 2027-02-31, which would silently roll over into the next month
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+
+    Some(days * 86400)
+}
+
+pub fn format_date(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+// Parses a span such as `90d`, `12w`, `6m` or `1y` into a number of days. Months and years are
+// approximated as 30 and 365 days respectively
+pub fn parse_days(value: &str) -> Option<i64> {
+    let (unit_start, _) = value.char_indices().last()?;
+    let (amount, unit) = value.split_at(unit_start);
+    if amount.is_empty() || !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: i64 = amount.parse().ok()?;
+
+    match unit {
+        "d" => Some(amount),
+        "w" => amount.checked_mul(7),
+        "m" => amount.checked_mul(30),
+        "y" => amount.checked_mul(365),
+        _ => None,
+    }
+}
+
+pub fn format_datetime(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time_of_day = seconds.rem_euclid(86400);
+    let hour = time_of_day / 3600;
+    let minute = (time_of_day % 3600) / 60;
+    let second = time_of_day % 60;
+
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z")
+}
+
+// Parses a `YYYY-MM-DDTHH:MM:SSZ` timestamp, as written by format_datetime
+pub fn parse_datetime(value: &str) -> Option<i64> {
+    let (date, time) = value.strip_suffix('Z')?.split_once('T')?;
+    let mut fields = time.splitn(3, ':');
+    let hour: i64 = fields.next().filter(|f| f.len() == 2)?.parse().ok()?;
+    let minute: i64 = fields.next().filter(|f| f.len() == 2)?.parse().ok()?;
+    let second: i64 = fields.next().filter(|f| f.len() == 2)?.parse().ok()?;
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    Some(parse_date(date)? + hour * 3600 + minute * 60 + second)
+}
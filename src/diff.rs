@@ -0,0 +1,477 @@
+use std::{
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+};
+
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use crate::checksum;
+use crate::crypto;
+use crate::kind::Kind;
+use crate::layout;
+use crate::manifest;
+use crate::sealed;
+use crate::snapshot;
+use crate::users;
+use crate::utf8path_ext::ExtraUtf8Path;
+
+// Beyond this many pairs of lines to compare, a text diff is too costly and only sizes are reported
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Error, Debug)]
+pub enum DiffError {
+    #[error("path '{0}' does not exist")]
+    MissingPath(Utf8PathBuf),
+
+    #[error("'{0}' is neither a secrets directory, a snapshot nor a container of snapshots")]
+    UnknownSource(Utf8PathBuf),
+
+    #[error("failed to find the newest snapshot in container '{0}'\n{1}")]
+    FindNewest(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to read the manifest of snapshot '{0}'\n{1}")]
+    ReadManifest(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to load manifest\n{0}")]
+    LoadManifest(manifest::ManifestError),
+
+    #[error(transparent)]
+    Unseal(sealed::SealedError),
+
+    #[error(
+        "snapshot '{0}' has a private layout, its secrets are only known with the passphrase (use --content)"
+    )]
+    PrivateLayout(Utf8PathBuf),
+
+    #[error(transparent)]
+    ReadIndex(layout::IndexError),
+
+    #[error("failed to read the user database\n{0}")]
+    LoadUsers(std::io::Error),
+
+    #[error("failed to read the checksum of '{0}'\n{1}")]
+    ReadChecksum(Utf8PathBuf, checksum::ChecksumError),
+
+    #[error("failed to read file at '{0}'\n{1}")]
+    Read(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to decrypt '{0}' (is the passphrase the one of both snapshots?)\n{1}")]
+    Decrypt(Utf8PathBuf, age::DecryptError),
+}
+impl DiffError {
+    fn read_checksum(path: &Utf8PathBuf) -> impl Fn(checksum::ChecksumError) -> Self {
+        |e| Self::ReadChecksum(path.clone(), e)
+    }
+
+    fn read(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::Read(path.clone(), e)
+    }
+
+    fn decrypt(path: &Utf8PathBuf) -> impl Fn(age::DecryptError) -> Self {
+        |e| Self::Decrypt(path.clone(), e)
+    }
+}
+
+// One side of a diff: an exported snapshot (directory or archive), or a live secrets directory
+enum Side {
+    Snapshot(Utf8PathBuf),
+    // a snapshot exported with --seal-checksums, whose digests are only known with the passphrase
+    Sealed(Utf8PathBuf, Option<sealed::Checksums>),
+    Live(Utf8PathBuf),
+}
+impl Side {
+    fn resolve(path: String, passphrase: Option<&str>) -> Result<Self, DiffError> {
+        let path = Utf8PathBuf::from(path);
+        if !path.exists() {
+            return Err(DiffError::MissingPath(path));
+        }
+
+        // both secrets directories and snapshots hold a manifest, but only snapshots hold
+        // checksums of their whole content
+        match snapshot::classify(&path) {
+            snapshot::SourceKind::Snapshot
+                if path.join("sha256sums.txt").exists() || snapshot::is_tar(&path) =>
+            {
+                Self::snapshot(path, passphrase)
+            }
+            snapshot::SourceKind::Snapshot => Ok(Side::Live(path)),
+            snapshot::SourceKind::Container => {
+                let newest = snapshot::newest(&path)
+                    .map_err(|e| DiffError::FindNewest(path.clone(), e))?
+                    .ok_or(DiffError::UnknownSource(path.clone()))?;
+                Self::snapshot(path.join(newest), passphrase)
+            }
+            snapshot::SourceKind::Neither => Err(DiffError::UnknownSource(path)),
+        }
+    }
+
+    fn snapshot(path: Utf8PathBuf, passphrase: Option<&str>) -> Result<Self, DiffError> {
+        if !sealed::is_sealed(&path) {
+            return Ok(Side::Snapshot(path));
+        }
+
+        let checksums = match passphrase {
+            Some(passphrase) => sealed::read(&path, passphrase).map_err(DiffError::Unseal)?,
+            None => None,
+        };
+        Ok(Side::Sealed(path, checksums))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Side::Snapshot(path) | Side::Sealed(path, _) => format!("snapshot '{path}'"),
+            Side::Live(path) => format!("secrets directory '{path}'"),
+        }
+    }
+
+    fn secrets(&self, passphrase: Option<&str>) -> Result<Vec<manifest::Secret>, DiffError> {
+        match self {
+            Side::Sealed(path, _) if layout::is_private(path) => {
+                let passphrase = passphrase.ok_or(DiffError::PrivateLayout(path.clone()))?;
+                let index = layout::read_index(path, passphrase).map_err(DiffError::ReadIndex)?;
+                manifest::parse(&path.join(layout::INDEX_FILENAME), &index)
+                    .map_err(DiffError::LoadManifest)
+            }
+            Side::Snapshot(path) | Side::Sealed(path, _) => {
+                let manifest_rel_path = Utf8PathBuf::from(manifest::MANIFEST_FILENAME);
+                let content = snapshot::read_file(path, &manifest_rel_path)
+                    .map_err(|e| DiffError::ReadManifest(path.clone(), e))?;
+                manifest::parse(
+                    &path.join(manifest::MANIFEST_FILENAME),
+                    &String::from_utf8_lossy(&content),
+                )
+                .map_err(DiffError::LoadManifest)
+            }
+            Side::Live(path) => manifest::load(path).map_err(DiffError::LoadManifest),
+        }
+    }
+
+    // What the secret holds, as far as it can be told without a passphrase
+    fn content(&self, secret: &manifest::Secret) -> Result<Content, DiffError> {
+        match (self, secret.kind == Some(Kind::Symlink)) {
+            // the target of a link of a private layout is encrypted, as it names another secret
+            (Side::Sealed(..), true) if secret.captured.stored.is_some() => Ok(Content::Unknown),
+            (Side::Snapshot(path) | Side::Sealed(path, _), true) => {
+                let link_rel_path = secret.path.add_extension("symlink");
+                let target = snapshot::read_file(path, &link_rel_path)
+                    .map_err(DiffError::read(&path.join(link_rel_path)))?;
+                Ok(Content::Link(String::from_utf8_lossy(&target).to_string()))
+            }
+            (Side::Snapshot(path), false) => {
+                let sha_rel_path = secret.path.add_extension("sha256");
+                let sha_path = path.join(&sha_rel_path);
+                let sha_content =
+                    snapshot::read_file(path, &sha_rel_path).map_err(DiffError::read(&sha_path))?;
+                checksum::parse_digest(&String::from_utf8_lossy(&sha_content), &sha_path)
+                    .map(Content::Digest)
+                    .map_err(DiffError::read_checksum(&sha_path))
+            }
+            (Side::Sealed(_, checksums), false) => Ok(checksums
+                .as_ref()
+                .and_then(|c| c.digest(&secret.path))
+                .map_or(Content::Unknown, |digest| {
+                    Content::Digest(digest.to_string())
+                })),
+            (Side::Live(path), true) => {
+                let link = path.join(&secret.path);
+                let target = link.read_link_utf8().map_err(DiffError::read(&link))?;
+                Ok(Content::Link(target.to_string()))
+            }
+            // the output of a command is only known once export runs it
+            (Side::Live(_), false) if secret.from_cmd.is_some() => Ok(Content::Unknown),
+            (Side::Live(path), false) => {
+                let sha_path = secret.sidecar(path);
+                checksum::read_digest(&sha_path)
+                    .map(Content::Digest)
+                    .map_err(DiffError::read_checksum(&sha_path))
+            }
+        }
+    }
+
+    // The owner and mode import would restore the secret with. Owners left to whoever runs the
+    // import are None, and so are the modes of symlinks
+    fn metadata(
+        &self,
+        secret: &manifest::Secret,
+        db: &users::Database,
+    ) -> Result<(Option<String>, Option<u32>), DiffError> {
+        let mut owner = secret.owner.as_ref().map(|o| o.as_str().to_string());
+        let mut mode = secret.mode;
+        match self {
+            Side::Snapshot(_) | Side::Sealed(..) => {
+                owner = owner.or(secret
+                    .captured
+                    .owner
+                    .as_ref()
+                    .map(|o| o.as_str().to_string()));
+                mode = mode.or(secret.captured.mode);
+            }
+            Side::Live(path)
+                if (secret.capture_owner || secret.capture_mode) && secret.from_cmd.is_none() =>
+            {
+                let file = secret.location(path);
+                let metadata = fs::metadata(&file).map_err(DiffError::read(&file))?;
+                if secret.capture_owner {
+                    owner = Some(db.owner_spec(metadata.uid(), metadata.gid()));
+                }
+                if secret.capture_mode {
+                    mode = Some(metadata.permissions().mode() & 0o7777);
+                }
+            }
+            Side::Live(_) => {}
+        }
+
+        if secret.kind == Some(Kind::Symlink) {
+            return Ok((owner, None));
+        }
+        Ok((owner, Some(mode.unwrap_or(0o600))))
+    }
+
+    // The plaintext of the secret, decrypted from a snapshot or read from the secrets directory
+    fn plaintext(&self, secret: &manifest::Secret, passphrase: &str) -> Result<Vec<u8>, DiffError> {
+        match self {
+            Side::Snapshot(path) | Side::Sealed(path, _) => {
+                let age_rel_path = secret.stored_path().add_extension("age");
+                let age_path = path.join(&age_rel_path);
+                let encrypted =
+                    snapshot::read_file(path, &age_rel_path).map_err(DiffError::read(&age_path))?;
+                crypto::decrypt(encrypted, passphrase).map_err(DiffError::decrypt(&age_path))
+            }
+            Side::Live(path) => {
+                let file = secret.location(path);
+                fs::read(&file).map_err(DiffError::read(&file))
+            }
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum Content {
+    Digest(String),
+    Link(String),
+    Unknown,
+}
+
+// A secret listed on both sides, with what differs between them
+struct Change {
+    path: Utf8PathBuf,
+    details: Vec<String>,
+    // whether the content itself differs, and so is worth decrypting
+    content: bool,
+}
+
+fn describe_owner(owner: &Option<String>) -> &str {
+    owner.as_deref().unwrap_or("(importing user)")
+}
+
+fn describe_mode(mode: Option<u32>) -> String {
+    mode.map_or("(none)".to_string(), |m| format!("{m:04o}"))
+}
+
+fn compare(
+    path: &Utf8PathBuf,
+    (old_content, new_content): (Content, Content),
+    (old_owner, new_owner): (Option<String>, Option<String>),
+    (old_mode, new_mode): (Option<u32>, Option<u32>),
+) -> Option<Change> {
+    let mut change = Change {
+        path: path.clone(),
+        details: Vec::new(),
+        content: false,
+    };
+
+    match (old_content, new_content) {
+        (Content::Link(old), Content::Link(new)) if old != new => {
+            change.details.push(format!("link {old} -> {new}"));
+        }
+        (Content::Digest(old), Content::Digest(new)) if old != new => {
+            change.details.push("content".to_string());
+            change.content = true;
+        }
+        (Content::Link(_), Content::Digest(_)) | (Content::Digest(_), Content::Link(_)) => {
+            change.details.push("kind".to_string());
+        }
+        _ => {}
+    }
+    if old_owner != new_owner {
+        change.details.push(format!(
+            "owner {} -> {}",
+            describe_owner(&old_owner),
+            describe_owner(&new_owner)
+        ));
+    }
+    if old_mode != new_mode {
+        change.details.push(format!(
+            "mode {} -> {}",
+            describe_mode(old_mode),
+            describe_mode(new_mode)
+        ));
+    }
+
+    (!change.details.is_empty()).then_some(change)
+}
+
+fn as_text(content: &[u8]) -> Option<&str> {
+    std::str::from_utf8(content)
+        .ok()
+        .filter(|text| !text.contains('\0'))
+}
+
+// The lines removed from and added to old to get new, through their longest common subsequence
+fn line_diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            (i, j) = (i + 1, j + 1);
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    lines
+}
+
+// Prints what changed in the content of a secret without printing the content itself, unless
+// `reveal` asks for the changed lines
+fn print_content_summary(old: &[u8], new: &[u8], reveal: bool) {
+    let sizes = format!("{} -> {} bytes", old.len(), new.len());
+
+    let (Some(old_text), Some(new_text)) = (as_text(old), as_text(new)) else {
+        let differing =
+            old.iter().zip(new).filter(|(a, b)| a != b).count() + old.len().abs_diff(new.len());
+        println!("    {sizes}, {differing} bytes differ");
+        return;
+    };
+
+    let old_lines: Vec<_> = old_text.lines().collect();
+    let new_lines: Vec<_> = new_text.lines().collect();
+    if old_lines.len() * new_lines.len() > MAX_DIFF_CELLS {
+        println!("    {sizes}, too many lines to compare");
+        return;
+    }
+
+    let lines = line_diff(&old_lines, &new_lines);
+    let removed = lines.iter().filter(|(sign, _)| *sign == '-').count();
+    let added = lines.len() - removed;
+    if lines.is_empty() {
+        // only the line endings differ
+        println!("    {sizes}, same lines");
+        return;
+    }
+    println!("    {sizes}, {removed} lines removed, {added} lines added");
+    if reveal {
+        for (sign, line) in lines {
+            println!("    {sign} {line}");
+        }
+    }
+}
+
+// Compares two snapshots, or a snapshot and a secrets directory, reporting the secrets added,
+// removed and changed (content, owner or mode) from the first to the second. The checksums are
+// enough to tell which contents changed, the passphrase is only needed to tell how
+pub fn diff(
+    old: String,
+    new: String,
+    passphrase: Option<String>,
+    reveal: bool,
+) -> Result<(), DiffError> {
+    let old = Side::resolve(old, passphrase.as_deref())?;
+    let new = Side::resolve(new, passphrase.as_deref())?;
+    let db = users::Database::load().map_err(DiffError::LoadUsers)?;
+
+    println!("Comparing {} with {}:", old.describe(), new.describe());
+    println!();
+
+    for side in [&old, &new] {
+        if let Side::Sealed(path, None) = side {
+            println!(
+                "Note: the checksums of snapshot '{path}' are sealed, contents are only compared with --content"
+            );
+            println!();
+        }
+    }
+
+    let old_secrets = old.secrets(passphrase.as_deref())?;
+    let new_secrets = new.secrets(passphrase.as_deref())?;
+
+    let mut added: Vec<_> = new_secrets
+        .iter()
+        .filter(|s| !old_secrets.iter().any(|o| o.path == s.path))
+        .map(|s| s.path.clone())
+        .collect();
+    let mut removed: Vec<_> = old_secrets
+        .iter()
+        .filter(|s| !new_secrets.iter().any(|n| n.path == s.path))
+        .map(|s| s.path.clone())
+        .collect();
+    added.sort();
+    removed.sort();
+
+    let mut changes = Vec::new();
+    for new_secret in &new_secrets {
+        let Some(old_secret) = old_secrets.iter().find(|s| s.path == new_secret.path) else {
+            continue;
+        };
+        let (old_owner, old_mode) = old.metadata(old_secret, &db)?;
+        let (new_owner, new_mode) = new.metadata(new_secret, &db)?;
+        let change = compare(
+            &new_secret.path,
+            (old.content(old_secret)?, new.content(new_secret)?),
+            (old_owner, new_owner),
+            (old_mode, new_mode),
+        );
+        if let Some(change) = change {
+            changes.push((change, old_secret, new_secret));
+        }
+    }
+    changes.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+
+    if added.is_empty() && removed.is_empty() && changes.is_empty() {
+        println!("No differences.");
+        return Ok(());
+    }
+
+    for path in &added {
+        println!("added    {path}");
+    }
+    for path in &removed {
+        println!("removed  {path}");
+    }
+    for (change, old_secret, new_secret) in &changes {
+        println!("changed  {}  ({})", change.path, change.details.join(", "));
+        if let Some(passphrase) = &passphrase
+            && change.content
+        {
+            let old_content = old.plaintext(old_secret, passphrase)?;
+            let new_content = new.plaintext(new_secret, passphrase)?;
+            print_content_summary(&old_content, &new_content, reveal);
+        }
+    }
+    println!();
+
+    println!(
+        "{} added, {} removed, {} changed.",
+        added.len(),
+        removed.len(),
+        changes.len()
+    );
+
+    Ok(())
+}
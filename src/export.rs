@@ -1,25 +1,162 @@
-use std::{fs, io::Write};
+use std::{
+    cell::{Cell, OnceCell},
+    fs,
+    io::Write,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+};
 
 use camino::Utf8PathBuf;
 use thiserror::Error;
 
+use crate::cert;
 use crate::checksum;
+use crate::config;
 use crate::crypto;
+use crate::date;
+use crate::hooks;
+use crate::info;
+use crate::kind;
+use crate::layout;
 use crate::manifest;
+use crate::manifest_edit;
+use crate::objects;
+use crate::plan::{Action, Plan};
+use crate::recovery;
+use crate::sealed;
 use crate::snapshot;
+use crate::users;
 use crate::utf8path_ext::ExtraUtf8Path;
+use crate::xattr;
+
+pub struct ExportOptions {
+    // export secrets even if their content does not match their kind
+    pub skip_validation: bool,
+    // record the owner, mode and mtime of every secret, as if they were all `owner=auto mode=auto`
+    pub capture_metadata: bool,
+    // record the `user.*` extended attributes and the POSIX ACL of every secret
+    pub capture_xattrs: bool,
+    // host config holding the from-cmd= commands and the hooks, instead of the default one
+    pub config: Option<String>,
+    // do not run hooks
+    pub no_hooks: bool,
+    // turn the container into a deduplicated one, if it is not already
+    pub dedup: bool,
+    pub format: snapshot::Format,
+    // encrypt the plaintext digests of the secrets rather than writing them next to each secret
+    pub seal_checksums: bool,
+    // store the secrets under random names, and the manifest as an encrypted index (implies
+    // seal_checksums)
+    pub private_layout: bool,
+    // recorded in snapshot.json, to tell the snapshot apart from the others of the container
+    pub label: Option<String>,
+    pub note: Option<String>,
+    // keep the snapshot in the containers it was written to when others fail, rather than removing
+    // it from all of them
+    pub best_effort: bool,
+    // list what the export would do, without writing anything nor running any command
+    pub dry_run: bool,
+}
+
+// State of an export into a deduplicated container
+struct Dedup {
+    container: Utf8PathBuf,
+    // newest snapshot before this one, whose encrypted files are reused for the unchanged secrets
+    previous: Option<Utf8PathBuf>,
+    reused: Cell<usize>,
+    // read from the previous snapshot once, on the first secret that needs them: its sha256sums.txt,
+    // its sealed checksums (if it was sealed and they decrypt), and whether it was encrypted with
+    // the current passphrase
+    previous_sums: OnceCell<Option<String>>,
+    previous_sealed: OnceCell<Option<sealed::Checksums>>,
+    same_passphrase: OnceCell<bool>,
+}
+impl Dedup {
+    // Reads the container of a snapshot being written, if it is deduplicated
+    fn of(snapshot: &Utf8PathBuf) -> std::io::Result<Option<Self>> {
+        let container = snapshot
+            .parent()
+            .expect("snapshots are inside a container")
+            .to_path_buf();
+        if !objects::is_enabled(&container) {
+            return Ok(None);
+        }
+
+        let previous = snapshot::newest(&container)?.map(|name| container.join(name));
+        Ok(Some(Self {
+            container,
+            previous,
+            reused: Cell::new(0),
+            previous_sums: OnceCell::new(),
+            previous_sealed: OnceCell::new(),
+            same_passphrase: OnceCell::new(),
+        }))
+    }
+
+    // The encrypted file of the previous snapshot, if the secret is unchanged since then, as told
+    // by the plaintext digest recorded in its sidecar (or in its sealed checksums), and the file is
+    // still the one its sha256sums.txt lists. Only the first file reused is decrypted, to tell
+    // whether the passphrase changed since: the files of a snapshot all share the same one
+    fn reusable(
+        &self,
+        file_rel_path: &Utf8PathBuf,
+        file_content: &[u8],
+        passphrase: &str,
+    ) -> Option<Vec<u8>> {
+        let previous = self.previous.as_ref()?;
+
+        let previous_sha = previous.join(file_rel_path).add_extension("sha256");
+        let previous_digest = match previous_sha.exists() {
+            true => checksum::read_digest(&previous_sha).ok()?,
+            false => self
+                .previous_sealed
+                .get_or_init(|| sealed::read(previous, passphrase).ok().flatten())
+                .as_ref()?
+                .digest(file_rel_path)?
+                .to_string(),
+        };
+        if previous_digest != sha256::digest(file_content) {
+            return None;
+        }
+
+        let age_rel_path = file_rel_path.add_extension("age");
+        let encrypted_content = fs::read(previous.join(&age_rel_path)).ok()?;
+        let listed = format!("{}  {age_rel_path}", sha256::digest(&encrypted_content));
+        let sums = self
+            .previous_sums
+            .get_or_init(|| fs::read_to_string(previous.join("sha256sums.txt")).ok());
+        if !sums.as_ref()?.lines().any(|line| line == listed) {
+            return None;
+        }
+
+        let same_passphrase = *self.same_passphrase.get_or_init(|| {
+            crypto::decrypt(&encrypted_content, passphrase).is_ok_and(|c| c == file_content)
+        });
+        same_passphrase.then_some(encrypted_content)
+    }
+}
 
 #[derive(Error, Debug)]
 pub enum ExportFileError {
     #[error("failed to verify integrity of source file at '{0}'\n{1}")]
     VerifySource(Utf8PathBuf, checksum::ChecksumError),
 
+    #[error("at= location '{0}' is not a copy of the first one, which is the one exported\n{1}")]
+    DivergedLocation(Utf8PathBuf, checksum::ChecksumError),
+
     #[error("failed to generate missing checksum of source file at '{0}'\n{1}")]
     GenerateMissingChecksum(Utf8PathBuf, checksum::ChecksumError),
 
+    #[error("failed to create the directory of sidecar '{0}' in the secrets directory\n{1}")]
+    CreateSourceParent(Utf8PathBuf, std::io::Error),
+
     #[error("failed to read file at '{0}'\n{1}")]
     Read(Utf8PathBuf, std::io::Error),
 
+    #[error(
+        "content of source file at '{0}' does not match its kind (use --skip-validation to export it anyway)\n{1}"
+    )]
+    Validation(Utf8PathBuf, kind::ValidationError),
+
     #[error("failed to encrypt contents of source file at '{0}'\n{1}")]
     Encryption(Utf8PathBuf, age::EncryptError),
 
@@ -42,11 +179,32 @@ pub enum ExportFileError {
 
     #[error("failed to append checksum of exported file ('{0}') to export's sha256sums.txt\n{1}")]
     AppendChecksum(Utf8PathBuf, checksum::ChecksumError),
+
+    #[error("failed to capture metadata of source file at '{0}'\n{1}")]
+    CaptureMetadata(Utf8PathBuf, std::io::Error),
+
+    #[error("source '{0}' is listed as kind=symlink but is not a symlink")]
+    NotASymlink(Utf8PathBuf),
+
+    #[error("failed to read target of symlink '{0}'\n{1}")]
+    ReadLink(Utf8PathBuf, std::io::Error),
+
+    #[error("refusing to export symlink '{0}'\n{1}")]
+    LinkTarget(Utf8PathBuf, manifest::InvalidLinkTarget),
+
+    #[error("failed to read the secret from its command\n{0}")]
+    RunCommand(hooks::HookError),
+
+    #[error("failed to store the encrypted content of '{0}' in the shared objects\n{1}")]
+    StoreObject(Utf8PathBuf, std::io::Error),
 }
 impl ExportFileError {
     fn verify_source(source: &Utf8PathBuf) -> impl Fn(checksum::ChecksumError) -> Self {
         |e| Self::VerifySource(source.clone(), e)
     }
+    fn diverged_location(location: &Utf8PathBuf) -> impl Fn(checksum::ChecksumError) -> Self {
+        |e| Self::DivergedLocation(location.clone(), e)
+    }
     fn generate_missing_checksum(source: &Utf8PathBuf) -> impl Fn(checksum::ChecksumError) -> Self {
         |e| Self::GenerateMissingChecksum(source.clone(), e)
     }
@@ -54,10 +212,18 @@ impl ExportFileError {
         |e| Self::Read(source.clone(), e)
     }
 
+    fn validation(source: &Utf8PathBuf) -> impl Fn(kind::ValidationError) -> Self {
+        |e| Self::Validation(source.clone(), e)
+    }
+
     fn encryption(source: &Utf8PathBuf) -> impl Fn(age::EncryptError) -> Self {
         |e| Self::Encryption(source.clone(), e)
     }
 
+    fn create_source_parent(sidecar: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::CreateSourceParent(sidecar.clone(), e)
+    }
+
     fn write_to_target(target: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
         |e| Self::WriteToTarget(target.clone(), e)
     }
@@ -73,32 +239,101 @@ impl ExportFileError {
     fn append_checksum(target: &Utf8PathBuf) -> impl Fn(checksum::ChecksumError) -> Self {
         |e| Self::AppendChecksum(target.clone(), e)
     }
+
+    fn read_link(source: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::ReadLink(source.clone(), e)
+    }
+
+    fn capture_metadata(source: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::CaptureMetadata(source.clone(), e)
+    }
+
+    fn link_target(source: &Utf8PathBuf) -> impl Fn(manifest::InvalidLinkTarget) -> Self {
+        |e| Self::LinkTarget(source.clone(), e)
+    }
+
+    fn store_object(source: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::StoreObject(source.clone(), e)
+    }
 }
 
 fn export_file(
-    file_rel_path: &Utf8PathBuf,
+    secret: &manifest::Secret,
     source: &Utf8PathBuf,
     target: &Utf8PathBuf,
     passphrase: &str,
-) -> Result<(), ExportFileError> {
-    let file_source = source.join(file_rel_path);
-    let file_target = target.join(file_rel_path).add_extension("age");
-    let file_target_rel_path = file_rel_path.add_extension("age");
-
-    let sha_source = source.join(file_rel_path).add_extension("sha256");
-    let sha_target = target.join(file_rel_path).add_extension("sha256");
-    let sha_target_rel_path = file_rel_path.add_extension("sha256");
+    options: &ExportOptions,
+    dedup: Option<&Dedup>,
+) -> Result<Vec<u8>, ExportFileError> {
+    let file_source = secret.location(source);
+    let sha_source = secret.sidecar(source);
 
     if !sha_source.exists() {
-        checksum::generate_file_checksum(&file_source)
+        // a secret living at an at= location may have nothing else in the secrets directory yet
+        if let Some(parent) = sha_source.parent() {
+            fs::create_dir_all(parent)
+                .map_err(ExportFileError::create_source_parent(&sha_source))?;
+        }
+        checksum::generate_checksum_at(&file_source, &sha_source)
             .map_err(ExportFileError::generate_missing_checksum(&file_source))?;
     }
-    checksum::verify_file_checksum(&file_source)
+    checksum::verify_checksum_at(&file_source, &sha_source)
         .map_err(ExportFileError::verify_source(&file_source))?;
+    // only the first location is read, the others must hold the same content
+    for location in secret.at.iter().skip(1) {
+        checksum::verify_checksum_at(location, &sha_source)
+            .map_err(ExportFileError::diverged_location(location))?;
+    }
 
     let file_content = fs::read(&file_source).map_err(ExportFileError::read(&file_source))?;
-    let encrypted_content = crypto::encrypt(&file_content, passphrase)
-        .map_err(ExportFileError::encryption(&file_source))?;
+    if let Some(kind) = secret.kind
+        && !options.skip_validation
+    {
+        kind::validate(kind, &file_content).map_err(ExportFileError::validation(&file_source))?;
+    }
+
+    let sha_content = match options.seal_checksums {
+        true => None,
+        false => Some(fs::read(&sha_source).map_err(ExportFileError::read(&sha_source))?),
+    };
+    write_encrypted(
+        &secret.stored_path(),
+        target,
+        &file_content,
+        sha_content.as_deref(),
+        passphrase,
+        &file_source,
+        dedup,
+    )?;
+
+    Ok(file_content)
+}
+
+// Writes the encrypted content of a secret and its sidecar (unless the snapshot is sealed) to the
+// snapshot, checking that the encrypted file decrypts back to the content. source only names the
+// secret in errors. In a
+// deduplicated container, the encrypted file is an object linked into the snapshot, and the one of
+// the previous snapshot is reused when the secret did not change
+fn write_encrypted(
+    file_rel_path: &Utf8PathBuf,
+    target: &Utf8PathBuf,
+    file_content: &[u8],
+    sha_content: Option<&[u8]>,
+    passphrase: &str,
+    source: &Utf8PathBuf,
+    dedup: Option<&Dedup>,
+) -> Result<(), ExportFileError> {
+    let file_target = target.join(file_rel_path).add_extension("age");
+    let file_target_rel_path = file_rel_path.add_extension("age");
+    let sha_target = target.join(file_rel_path).add_extension("sha256");
+    let sha_target_rel_path = file_rel_path.add_extension("sha256");
+
+    let reused = dedup.and_then(|d| d.reusable(file_rel_path, file_content, passphrase));
+    let encrypted_content = match &reused {
+        Some(encrypted_content) => encrypted_content.clone(),
+        None => crypto::encrypt(file_content, passphrase)
+            .map_err(ExportFileError::encryption(source))?,
+    };
 
     if let Some(parent) = file_target.parent() {
         let parent = parent.to_path_buf();
@@ -106,29 +341,196 @@ fn export_file(
             fs::create_dir_all(&parent).map_err(ExportFileError::create_target_parent(&parent))?;
         }
     }
-    fs::write(&file_target, encrypted_content)
-        .map_err(ExportFileError::write_to_target(&file_target))?;
+    match dedup {
+        Some(dedup) => {
+            let object = objects::store(&dedup.container, &encrypted_content)
+                .map_err(ExportFileError::store_object(source))?;
+            objects::link(&object, &file_target)
+                .map_err(ExportFileError::write_to_target(&file_target))?;
+        }
+        None => fs::write(&file_target, &encrypted_content)
+            .map_err(ExportFileError::write_to_target(&file_target))?,
+    }
 
-    let encrypted_content =
+    let written_content =
         fs::read(&file_target).map_err(ExportFileError::read_target(&file_target))?;
-    let decrypted_content =
-        crypto::decrypt(encrypted_content, passphrase).map_err(ExportFileError::DecryptEndpoint)?;
-
-    if decrypted_content != file_content {
-        return Err(ExportFileError::VerifyExport);
+    // a reused file was already checked against its digests, it only has to be the one that got
+    // written
+    match reused {
+        Some(_) if written_content == encrypted_content => {
+            let dedup = dedup.expect("only deduplicated exports reuse files");
+            dedup.reused.set(dedup.reused.get() + 1);
+        }
+        Some(_) => return Err(ExportFileError::VerifyExport),
+        None => {
+            let decrypted_content = crypto::decrypt(written_content, passphrase)
+                .map_err(ExportFileError::DecryptEndpoint)?;
+            if decrypted_content != file_content {
+                return Err(ExportFileError::VerifyExport);
+            }
+        }
     }
 
-    let sha_content = fs::read(&sha_source).map_err(ExportFileError::read(&sha_source))?;
-    fs::write(&sha_target, sha_content).map_err(ExportFileError::write_to_target(&sha_target))?;
-
     checksum::append_checksum(target, &file_target_rel_path)
         .map_err(ExportFileError::append_checksum(&file_target))?;
-    checksum::append_checksum(target, &sha_target_rel_path)
-        .map_err(ExportFileError::append_checksum(&sha_target))?;
+    if let Some(sha_content) = sha_content {
+        fs::write(&sha_target, sha_content)
+            .map_err(ExportFileError::write_to_target(&sha_target))?;
+        checksum::append_checksum(target, &sha_target_rel_path)
+            .map_err(ExportFileError::append_checksum(&sha_target))?;
+    }
 
     Ok(())
 }
 
+// Secrets read from a command never touch the secrets directory: the output of the command is
+// hashed and encrypted straight into the snapshot, sidecar included
+fn export_command(
+    secret: &manifest::Secret,
+    source: &Utf8PathBuf,
+    target: &Utf8PathBuf,
+    passphrase: &str,
+    options: &ExportOptions,
+    config: &config::Config,
+    dedup: Option<&Dedup>,
+) -> Result<Vec<u8>, ExportFileError> {
+    let file_rel_path = &secret.path;
+    let name = secret
+        .from_cmd
+        .as_deref()
+        .expect("only called on from-cmd secrets");
+    let command = config
+        .command(name)
+        .expect("commands are checked before export");
+
+    let env = [
+        ("SECS_MAN_SECRETS_DIR", source.to_string()),
+        ("SECS_MAN_SECRET", file_rel_path.to_string()),
+    ];
+    let file_content = hooks::capture(name, command, &env).map_err(ExportFileError::RunCommand)?;
+    if let Some(kind) = secret.kind
+        && !options.skip_validation
+    {
+        kind::validate(kind, &file_content).map_err(ExportFileError::validation(file_rel_path))?;
+    }
+
+    let sha_content = format!(
+        "{}  {}\n",
+        sha256::digest(&file_content),
+        file_rel_path
+            .file_name()
+            .expect("secret paths have a file name")
+    );
+    write_encrypted(
+        &secret.stored_path(),
+        target,
+        &file_content,
+        (!options.seal_checksums).then_some(sha_content.as_bytes()),
+        passphrase,
+        file_rel_path,
+        dedup,
+    )?;
+
+    Ok(file_content)
+}
+
+// Reads the metadata of a secret that has to be recorded in the snapshot: its owner for
+// `owner=auto`, its mode for `mode=auto`, both (and the mtime) with --capture-metadata, and its
+// extended attributes with --capture-xattrs
+fn capture(
+    secret: &manifest::Secret,
+    source: &Utf8PathBuf,
+    options: &ExportOptions,
+    db: &users::Database,
+) -> Result<Option<manifest::Captured>, ExportFileError> {
+    let capture_owner = secret.capture_owner || options.capture_metadata;
+    let capture_mode = secret.capture_mode || options.capture_metadata;
+    // secrets read from a command have no file to take metadata from
+    if (!capture_owner && !capture_mode && !options.capture_xattrs) || secret.from_cmd.is_some() {
+        return Ok(None);
+    }
+
+    let file_source = secret.location(source);
+    let metadata =
+        fs::metadata(&file_source).map_err(ExportFileError::capture_metadata(&file_source))?;
+
+    let mut captured = manifest::Captured::default();
+    if capture_owner {
+        let (uid, gid) = (metadata.uid(), metadata.gid());
+        let owner = db.owner_spec(uid, gid);
+        captured.owner = Some(
+            owner
+                .parse()
+                .expect("owner_spec falls back to ids for the names a spec can't hold"),
+        );
+        captured.ids = Some((uid, gid));
+    }
+    if capture_mode {
+        captured.mode = Some(metadata.permissions().mode() & 0o7777);
+    }
+    if capture_owner || capture_mode {
+        captured.mtime = Some(metadata.mtime());
+    }
+    if options.capture_xattrs {
+        captured.xattrs = xattr::read_all(&file_source)
+            .map_err(ExportFileError::capture_metadata(&file_source))?;
+    }
+
+    Ok(Some(captured))
+}
+
+// Symlinks are exported as a plaintext `<path>.symlink` holding their target (which is no secret)
+// rather than as a copy of the file they point to, so that import recreates the link. With a
+// private layout, the target would tell the name of another secret, so it is encrypted like a
+// secret of its own. Returns the target, for the recovery kit
+fn export_symlink(
+    secret: &manifest::Secret,
+    source: &Utf8PathBuf,
+    target: &Utf8PathBuf,
+    passphrase: &str,
+) -> Result<Utf8PathBuf, ExportFileError> {
+    let file_rel_path = &secret.path;
+    let file_source = source.join(file_rel_path);
+    let link_target = target.join(file_rel_path).add_extension("symlink");
+    let link_target_rel_path = file_rel_path.add_extension("symlink");
+
+    if !file_source.is_symlink() {
+        return Err(ExportFileError::NotASymlink(file_source));
+    }
+    let destination = file_source
+        .read_link_utf8()
+        .map_err(ExportFileError::read_link(&file_source))?;
+    manifest::check_resolved_link_target(source, file_rel_path, &destination)
+        .map_err(ExportFileError::link_target(&file_source))?;
+
+    if secret.captured.stored.is_some() {
+        write_encrypted(
+            &secret.stored_path(),
+            target,
+            destination.as_str().as_bytes(),
+            None,
+            passphrase,
+            &file_source,
+            None,
+        )?;
+        return Ok(destination);
+    }
+
+    if let Some(parent) = link_target.parent() {
+        let parent = parent.to_path_buf();
+        if !parent.exists() {
+            fs::create_dir_all(&parent).map_err(ExportFileError::create_target_parent(&parent))?;
+        }
+    }
+    fs::write(&link_target, destination.as_str())
+        .map_err(ExportFileError::write_to_target(&link_target))?;
+
+    checksum::append_checksum(target, &link_target_rel_path)
+        .map_err(ExportFileError::append_checksum(&link_target))?;
+
+    Ok(destination)
+}
+
 #[derive(Error, Debug)]
 pub enum ExportAdditionalError {
     #[error("failed to obtain executable path\n{0}")]
@@ -143,12 +545,21 @@ pub enum ExportAdditionalError {
     #[error("failed to copy executable to export\n{0}")]
     CopyExe(std::io::Error),
 
-    #[error("failed to read manifest to copy it to export\n{0}")]
-    ReadManifest(std::io::Error),
-
     #[error("failed to write manifest to export\n{0}")]
     WriteManifest(std::io::Error),
 
+    #[error("failed to record the captured metadata in the exported manifest\n{0}")]
+    RecordCaptured(manifest_edit::ManifestEditError),
+
+    #[error("failed to encrypt the index of the snapshot\n{0}")]
+    EncryptIndex(age::EncryptError),
+
+    #[error("failed to decrypt the index of the snapshot to verify correct decryption\n{0}")]
+    DecryptIndex(age::DecryptError),
+
+    #[error("failed to verify the index of the snapshot, it does not decrypt back to the manifest")]
+    VerifyIndex,
+
     #[error("failed to generate checksum for exported file '{0}'\n{1}")]
     GenerateChecksum(Utf8PathBuf, checksum::ChecksumError),
 }
@@ -157,10 +568,15 @@ impl ExportAdditionalError {
         |e| Self::GenerateChecksum(file.clone(), e)
     }
 }
+// Exports the executable and the manifest, as a plaintext manifest or, given the passphrase of a
+// private layout, as its encrypted index. Returns the content of the manifest
 fn export_additional(
     source: &Utf8PathBuf,
     target: &Utf8PathBuf,
-) -> Result<(), ExportAdditionalError> {
+    captured: &[(Utf8PathBuf, manifest::Captured)],
+    dedup: Option<&Dedup>,
+    index_passphrase: Option<&str>,
+) -> Result<String, ExportAdditionalError> {
     println!("Exporting additional files... ");
 
     print!("exporting executable... ");
@@ -180,22 +596,47 @@ fn export_additional(
     let exe_name = Utf8PathBuf::from(exe_name);
 
     let exe_target = target.join(&exe_name);
-    fs::copy(&exe_path, &exe_target)
-        .map_err(ExportAdditionalError::CopyExe)
-        .inspect_err(|_| println!("error"))?;
+    // the executable rarely changes between exports, and is by far the largest file of a snapshot
+    match dedup {
+        Some(dedup) => fs::read(&exe_path)
+            .and_then(|content| objects::store(&dedup.container, &content))
+            .and_then(|object| objects::link(&object, &exe_target)),
+        None => fs::copy(&exe_path, &exe_target).map(|_| ()),
+    }
+    .map_err(ExportAdditionalError::CopyExe)
+    .inspect_err(|_| println!("error"))?;
     checksum::append_checksum(target, &exe_name)
         .map_err(ExportAdditionalError::generate_checksum(&exe_target))?;
     println!("ok");
 
     print!("exporting manifest... ");
     std::io::stdout().flush().unwrap();
-    let manifest_name = Utf8PathBuf::from(manifest::MANIFEST_FILENAME);
-    let manifest_source = source.join(&manifest_name);
-    let manifest_target = target.join(&manifest_name);
-    let manifest_content = fs::read(&manifest_source)
-        .map_err(ExportAdditionalError::ReadManifest)
+    // always rendered, so that the metadata captured by a previous export doesn't linger in a
+    // manifest restored from a snapshot
+    let manifest_content = manifest_edit::with_captured(source, captured)
+        .map_err(ExportAdditionalError::RecordCaptured)
         .inspect_err(|_| println!("error"))?;
-    fs::write(&manifest_target, manifest_content)
+    let (manifest_name, exported_content) = match index_passphrase {
+        Some(passphrase) => {
+            let encrypted = crypto::encrypt(&manifest_content, passphrase)
+                .map_err(ExportAdditionalError::EncryptIndex)
+                .inspect_err(|_| println!("error"))?;
+            let decrypted = crypto::decrypt(&encrypted, passphrase)
+                .map_err(ExportAdditionalError::DecryptIndex)
+                .inspect_err(|_| println!("error"))?;
+            if decrypted != manifest_content.as_bytes() {
+                println!("error");
+                return Err(ExportAdditionalError::VerifyIndex);
+            }
+            (Utf8PathBuf::from(layout::INDEX_FILENAME), encrypted)
+        }
+        None => (
+            Utf8PathBuf::from(manifest::MANIFEST_FILENAME),
+            manifest_content.clone().into_bytes(),
+        ),
+    };
+    let manifest_target = target.join(&manifest_name);
+    fs::write(&manifest_target, exported_content)
         .map_err(ExportAdditionalError::WriteManifest)
         .inspect_err(|_| println!("error"))?;
     checksum::append_checksum(target, &manifest_name)
@@ -204,7 +645,7 @@ fn export_additional(
 
     println!();
 
-    Ok(())
+    Ok(manifest_content)
 }
 
 #[derive(Error, Debug)]
@@ -218,13 +659,48 @@ pub enum ExportError {
     MissingTargetPath(Utf8PathBuf),
     #[error("target path '{0}' is not a directory")]
     TargetNotDir(Utf8PathBuf),
+    #[error("failed to resolve target path '{0}'\n{1}")]
+    ResolveTarget(Utf8PathBuf, std::io::Error),
+    #[error("target paths '{0}' and '{1}' are the same container")]
+    DuplicateTarget(Utf8PathBuf, Utf8PathBuf),
 
     #[error("failed to load manifest\n{0}")]
     LoadManifest(manifest::ManifestError),
 
+    #[error("failed to load config\n{0}")]
+    LoadConfig(config::ConfigError),
+
+    #[error(
+        "secret '{0}' is read from command '{1}', which is not defined in the [commands] of the config"
+    )]
+    UnknownCommand(Utf8PathBuf, String),
+
+    #[error("secret '{0}' would be written as '{1}', which collides with a file of the snapshot")]
+    ReservedName(Utf8PathBuf, Utf8PathBuf),
+
+    #[error("pre-export hook failed, nothing was exported\n{0}")]
+    PreHook(hooks::HookError),
+
+    #[error("the snapshot was created, but the post-export hook failed\n{0}")]
+    PostHook(hooks::HookError),
+
     #[error("failed to scan source directory for unlisted files\n{0}")]
     ScanSource(std::io::Error),
 
+    #[error("failed to read the users and groups of this host\n{0}")]
+    LoadUsers(std::io::Error),
+
+    #[error("failed to set up the shared objects of container '{0}'\n{1}")]
+    EnableDedup(Utf8PathBuf, std::io::Error),
+
+    #[error(
+        "snapshots of deduplicated containers can't be written as archives (use another container than '{0}' for --format tar)"
+    )]
+    TarDedup(Utf8PathBuf),
+
+    #[error("failed to find the previous snapshot of the container\n{0}")]
+    ReadPrevious(std::io::Error),
+
     #[error("failed to remove stale partial snapshots in container '{0}'\n{1}")]
     RemoveStalePartials(Utf8PathBuf, std::io::Error),
 
@@ -237,14 +713,89 @@ pub enum ExportError {
     #[error("failed to finalize snapshot at '{0}'\n{1}")]
     Finalize(Utf8PathBuf, std::io::Error),
 
+    #[error("failed to copy the snapshot to '{0}'\n{1}")]
+    CopySnapshot(Utf8PathBuf, std::io::Error),
+
+    #[error("the copy of the snapshot at '{0}' does not match the original")]
+    CopyMismatch(Utf8PathBuf),
+
+    #[error(transparent)]
+    OpenCopy(snapshot::OpenError),
+
+    #[error(
+        "the snapshot could not be written to every container, and was removed from all of them (use --best-effort to keep it where it was written)"
+    )]
+    RolledBack,
+
+    #[error("the snapshot could not be written to {failed} of {total} containers")]
+    ContainersFailed { failed: usize, total: usize },
+
     #[error("failed to export file '{0}'\n{1}")]
     ExportFile(Utf8PathBuf, ExportFileError),
 
+    #[error("failed to read '{0}' to check it against its key pair\n{1}")]
+    ReadKeyPair(Utf8PathBuf, std::io::Error),
+
+    #[error("key pair check failed (use --skip-validation to export anyway)\n{0}")]
+    KeyPair(kind::ValidationError),
+
+    #[error("failed to pick a random name for a secret\n{0}")]
+    RandomName(std::io::Error),
+
+    #[error("failed to encrypt the checksums of the secrets\n{0}")]
+    SealChecksums(age::EncryptError),
+
+    #[error("failed to write the sealed checksums to the snapshot\n{0}")]
+    WriteSealedChecksums(std::io::Error),
+
+    #[error("failed to read back the sealed checksums\n{0}")]
+    VerifySealedChecksums(sealed::SealedError),
+
+    #[error("the sealed checksums do not decrypt back to the checksums of the secrets")]
+    SealedChecksumsMismatch,
+
+    #[error("failed to append checksum of the sealed checksums to export's sha256sums.txt\n{0}")]
+    AppendSealedChecksum(checksum::ChecksumError),
+
+    #[error("failed to record certificate expiries in the snapshot\n{0}")]
+    WriteCertExpiry(std::io::Error),
+
+    #[error(
+        "failed to append checksum of the certificate expiries to export's sha256sums.txt\n{0}"
+    )]
+    AppendCertExpiryChecksum(checksum::ChecksumError),
+
+    #[error("failed to serialize the metadata of the snapshot\n{0}")]
+    SerializeInfo(serde_json::Error),
+
+    #[error("failed to write the metadata of the snapshot\n{0}")]
+    WriteInfo(std::io::Error),
+
+    #[error(
+        "failed to append checksum of the metadata of the snapshot to export's sha256sums.txt\n{0}"
+    )]
+    AppendInfoChecksum(checksum::ChecksumError),
+
+    #[error("failed to read the exported checksum of '{0}' for the recovery kit\n{1}")]
+    ReadExportedSidecar(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to encrypt the recovery script\n{0}")]
+    EncryptRecoveryScript(age::EncryptError),
+
+    #[error("failed to write the recovery kit to the snapshot\n{0}")]
+    WriteRecoveryKit(std::io::Error),
+
+    #[error("failed to append checksum of the recovery kit to export's sha256sums.txt\n{0}")]
+    AppendRecoveryKitChecksum(checksum::ChecksumError),
+
     #[error(transparent)]
     ExportAdditional(ExportAdditionalError),
 
     #[error(transparent)]
     VerifyExport(checksum::ChecksumError),
+
+    #[error("the export would stop at {0} conflicts, see the plan above")]
+    PlanConflicts(usize),
 }
 impl ExportError {
     fn export_file(file: &Utf8PathBuf) -> impl FnOnce(ExportFileError) -> Self {
@@ -262,9 +813,13 @@ impl ExportError {
     fn finalize(target: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
         |e| Self::Finalize(target.clone(), e)
     }
+
+    fn copy_snapshot(target: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::CopySnapshot(target.clone(), e)
+    }
 }
 
-fn discover_files(dir: &Utf8PathBuf) -> std::io::Result<Vec<Utf8PathBuf>> {
+pub fn discover_files(dir: &Utf8PathBuf) -> std::io::Result<Vec<Utf8PathBuf>> {
     fn recurse(
         dir: &Utf8PathBuf,
         base: &Utf8PathBuf,
@@ -276,13 +831,14 @@ fn discover_files(dir: &Utf8PathBuf) -> std::io::Result<Vec<Utf8PathBuf>> {
                 continue;
             };
 
+            // symlinks are listed but not followed, they can be backed up as kind=symlink
             let file_type = entry.file_type()?;
             if file_type.is_dir() {
                 recurse(&path, base, out)?;
-            } else if file_type.is_file() {
-                if let Ok(rel) = path.strip_prefix(base) {
-                    out.push(rel.to_path_buf());
-                }
+            } else if (file_type.is_file() || file_type.is_symlink())
+                && let Ok(rel) = path.strip_prefix(base)
+            {
+                out.push(rel.to_path_buf());
             }
         }
 
@@ -295,17 +851,25 @@ fn discover_files(dir: &Utf8PathBuf) -> std::io::Result<Vec<Utf8PathBuf>> {
     Ok(out)
 }
 
-fn warn_unlisted_files(source: &Utf8PathBuf, secrets: &[manifest::Secret]) -> std::io::Result<()> {
-    let files = discover_files(source)?;
-
-    let unlisted: Vec<&Utf8PathBuf> = files
-        .iter()
+pub fn unlisted_files(
+    source: &Utf8PathBuf,
+    secrets: &[manifest::Secret],
+) -> std::io::Result<Vec<Utf8PathBuf>> {
+    let mut unlisted: Vec<Utf8PathBuf> = discover_files(source)?
+        .into_iter()
         .filter(|p| {
             let is_sidecar = p.extension() == Some("sha256");
             let is_manifest = p.file_name() == Some(manifest::MANIFEST_FILENAME);
-            !is_sidecar && !is_manifest && !secrets.iter().any(|s| &s.path == *p)
+            !is_sidecar && !is_manifest && !secrets.iter().any(|s| &s.path == p)
         })
         .collect();
+    unlisted.sort();
+
+    Ok(unlisted)
+}
+
+fn warn_unlisted_files(source: &Utf8PathBuf, secrets: &[manifest::Secret]) -> std::io::Result<()> {
+    let unlisted = unlisted_files(source, secrets)?;
 
     if !unlisted.is_empty() {
         println!(
@@ -320,15 +884,225 @@ fn warn_unlisted_files(source: &Utf8PathBuf, secrets: &[manifest::Secret]) -> st
     Ok(())
 }
 
-fn remove_stale_partials(container: &Utf8PathBuf) -> std::io::Result<()> {
-    for entry in fs::read_dir(container)? {
-        let entry = entry?;
-        let Ok(path) = Utf8PathBuf::from_path_buf(entry.path()) else {
+// A `.pub` listed next to its private key must be the public half of that same key, otherwise
+// restoring both would leave a broken pair behind. Returns the broken pairs, by private key
+fn check_key_pairs(
+    source: &Utf8PathBuf,
+    secrets: &[manifest::Secret],
+) -> Vec<(Utf8PathBuf, ExportError)> {
+    let read = |secret: &manifest::Secret| {
+        let file = secret.location(source);
+        fs::read(&file).map_err(|e| ExportError::ReadKeyPair(file, e))
+    };
+
+    let mut broken = Vec::new();
+    for (private, public) in kind::ssh_pairs(secrets) {
+        // the output of a command is only known once it runs, during the export itself
+        if public.from_cmd.is_some() || private.from_cmd.is_some() {
             continue;
-        };
-        if path.file_name().is_some_and(snapshot::is_partial) {
-            fs::remove_dir_all(&path)?;
         }
+
+        let checked = read(private).and_then(|private_content| {
+            kind::check_ssh_pair(
+                &private.path,
+                &private_content,
+                &public.path,
+                &read(public)?,
+            )
+            .map_err(ExportError::KeyPair)
+        });
+        if let Err(e) = checked {
+            broken.push((private.location(source), e));
+        }
+    }
+
+    broken
+}
+
+// A certificate listed next to its private key should have been issued for that key, otherwise
+// restoring both would leave a server that fails its TLS handshakes. This is only a warning, as
+// keys whose public half can't be extracted (encrypted or Ed25519 ones) can't be checked anyway
+fn warn_cert_key_mismatches(
+    source: &Utf8PathBuf,
+    secrets: &[manifest::Secret],
+) -> Result<(), ExportError> {
+    let read = |secret: &manifest::Secret| {
+        let file = secret.location(source);
+        fs::read(&file).map_err(|e| ExportError::ReadKeyPair(file, e))
+    };
+
+    let mut mismatches = Vec::new();
+    for (certificate, key) in cert::key_pairs(secrets) {
+        if certificate.from_cmd.is_some() || key.from_cmd.is_some() {
+            continue;
+        }
+
+        let certificate_content = read(certificate)?;
+        if !cert::is_certificate(certificate, &certificate_content) {
+            continue;
+        }
+        if let cert::KeyMatch::Mismatch = cert::key_matches(&certificate_content, &read(key)?) {
+            mismatches.push((&certificate.path, &key.path));
+        }
+    }
+    cert::warn_key_mismatches(&mismatches);
+
+    Ok(())
+}
+
+fn write_cert_expiries(
+    dir: &Utf8PathBuf,
+    expiries: &[(Utf8PathBuf, i64)],
+) -> Result<(), ExportError> {
+    if expiries.is_empty() {
+        return Ok(());
+    }
+
+    print!("Recording certificate expiries... ");
+    std::io::stdout().flush().unwrap();
+    let expiry_name = Utf8PathBuf::from(cert::EXPIRY_FILENAME);
+    fs::write(dir.join(&expiry_name), cert::render_expiries(expiries))
+        .map_err(ExportError::WriteCertExpiry)
+        .inspect_err(|_| println!("error"))?;
+    checksum::append_checksum(dir, &expiry_name)
+        .map_err(ExportError::AppendCertExpiryChecksum)
+        .inspect_err(|_| println!("error"))?;
+    println!("ok");
+    println!();
+
+    Ok(())
+}
+
+// The sidecar import restores next to a secret: the one exported with it, or the one import makes
+// up from the sealed checksums
+fn exported_sidecar(
+    secret: &manifest::Secret,
+    dir: &Utf8PathBuf,
+    content: &[u8],
+    sealed: bool,
+) -> Result<String, ExportError> {
+    if sealed {
+        let file_name = secret.path.file_name().unwrap_or(secret.path.as_str());
+        return Ok(format!("{}  {file_name}", sha256::digest(content)));
+    }
+
+    let sha_path = dir.join(&secret.path).add_extension("sha256");
+    let sidecar = fs::read_to_string(&sha_path)
+        .map_err(|e| ExportError::ReadExportedSidecar(sha_path.clone(), e))?;
+
+    Ok(sidecar.trim_end_matches('\n').to_string())
+}
+
+fn write_recovery_kit(
+    dir: &Utf8PathBuf,
+    entries: &[recovery::Entry],
+    manifest_content: &str,
+    passphrase: &str,
+    options: &ExportOptions,
+) -> Result<(), ExportError> {
+    print!("Writing recovery kit... ");
+    std::io::stdout().flush().unwrap();
+
+    // the script restores the manifest the way import does, without the captured metadata nor the
+    // random names of the snapshot
+    let script =
+        recovery::render_script(entries, &manifest_edit::without_captured(manifest_content));
+    let (script_name, script) = match options.seal_checksums {
+        true => (
+            recovery::ENCRYPTED_SCRIPT_FILENAME,
+            crypto::encrypt(script, passphrase)
+                .map_err(ExportError::EncryptRecoveryScript)
+                .inspect_err(|_| println!("error"))?,
+        ),
+        false => (recovery::SCRIPT_FILENAME, script.into_bytes()),
+    };
+    let readme = recovery::render_readme(entries, options.seal_checksums, options.private_layout);
+
+    for (name, content) in [
+        (recovery::README_FILENAME, readme.into_bytes()),
+        (script_name, script),
+    ] {
+        let name = Utf8PathBuf::from(name);
+        fs::write(dir.join(&name), content)
+            .map_err(ExportError::WriteRecoveryKit)
+            .inspect_err(|_| println!("error"))?;
+        checksum::append_checksum(dir, &name)
+            .map_err(ExportError::AppendRecoveryKitChecksum)
+            .inspect_err(|_| println!("error"))?;
+    }
+    println!("ok");
+    println!();
+
+    Ok(())
+}
+
+fn write_info(dir: &Utf8PathBuf, info: &info::Info) -> Result<(), ExportError> {
+    print!("Recording snapshot metadata... ");
+    std::io::stdout().flush().unwrap();
+    let info_name = Utf8PathBuf::from(info::INFO_FILENAME);
+    let content = serde_json::to_string_pretty(info)
+        .map_err(ExportError::SerializeInfo)
+        .inspect_err(|_| println!("error"))?;
+    fs::write(dir.join(&info_name), format!("{content}\n"))
+        .map_err(ExportError::WriteInfo)
+        .inspect_err(|_| println!("error"))?;
+    checksum::append_checksum(dir, &info_name)
+        .map_err(ExportError::AppendInfoChecksum)
+        .inspect_err(|_| println!("error"))?;
+    println!("ok");
+    println!();
+
+    Ok(())
+}
+
+// Metadata recording nothing but the name a secret is stored under
+fn stored_only(stored: &str) -> manifest::Captured {
+    manifest::Captured {
+        stored: Some(stored.to_string()),
+        ..Default::default()
+    }
+}
+
+fn write_sealed_checksums(
+    dir: &Utf8PathBuf,
+    digests: &[(Utf8PathBuf, String)],
+    passphrase: &str,
+) -> Result<(), ExportError> {
+    print!("Sealing checksums... ");
+    std::io::stdout().flush().unwrap();
+    let sealed_name = Utf8PathBuf::from(sealed::SEALED_FILENAME);
+    let encrypted = crypto::encrypt(sealed::render(digests), passphrase)
+        .map_err(ExportError::SealChecksums)
+        .inspect_err(|_| println!("error"))?;
+    fs::write(dir.join(&sealed_name), encrypted)
+        .map_err(ExportError::WriteSealedChecksums)
+        .inspect_err(|_| println!("error"))?;
+
+    // read back like import will, so that a snapshot whose secrets can't be checked never lands
+    let written = sealed::read(dir, passphrase)
+        .map_err(ExportError::VerifySealedChecksums)
+        .inspect_err(|_| println!("error"))?
+        .expect("sealed checksums were just written");
+    if digests
+        .iter()
+        .any(|(path, digest)| written.digest(path) != Some(digest.as_str()))
+    {
+        println!("error");
+        return Err(ExportError::SealedChecksumsMismatch);
+    }
+
+    checksum::append_checksum(dir, &sealed_name)
+        .map_err(ExportError::AppendSealedChecksum)
+        .inspect_err(|_| println!("error"))?;
+    println!("ok");
+    println!();
+
+    Ok(())
+}
+
+fn remove_stale_partials(container: &Utf8PathBuf) -> std::io::Result<()> {
+    for partial in snapshot::list_partials(container)? {
+        snapshot::remove(&partial)?;
     }
 
     Ok(())
@@ -339,21 +1113,145 @@ fn write_contents(
     dir: &Utf8PathBuf,
     secrets: &[manifest::Secret],
     passphrase: &str,
+    options: &ExportOptions,
+    db: &users::Database,
+    config: &config::Config,
 ) -> Result<(), ExportError> {
+    let dedup = Dedup::of(dir).map_err(ExportError::ReadPrevious)?;
+
     println!("Exporting secrets... ");
+    let mut expiries = Vec::new();
+    let mut captured = Vec::new();
+    let mut digests = Vec::new();
+    let mut total_size = 0;
+    let mut entries = Vec::new();
     for secret in secrets {
         let file_rel_path = &secret.path;
         print!("exporting '{file_rel_path}'... ");
         std::io::stdout().flush().unwrap();
 
-        export_file(file_rel_path, source, dir, passphrase)
+        // a manifest restored from a snapshot with a private layout may still hold the names of
+        // that snapshot, which mean nothing to this one
+        let mut secret = secret.clone();
+        secret.captured.stored = None;
+        if options.private_layout {
+            let stored = layout::random_name()
+                .map_err(ExportError::RandomName)
+                .inspect_err(|_| println!("error"))?;
+            secret.captured.stored = Some(stored);
+        }
+        let secret = &secret;
+
+        if secret.kind == Some(kind::Kind::Symlink) {
+            let destination = export_symlink(secret, source, dir, passphrase)
+                .map_err(ExportError::export_file(file_rel_path))
+                .inspect_err(|_| println!("error"))?;
+            if let Some(stored) = &secret.captured.stored {
+                captured.push((file_rel_path.clone(), stored_only(stored)));
+            }
+            let content = recovery::Content::Link { destination };
+            entries.push(recovery::Entry::new(secret, None, content));
+            println!("ok (symlink)");
+            continue;
+        }
+
+        let content = match &secret.from_cmd {
+            Some(_) => export_command(
+                secret,
+                source,
+                dir,
+                passphrase,
+                options,
+                config,
+                dedup.as_ref(),
+            ),
+            None => export_file(secret, source, dir, passphrase, options, dedup.as_ref()),
+        }
+        .map_err(ExportError::export_file(file_rel_path))
+        .inspect_err(|_| println!("error"))?;
+        let metadata = capture(secret, source, options, db)
             .map_err(ExportError::export_file(file_rel_path))
             .inspect_err(|_| println!("error"))?;
-        println!("ok");
+        let kit_content = match &secret.to_cmd {
+            Some(name) => recovery::Content::Command { name: name.clone() },
+            None => recovery::Content::File {
+                digest: sha256::digest(&content),
+                sidecar: exported_sidecar(secret, dir, &content, options.seal_checksums)
+                    .inspect_err(|_| println!("error"))?,
+            },
+        };
+        entries.push(recovery::Entry::new(secret, metadata.as_ref(), kit_content));
+        match (metadata, &secret.captured.stored) {
+            (Some(metadata), stored) => captured.push((
+                file_rel_path.clone(),
+                manifest::Captured {
+                    stored: stored.clone(),
+                    ..metadata
+                },
+            )),
+            (None, Some(stored)) => captured.push((file_rel_path.clone(), stored_only(stored))),
+            (None, None) => {}
+        }
+        if options.seal_checksums {
+            digests.push((file_rel_path.clone(), sha256::digest(&content)));
+        }
+        total_size += content.len() as u64;
+
+        if cert::is_certificate(secret, &content) {
+            match cert::not_after(&content) {
+                Some(not_after) => {
+                    expiries.push((file_rel_path.clone(), not_after));
+                    println!("ok (certificate expires {})", date::format_date(not_after));
+                }
+                None => println!("ok (warning: could not read the certificate expiry)"),
+            }
+        } else {
+            println!("ok");
+        }
     }
     println!();
 
-    export_additional(source, dir).map_err(ExportError::ExportAdditional)?;
+    if let Some(dedup) = &dedup
+        && let Some(previous) = &dedup.previous
+        && dedup.reused.get() > 0
+    {
+        println!(
+            "{} unchanged secrets reused from {}",
+            dedup.reused.get(),
+            previous.file_name().unwrap_or(previous.as_str())
+        );
+        println!();
+    }
+
+    if options.seal_checksums {
+        write_sealed_checksums(dir, &digests, passphrase)?;
+    }
+
+    // the expiries would tell the names of the certificates
+    if !options.private_layout {
+        write_cert_expiries(dir, &expiries)?;
+    }
+
+    let encryption = info::Encryption {
+        scheme: "age-scrypt".to_string(),
+        sealed_checksums: options.seal_checksums,
+        private_layout: options.private_layout,
+    };
+    let info = info::Info::new(
+        options.label.clone(),
+        options.note.clone(),
+        secrets.len(),
+        total_size,
+        encryption,
+    );
+    write_info(dir, &info)?;
+
+    let index_passphrase = options.private_layout.then_some(passphrase);
+    let manifest_content =
+        export_additional(source, dir, &captured, dedup.as_ref(), index_passphrase)
+            .map_err(ExportError::ExportAdditional)?;
+
+    write_recovery_kit(dir, &entries, &manifest_content, passphrase, options)?;
 
     print!("Verifying export integrity... ");
     std::io::stdout().flush().unwrap();
@@ -366,33 +1264,179 @@ fn write_contents(
     Ok(())
 }
 
-fn build_snapshot(
+fn export_name(name: &str, format: snapshot::Format) -> String {
+    match format {
+        snapshot::Format::Dir => name.to_string(),
+        snapshot::Format::Tar => format!("{name}.tar"),
+    }
+}
+
+// Where copy_tree stores the files it copies into a deduplicated container: only the files listed
+// in the checksums of the snapshot become objects, as objects::unreferenced tells the objects still
+// in use from these checksums (sha256sums.txt itself, which lists none of itself, is copied)
+struct CopyObjects<'a> {
+    container: &'a Utf8PathBuf,
+    listed: Vec<&'a str>,
+}
+
+// Copies the files of a snapshot directory, following the links to the objects of its container.
+// The copies are linked to the objects of the destination container, if it is deduplicated
+fn copy_tree(
     source: &Utf8PathBuf,
+    destination: &Utf8PathBuf,
+    objects: Option<&CopyObjects>,
+) -> std::io::Result<()> {
+    fs::create_dir(destination)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name
+            .to_str()
+            .ok_or_else(|| std::io::Error::other("snapshot holds a non utf8 file name"))?;
+        let entry_source = source.join(name);
+        let entry_destination = destination.join(name);
+
+        if fs::metadata(&entry_source)?.is_dir() {
+            copy_tree(&entry_source, &entry_destination, objects)?;
+            continue;
+        }
+        let content = fs::read(&entry_source)?;
+        match objects {
+            Some(objects) if objects.listed.contains(&sha256::digest(&content).as_str()) => {
+                let object = objects::store(objects.container, &content)?;
+                objects::link(&object, &entry_destination)?;
+            }
+            // copied rather than written, to keep its mode
+            _ => {
+                fs::copy(&entry_source, &entry_destination)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Copies a snapshot written to another container to this one, through a partial name, and checks
+// the copy as verify-export would
+fn replicate(snapshot: &Utf8PathBuf, container: &Utf8PathBuf) -> Result<(), ExportError> {
+    let name = snapshot.file_name().expect("snapshots have a file name");
+    let partial = container.join(snapshot::to_partial(name));
+    let export_path = container.join(name);
+    let is_tar = snapshot::is_tar(snapshot);
+
+    let copied = match is_tar {
+        true => fs::copy(snapshot, &partial).map(|_| ()),
+        false => fs::read_to_string(snapshot.join("sha256sums.txt")).and_then(|sums| {
+            let objects = CopyObjects {
+                container,
+                listed: sums
+                    .lines()
+                    .filter_map(|line| line.split_once("  "))
+                    .map(|(digest, _)| digest)
+                    .collect(),
+            };
+            let objects = objects::is_enabled(container).then_some(&objects);
+            copy_tree(snapshot, &partial, objects)
+        }),
+    }
+    .map_err(ExportError::copy_snapshot(container));
+    let verified = copied.and_then(|()| match is_tar {
+        true => verify_archive_copy(snapshot, &partial),
+        false => checksum::verify_checksums(&partial).map_err(ExportError::VerifyExport),
+    });
+    if let Err(e) = verified {
+        let _ = snapshot::remove(&partial);
+        return Err(e);
+    }
+
+    fs::rename(&partial, &export_path)
+        .map_err(ExportError::finalize(&export_path))
+        .inspect_err(|_| {
+            let _ = snapshot::remove(&partial);
+        })?;
+    if !is_tar {
+        return Ok(());
+    }
+
+    // the outer `.sha256` names the archive, which has the same name in every container
+    let verified = fs::copy(
+        snapshot.add_extension("sha256"),
+        export_path.add_extension("sha256"),
+    )
+    .map_err(ExportError::copy_snapshot(container))
+    .and_then(|_| snapshot::open(&export_path).map_err(ExportError::OpenCopy))
+    .and_then(|opened| checksum::verify_checksums(&opened.dir).map_err(ExportError::VerifyExport));
+    if let Err(e) = verified {
+        let _ = snapshot::remove(&export_path);
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+// Checks an archive being copied against the outer `.sha256` of the original, before it is named
+// as an archive
+fn verify_archive_copy(snapshot: &Utf8PathBuf, copy: &Utf8PathBuf) -> Result<(), ExportError> {
+    let digest = checksum::read_digest(&snapshot.add_extension("sha256"))
+        .map_err(ExportError::VerifyExport)?;
+    let content = fs::read(copy).map_err(ExportError::copy_snapshot(copy))?;
+    if sha256::digest(content) != digest {
+        return Err(ExportError::CopyMismatch(copy.clone()));
+    }
+
+    Ok(())
+}
+
+// Writes a snapshot through a partial directory, which only gets its final name once write_contents
+// succeeded, and is removed otherwise. Archives are packed from that same partial directory. Returns
+// the name of the snapshot
+fn build_snapshot(
     container: &Utf8PathBuf,
     name: &str,
-    secrets: &[manifest::Secret],
-    passphrase: &str,
-) -> Result<(), ExportError> {
+    format: snapshot::Format,
+    write_contents: impl FnOnce(&Utf8PathBuf) -> Result<(), ExportError>,
+) -> Result<String, ExportError> {
     let partial_dir = container.join(snapshot::to_partial(name));
-    let export_dir = container.join(name);
+    let export_name = export_name(name, format);
+    let export_path = container.join(&export_name);
 
-    if export_dir.exists() {
-        return Err(ExportError::SnapshotExists(export_dir));
+    if export_path.exists() {
+        return Err(ExportError::SnapshotExists(export_path));
     }
 
     fs::create_dir(&partial_dir).map_err(ExportError::create_partial(&partial_dir))?;
 
-    if let Err(e) = write_contents(source, &partial_dir, secrets, passphrase) {
+    if let Err(e) = write_contents(&partial_dir) {
         let _ = fs::remove_dir_all(&partial_dir);
         return Err(e);
     }
 
-    fs::rename(&partial_dir, &export_dir).map_err(ExportError::finalize(&export_dir))?;
+    match format {
+        snapshot::Format::Dir => {
+            fs::rename(&partial_dir, &export_path).map_err(ExportError::finalize(&export_path))?
+        }
+        snapshot::Format::Tar => {
+            print!("Packing snapshot archive... ");
+            std::io::stdout().flush().unwrap();
+            let packed = snapshot::pack(&partial_dir, &export_path);
+            let _ = fs::remove_dir_all(&partial_dir);
+            packed
+                .map_err(ExportError::finalize(&export_path))
+                .inspect_err(|_| println!("error"))?;
+            println!("ok");
+            println!();
+        }
+    }
 
-    Ok(())
+    Ok(export_name)
 }
 
-pub fn export(source: String, target: String, passphrase: String) -> Result<(), ExportError> {
+pub fn export(
+    source: String,
+    targets: Vec<String>,
+    passphrase: String,
+    options: ExportOptions,
+) -> Result<(), ExportError> {
     let source = {
         let path = Utf8PathBuf::from(&source);
         if !path.exists() {
@@ -403,27 +1447,575 @@ pub fn export(source: String, target: String, passphrase: String) -> Result<(),
         path
     };
 
-    let target = {
+    let mut checked_targets: Vec<Utf8PathBuf> = Vec::new();
+    // a container given twice would get the snapshot written over itself
+    let mut resolved_targets = Vec::new();
+    for target in targets {
         let path = Utf8PathBuf::from(&target);
         if !path.exists() {
             return Err(ExportError::MissingTargetPath(path));
         } else if !path.is_dir() {
             return Err(ExportError::TargetNotDir(path));
         }
-        path
+        let resolved = path
+            .canonicalize_utf8()
+            .map_err(|e| ExportError::ResolveTarget(path.clone(), e))?;
+        if let Some(i) = resolved_targets.iter().position(|r| *r == resolved) {
+            return Err(ExportError::DuplicateTarget(
+                checked_targets[i].clone(),
+                path,
+            ));
+        }
+        resolved_targets.push(resolved);
+        checked_targets.push(path);
+    }
+    let targets = checked_targets;
+
+    // loaded even with --no-hooks, for the from-cmd= commands
+    let mut config = config::load(options.config.as_deref()).map_err(ExportError::LoadConfig)?;
+    if options.no_hooks {
+        config.hooks.clear();
+    }
+    // the hooks are listed in the plan instead
+    if options.dry_run {
+        return plan_export(&source, &targets, &options, &config);
+    }
+    let mut env = vec![
+        ("SECS_MAN_SECRETS_DIR", source.to_string()),
+        ("SECS_MAN_CONTAINER", targets[0].to_string()),
+        ("SECS_MAN_CONTAINERS", lines(&targets)),
+    ];
+
+    // run before the manifest is even read, so that it can prepare the secrets to export
+    hooks::run_hook(&config, hooks::Hook::PreExport, &env).map_err(ExportError::PreHook)?;
+
+    let result = export_snapshot(&source, &targets, &passphrase, &options, &config);
+    if let Ok(written) = &result
+        && let Some(first) = written.snapshots.first()
+    {
+        env.push(("SECS_MAN_SNAPSHOT", first.to_string()));
+        env.push(("SECS_MAN_SNAPSHOTS", lines(&written.snapshots)));
+    }
+    let result = result.and_then(|written| match written.failed {
+        0 => Ok(()),
+        failed => Err(ExportError::ContainersFailed {
+            failed,
+            total: targets.len(),
+        }),
+    });
+    hooks::run_post_hook(
+        &config,
+        hooks::Hook::PostExport,
+        env,
+        result,
+        ExportError::PostHook,
+    )?;
+
+    Ok(())
+}
+
+fn lines(paths: &[Utf8PathBuf]) -> String {
+    paths.iter().map(|path| format!("{path}\n")).collect()
+}
+
+// Snapshots written by an export, and the number of containers it failed to write to (only ever
+// non-zero for a best-effort export)
+struct Written {
+    snapshots: Vec<Utf8PathBuf>,
+    failed: usize,
+}
+
+// Copies the snapshot to the containers after the one it was written to, so that every container
+// holds the same encrypted files. `outcomes` holds the containers up to the one written to, the
+// ones before it having failed (with --best-effort). An all-or-nothing export removes the snapshot
+// from every container as soon as one copy fails
+fn replicate_all<'a>(
+    targets: &'a [Utf8PathBuf],
+    mut outcomes: Vec<(&'a Utf8PathBuf, Result<(), ExportError>)>,
+    name: &str,
+    best_effort: bool,
+) -> Result<Written, ExportError> {
+    let snapshot = outcomes
+        .last()
+        .expect("the snapshot was written")
+        .0
+        .join(name);
+    for container in &targets[outcomes.len()..] {
+        print!("Copying snapshot to '{container}'... ");
+        std::io::stdout().flush().unwrap();
+        let result = replicate(&snapshot, container);
+        match &result {
+            Ok(()) => println!("ok"),
+            Err(_) => println!("error"),
+        }
+        let failed = result.is_err();
+        outcomes.push((container, result));
+        if failed && !best_effort {
+            break;
+        }
+    }
+    println!();
+
+    let failed = outcomes
+        .iter()
+        .filter(|(_, result)| result.is_err())
+        .count();
+    let rollback = failed > 0 && !best_effort;
+    let mut snapshots = Vec::new();
+    println!("Containers:");
+    for (container, result) in &outcomes {
+        match result {
+            Ok(()) if rollback => {
+                let removed = snapshot::remove(&container.join(name));
+                match removed {
+                    Ok(()) => println!("  {container}: removed"),
+                    Err(e) => println!("  {container}: written, but could not be removed\n  {e}"),
+                }
+            }
+            Ok(()) => {
+                println!("  {container}: ok");
+                snapshots.push(container.join(name));
+            }
+            Err(e) => println!("  {container}: FAILED\n  {e}"),
+        }
+    }
+    for container in &targets[outcomes.len()..] {
+        println!("  {container}: skipped");
+    }
+    println!();
+
+    if rollback {
+        return Err(ExportError::RolledBack);
+    }
+
+    Ok(Written { snapshots, failed })
+}
+
+// Loads the manifest and checks the secrets, before anything is written
+// Loads the secrets to export, warning about the files left out of the manifest and the
+// certificates that don't match their key
+fn load_secrets(source: &Utf8PathBuf) -> Result<Vec<manifest::Secret>, ExportError> {
+    let secrets = manifest::load(source).map_err(ExportError::LoadManifest)?;
+    warn_unlisted_files(source, &secrets).map_err(ExportError::ScanSource)?;
+    warn_cert_key_mismatches(source, &secrets)?;
+
+    Ok(secrets)
+}
+
+// The files an export writes next to the secrets of a snapshot
+fn generated_names(options: &ExportOptions) -> Vec<&'static str> {
+    let mut names = vec![
+        manifest::MANIFEST_FILENAME,
+        "sha256sums.txt",
+        cert::EXPIRY_FILENAME,
+        info::INFO_FILENAME,
+        recovery::README_FILENAME,
+    ];
+    match options.seal_checksums {
+        true => names.extend([sealed::SEALED_FILENAME, recovery::ENCRYPTED_SCRIPT_FILENAME]),
+        false => names.push(recovery::SCRIPT_FILENAME),
+    }
+    if options.private_layout {
+        names.push(layout::INDEX_FILENAME);
+    }
+
+    names
+}
+
+// The files a secret is written as, outside of a private layout whose random names can't collide
+fn secret_names(secret: &manifest::Secret, options: &ExportOptions) -> Vec<Utf8PathBuf> {
+    if secret.kind == Some(kind::Kind::Symlink) {
+        return vec![secret.path.add_extension("symlink")];
+    }
+    let mut names = vec![secret.path.add_extension("age")];
+    if !options.seal_checksums {
+        names.push(secret.path.add_extension("sha256"));
+    }
+
+    names
+}
+
+// What would stop the export before anything is written, with the path each problem is about
+fn secret_problems(
+    source: &Utf8PathBuf,
+    secrets: &[manifest::Secret],
+    options: &ExportOptions,
+    config: &config::Config,
+) -> Vec<(Utf8PathBuf, ExportError)> {
+    let mut problems = Vec::new();
+    for secret in secrets {
+        if let Some(name) = &secret.from_cmd
+            && config.command(name).is_none()
+        {
+            let e = ExportError::UnknownCommand(secret.path.clone(), name.clone());
+            problems.push((secret.location(source), e));
+        }
+    }
+
+    if !options.private_layout {
+        let generated = generated_names(options);
+        for secret in secrets {
+            for name in secret_names(secret, options) {
+                if generated.contains(&name.as_str()) {
+                    let e = ExportError::ReservedName(secret.path.clone(), name);
+                    problems.push((secret.location(source), e));
+                }
+            }
+        }
+    }
+
+    if !options.skip_validation {
+        problems.extend(check_key_pairs(source, secrets));
+    }
+
+    problems
+}
+
+fn check_secrets(
+    source: &Utf8PathBuf,
+    options: &ExportOptions,
+    config: &config::Config,
+) -> Result<Vec<manifest::Secret>, ExportError> {
+    let secrets = load_secrets(source)?;
+    if let Some((_, e)) = secret_problems(source, &secrets, options, config)
+        .into_iter()
+        .next()
+    {
+        return Err(e);
+    }
+
+    Ok(secrets)
+}
+
+// the files of an archive can't be links to the objects of the container
+fn check_format(target: &Utf8PathBuf, options: &ExportOptions) -> Result<(), ExportError> {
+    if options.format == snapshot::Format::Tar && (options.dedup || objects::is_enabled(target)) {
+        return Err(ExportError::TarDedup(target.clone()));
+    }
+
+    Ok(())
+}
+
+// What export_file, export_command or export_symlink would do for a secret. Sources are read to be
+// checked, but missing sidecars are not generated
+fn plan_secret(
+    plan: &mut Plan,
+    secret: &manifest::Secret,
+    source: &Utf8PathBuf,
+    options: &ExportOptions,
+    config: &config::Config,
+) {
+    let file_rel_path = &secret.path;
+    let stored = match options.private_layout {
+        true => "<random name>.age".to_string(),
+        false => secret.stored_path().add_extension("age").to_string(),
+    };
+
+    if secret.kind == Some(kind::Kind::Symlink) {
+        let file_source = source.join(file_rel_path);
+        if !file_source.is_symlink() {
+            let e = ExportFileError::NotASymlink(file_source.clone());
+            return plan.push(Action::Conflict(file_source, e.to_string()));
+        }
+        let destination = match file_source.read_link_utf8() {
+            Ok(destination) => destination,
+            Err(e) => {
+                let e = ExportFileError::read_link(&file_source)(e);
+                return plan.push(Action::Conflict(file_source, e.to_string()));
+            }
+        };
+        match manifest::check_resolved_link_target(source, file_rel_path, &destination) {
+            Ok(()) => plan.push(Action::Symlink(file_source, destination)),
+            Err(e) => {
+                let e = ExportFileError::link_target(&file_source)(e);
+                plan.push(Action::Conflict(file_source, e.to_string()));
+            }
+        }
+        return;
+    }
+
+    if let Some(name) = &secret.from_cmd {
+        // an unknown command is already a conflict of the plan
+        let Some(command) = config.command(name) else {
+            return;
+        };
+        let what = format!("command '{name}' for '{file_rel_path}'");
+        plan.push(Action::Run(what, command.to_string()));
+        plan.push(Action::Encrypt(file_rel_path.clone(), stored));
+        return;
+    }
+
+    let file_source = secret.location(source);
+    let sha_source = secret.sidecar(source);
+    let file_content = match fs::read(&file_source) {
+        Ok(content) => content,
+        Err(e) => {
+            let e = ExportFileError::read(&file_source)(e);
+            return plan.push(Action::Conflict(file_source, e.to_string()));
+        }
     };
+    if !sha_source.exists() {
+        plan.push(Action::GenerateSidecar(sha_source.clone()));
+    } else if let Err(e) = checksum::verify_checksum_at(&file_source, &sha_source) {
+        let e = ExportFileError::verify_source(&file_source)(e);
+        return plan.push(Action::Conflict(file_source, e.to_string()));
+    }
+    // a missing sidecar would be generated from the first location
+    for location in secret.at.iter().skip(1) {
+        let checked = match sha_source.exists() {
+            true => checksum::verify_checksum_at(location, &sha_source),
+            false => fs::read(location)
+                .map_err(|e| checksum::ChecksumError::ReadSource(location.clone(), e))
+                .and_then(|content| {
+                    let digest = sha256::digest(&file_content);
+                    checksum::verify_digest(&content, &digest, location, &sha_source)
+                }),
+        };
+        if let Err(e) = checked {
+            let e = ExportFileError::diverged_location(location)(e);
+            plan.push(Action::Conflict(location.clone(), e.to_string()));
+        }
+    }
+    if let Some(kind) = secret.kind
+        && !options.skip_validation
+        && let Err(e) = kind::validate(kind, &file_content)
+    {
+        let e = ExportFileError::validation(&file_source)(e);
+        return plan.push(Action::Conflict(file_source, e.to_string()));
+    }
 
-    let secrets = manifest::load(&source).map_err(ExportError::LoadManifest)?;
+    plan.push(Action::Encrypt(file_source, stored));
+}
 
-    warn_unlisted_files(&source, &secrets).map_err(ExportError::ScanSource)?;
+// Lists what the export would do, hooks and commands included. Nothing is written, nothing is run
+// and nothing is encrypted, so the unchanged secrets a deduplicated container would reuse are not
+// told apart
+fn plan_export(
+    source: &Utf8PathBuf,
+    targets: &[Utf8PathBuf],
+    options: &ExportOptions,
+    config: &config::Config,
+) -> Result<(), ExportError> {
+    let secrets = load_secrets(source)?;
+    for target in targets {
+        check_format(target, options)?;
+    }
 
-    remove_stale_partials(&target).map_err(ExportError::remove_stale_partials(&target))?;
+    let mut plan = Plan::default();
+    for (path, e) in secret_problems(source, &secrets, options, config) {
+        plan.push(Action::Conflict(path, e.to_string()));
+    }
+    if let Some(command) = config.hook(hooks::Hook::PreExport) {
+        plan.push(Action::Run(
+            "pre-export hook".to_string(),
+            command.to_string(),
+        ));
+    }
+    let name = export_name(&snapshot::new_export(), options.format);
+    for target in targets {
+        plan.push(Action::Snapshot(target.join(&name)));
+    }
+    for secret in &secrets {
+        plan_secret(&mut plan, secret, source, options, config);
+    }
+    if let Some(command) = config.hook(hooks::Hook::PostExport) {
+        plan.push(Action::Run(
+            "post-export hook".to_string(),
+            command.to_string(),
+        ));
+    }
+
+    plan.print("Export plan (dry run, nothing was written):");
+    match plan.conflicts() {
+        0 => Ok(()),
+        conflicts => Err(ExportError::PlanConflicts(conflicts)),
+    }
+}
+
+fn export_snapshot(
+    source: &Utf8PathBuf,
+    targets: &[Utf8PathBuf],
+    passphrase: &str,
+    options: &ExportOptions,
+    config: &config::Config,
+) -> Result<Written, ExportError> {
+    let secrets = check_secrets(source, options, config)?;
+
+    // names are only needed to record owners
+    let db = if options.capture_metadata || secrets.iter().any(|s| s.capture_owner) {
+        users::Database::load().map_err(ExportError::LoadUsers)?
+    } else {
+        users::Database::default()
+    };
+
+    for target in targets {
+        remove_stale_partials(target).map_err(ExportError::remove_stale_partials(target))?;
+        check_format(target, options)?;
+    }
+    if options.dedup {
+        for target in targets {
+            objects::enable(target).map_err(|e| ExportError::EnableDedup(target.clone(), e))?;
+        }
+    }
 
     let name = snapshot::new_export();
-    build_snapshot(&source, &target, &name, &secrets, &passphrase)?;
+    for target in &targets[1..] {
+        let export_path = target.join(export_name(&name, options.format));
+        if export_path.exists() {
+            return Err(ExportError::SnapshotExists(export_path));
+        }
+    }
+    // with --best-effort, a container the snapshot can't be written to is left for the next one
+    let mut outcomes = Vec::new();
+    let name = loop {
+        let target = &targets[outcomes.len()];
+        let built = build_snapshot(target, &name, options.format, |dir| {
+            write_contents(source, dir, &secrets, passphrase, options, &db, config)
+        });
+        match built {
+            Ok(name) => {
+                outcomes.push((target, Ok(())));
+                break name;
+            }
+            Err(e) if options.best_effort && outcomes.len() + 1 < targets.len() => {
+                println!(
+                    "Warning: failed to write the snapshot to '{target}', trying the next container"
+                );
+                println!("{e}");
+                println!();
+                outcomes.push((target, Err(e)));
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    let written = match targets.len() {
+        1 => Written {
+            snapshots: vec![targets[0].join(&name)],
+            failed: 0,
+        },
+        _ => replicate_all(targets, outcomes, &name, options.best_effort)?,
+    };
 
-    println!("Export completed successfully!");
+    if written.failed == 0 {
+        println!("Export completed successfully!");
+    }
     println!("Snapshot: {name}");
 
-    Ok(())
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+    use std::{thread, time::Duration};
+
+    use super::*;
+    use crate::import;
+    use crate::test_support::{self, PASSPHRASE, TempDir};
+
+    const FILES: &[(&str, &str)] = &[("a", "first\n"), ("dir/b", "second\n")];
+
+    fn assert_imported(snapshot: &Utf8PathBuf, tmp: &TempDir, name: &str) -> Utf8PathBuf {
+        let imported = tmp.dir(name);
+        import::import(
+            snapshot.to_string(),
+            imported.to_string(),
+            Vec::new(),
+            test_support::encrypted(),
+            tmp.import_options(),
+        )
+        .unwrap();
+        for (path, content) in FILES {
+            assert_eq!(&fs::read_to_string(imported.join(path)).unwrap(), content);
+        }
+
+        imported
+    }
+
+    // A snapshot with a private layout tells nothing of the secrets it holds, and still restores
+    // them, symlinks included, with their modes and a manifest without the random names
+    #[test]
+    fn private_layout_round_trip() {
+        let tmp = TempDir::new();
+        let source = tmp.secrets_dir("source", FILES);
+        symlink("dir/b", source.join("link")).unwrap();
+        fs::write(
+            source.join(manifest::MANIFEST_FILENAME),
+            "a mode=0640\ndir/b\nlink kind=symlink\n",
+        )
+        .unwrap();
+
+        let mut options = tmp.export_options();
+        options.private_layout = true;
+        options.seal_checksums = true;
+        let snapshot = test_support::export(&source, &tmp.dir("container"), options);
+
+        assert!(snapshot.join(layout::INDEX_FILENAME).is_file());
+        for hidden in [manifest::MANIFEST_FILENAME, "a.age", "dir", "link.symlink"] {
+            assert!(
+                !snapshot.join(hidden).exists(),
+                "'{hidden}' is in the snapshot"
+            );
+        }
+        let sums = fs::read_to_string(snapshot.join("sha256sums.txt")).unwrap();
+        assert!(!sums.contains("dir/b"));
+
+        let imported = assert_imported(&snapshot, &tmp, "imported");
+        assert_eq!(imported.join("link").read_link_utf8().unwrap(), "dir/b");
+        let mode = fs::metadata(imported.join("a"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o640);
+        let manifest = fs::read_to_string(imported.join(manifest::MANIFEST_FILENAME)).unwrap();
+        assert_eq!(manifest, "a mode=0640\ndir/b\nlink kind=symlink\n");
+    }
+
+    // A copy to a deduplicated container that fails removes the snapshot from the container it was
+    // written to, without touching the objects the previous snapshot of that container links to
+    #[test]
+    fn dedup_rollback_round_trip() {
+        let tmp = TempDir::new();
+        let source = tmp.secrets_dir("source", FILES);
+        let first = tmp.dir("first");
+        let second = tmp.dir("second");
+
+        let options = || ExportOptions {
+            dedup: true,
+            ..tmp.export_options()
+        };
+        let previous = test_support::export(&source, &first, options());
+
+        // the unchanged secrets reuse the encrypted files of the previous snapshot, so the object
+        // of the copy is known in advance, and a directory in its place makes the copy fail
+        let encrypted = fs::read(previous.join("a.age")).unwrap();
+        let blocker = second
+            .join(objects::OBJECTS_DIRNAME)
+            .join(sha256::digest(&encrypted));
+        fs::create_dir_all(&blocker).unwrap();
+        // snapshot names only tell the second they were taken
+        thread::sleep(Duration::from_secs(1));
+        let export_to_both = || {
+            export(
+                source.to_string(),
+                vec![first.to_string(), second.to_string()],
+                PASSPHRASE.to_string(),
+                options(),
+            )
+        };
+        assert!(matches!(export_to_both(), Err(ExportError::RolledBack)));
+
+        assert_eq!(snapshot::list_snapshots(&first).unwrap().len(), 1);
+        assert!(snapshot::list_snapshots(&second).unwrap().is_empty());
+        checksum::verify_checksums(&previous).unwrap();
+        assert_imported(&previous, &tmp, "after-rollback");
+
+        fs::remove_dir(&blocker).unwrap();
+        thread::sleep(Duration::from_secs(1));
+        export_to_both().unwrap();
+        let copy = second.join(snapshot::newest(&second).unwrap().unwrap());
+        assert_eq!(fs::read(copy.join("a.age")).unwrap(), encrypted);
+        assert_imported(&copy, &tmp, "copy");
+    }
 }
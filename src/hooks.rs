@@ -0,0 +1,184 @@
+use std::{
+    fmt,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use thiserror::Error;
+
+use crate::config::Config;
+
+// Fixed points of export, import and verify-export at which the config can run a command. `pre-*`
+// hooks can stop the operation by failing, `post-*` hooks run whatever the outcome, which they get
+// in SECS_MAN_STATUS
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    PreExport,
+    PostExport,
+    PreImport,
+    PostImport,
+    PostVerify,
+}
+impl Hook {
+    pub const ALL: [Hook; 5] = [
+        Hook::PreExport,
+        Hook::PostExport,
+        Hook::PreImport,
+        Hook::PostImport,
+        Hook::PostVerify,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Hook::PreExport => "pre-export",
+            Hook::PostExport => "post-export",
+            Hook::PreImport => "pre-import",
+            Hook::PostImport => "post-import",
+            Hook::PostVerify => "post-verify",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|h| h.as_str() == name)
+    }
+}
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum FailurePolicy {
+    // stop at the first failing command, and fail
+    Abort,
+    // report failing commands, and carry on
+    Warn,
+}
+
+#[derive(Error, Debug)]
+pub enum HookError {
+    #[error("failed to run command '{0}'\n{1}")]
+    Spawn(String, std::io::Error),
+
+    #[error("command '{0}' failed ({1})")]
+    Failed(String, std::process::ExitStatus),
+
+    #[error("failed to pipe the secret to command '{0}'\n{1}")]
+    Pipe(String, std::io::Error),
+}
+
+// Runs a configured command through /bin/sh, with the given extra environment. Its output goes
+// straight to the terminal
+pub fn run(name: &str, command: &str, env: &[(&str, String)]) -> Result<(), HookError> {
+    let status = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .status()
+        .map_err(|e| HookError::Spawn(name.to_string(), e))?;
+
+    if !status.success() {
+        return Err(HookError::Failed(name.to_string(), status));
+    }
+
+    Ok(())
+}
+
+// Runs a configured command and returns what it printed on stdout, which is never shown. Its
+// stderr still goes to the terminal
+pub fn capture(name: &str, command: &str, env: &[(&str, String)]) -> Result<Vec<u8>, HookError> {
+    let output = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| HookError::Spawn(name.to_string(), e))?;
+
+    if !output.status.success() {
+        return Err(HookError::Failed(name.to_string(), output.status));
+    }
+
+    Ok(output.stdout)
+}
+
+// Runs a configured command with the given input on its stdin
+pub fn feed(
+    name: &str,
+    command: &str,
+    env: &[(&str, String)],
+    input: &[u8],
+) -> Result<(), HookError> {
+    let mut child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| HookError::Spawn(name.to_string(), e))?;
+
+    // dropped right after writing, so that the command sees the end of its input
+    let written = child.stdin.take().expect("stdin is piped").write_all(input);
+    let status = child
+        .wait()
+        .map_err(|e| HookError::Spawn(name.to_string(), e))?;
+
+    if !status.success() {
+        return Err(HookError::Failed(name.to_string(), status));
+    }
+    // a command that succeeds without reading all of its input is still a failure
+    written.map_err(|e| HookError::Pipe(name.to_string(), e))?;
+
+    Ok(())
+}
+
+// Runs a hook, if the config defines it
+pub fn run_hook(config: &Config, hook: Hook, env: &[(&str, String)]) -> Result<(), HookError> {
+    let Some(command) = config.hook(hook) else {
+        return Ok(());
+    };
+
+    println!("Running {hook} hook ({command})");
+    run(hook.as_str(), command, env)?;
+    println!("{hook} hook ok");
+    println!();
+
+    Ok(())
+}
+
+// Runs a `post-*` hook with the outcome of the operation it follows. A failing hook fails an
+// operation that succeeded, but is only reported for one that failed, so as not to hide its error
+pub fn run_post_hook<T, E>(
+    config: &Config,
+    hook: Hook,
+    env: Vec<(&str, String)>,
+    result: Result<T, E>,
+    hook_error: impl FnOnce(HookError) -> E,
+) -> Result<T, E> {
+    let status = if result.is_ok() { "ok" } else { "failed" };
+    run_post_hook_with_status(config, hook, env, status, result, hook_error)
+}
+
+// Same as run_post_hook, for operations that can end some other way than succeeding or failing
+pub fn run_post_hook_with_status<T, E>(
+    config: &Config,
+    hook: Hook,
+    mut env: Vec<(&str, String)>,
+    status: &str,
+    result: Result<T, E>,
+    hook_error: impl FnOnce(HookError) -> E,
+) -> Result<T, E> {
+    env.push(("SECS_MAN_STATUS", status.to_string()));
+
+    match (run_hook(config, hook, &env), result) {
+        (Ok(()), result) => result,
+        (Err(e), Ok(_)) => Err(hook_error(e)),
+        (Err(e), Err(original)) => {
+            println!("Warning: {e}");
+            println!();
+            Err(original)
+        }
+    }
+}
@@ -2,6 +2,7 @@ use std::{
     fs::{self, Permissions},
     io::Write,
     os::unix::fs::PermissionsExt,
+    time::{Duration, UNIX_EPOCH},
 };
 
 use thiserror::Error;
@@ -9,10 +10,49 @@ use thiserror::Error;
 use camino::{Utf8Path, Utf8PathBuf};
 
 use crate::{
-    checksum, chown_spec::ChownSpec, crypto, manifest, safe_fs, snapshot,
+    cert, checksum, chown,
+    chown_spec::ChownSpec,
+    config, crypto, hooks, info, kind, layout, manifest, manifest_edit,
+    owner_map::{self, OwnerMap},
+    plan::{Action, Plan},
+    prompt, safe_fs, sealed, snapshot, users,
     utf8path_ext::ExtraUtf8Path,
+    xattr,
 };
 
+pub struct ImportOptions {
+    // leave restored files owned by the runner at 0600, ignoring the manifest's owner and mode
+    pub skip_chown_chmod: bool,
+    // import secrets even if their content does not match their kind
+    pub skip_validation: bool,
+    // `from=to` renames applied to every owner before chowning
+    pub map_owner: Vec<String>,
+    // a file holding more of these renames, one per line
+    pub owner_map: Option<String>,
+    // write secrets annotated with at= to their locations rather than into the secrets directory
+    pub deploy: bool,
+    // host config holding the on-import and to-cmd= commands and the hooks, instead of the default
+    // one
+    pub config: Option<String>,
+    // do not run on-import commands nor hooks
+    pub no_hooks: bool,
+    // list the on-import commands that would run instead of running them
+    pub dry_run_hooks: bool,
+    pub hook_failure: hooks::FailurePolicy,
+    // list what the import would do, without writing anything nor running any command
+    pub dry_run: bool,
+    // import the newest snapshot of the container with this label rather than the newest one
+    pub label: Option<String>,
+}
+
+// The snapshot being imported, as a directory
+struct Source<'a> {
+    dir: &'a Utf8PathBuf,
+    source_type: &'a SourceType,
+    // digests of the secrets of a snapshot exported with --seal-checksums, which has no sidecars
+    sealed: Option<sealed::Checksums>,
+}
+
 pub enum SourceType {
     Encrypted { passphrase: String },
     Plaintext,
@@ -26,12 +66,22 @@ pub enum ImportFileError {
     #[error("failed to decrypt contents of source file at '{0}'\n{1}")]
     DecryptionFail(Utf8PathBuf, age::DecryptError),
 
+    #[error(
+        "content of '{0}' does not match its kind (use --skip-validation to import it anyway)\n{1}"
+    )]
+    Validation(Utf8PathBuf, kind::ValidationError),
+
     #[error("file at '{0}' has ill-formed parent directory, cannot resolve")]
     IllFormedParent(Utf8PathBuf),
 
     #[error("failed to create directory at '{0}'\n{1}")]
     CreateParent(Utf8PathBuf, std::io::Error),
 
+    #[error(
+        "the directory of deploy location '{0}' does not exist (deploy only creates directories inside of the secrets directory)"
+    )]
+    MissingDeployDir(Utf8PathBuf),
+
     #[error("failed to assign permissions to file at endpoint ('{0}')\n{1}")]
     ChmodFail(Utf8PathBuf, std::io::Error),
 
@@ -41,11 +91,32 @@ pub enum ImportFileError {
     #[error("failed to verify integrity of imported file at '{0}'\n{1}")]
     VerifyImport(Utf8PathBuf, checksum::ChecksumError),
 
-    #[error("failed to run chown for '{0}'\n{1}")]
-    ChownSpawn(Utf8PathBuf, std::io::Error),
+    #[error("failed to set owner of '{0}'\n{1}")]
+    Chown(Utf8PathBuf, chown::ChownError),
+
+    #[error("failed to restore modification time of '{0}'\n{1}")]
+    SetMtime(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to restore extended attribute '{1}' of '{0}'\n{2}")]
+    SetXattr(Utf8PathBuf, String, std::io::Error),
+
+    #[error("failed to read target of symlink '{0}'\n{1}")]
+    ReadLink(Utf8PathBuf, std::io::Error),
+
+    #[error("refusing to import symlink '{0}'\n{1}")]
+    LinkTarget(Utf8PathBuf, manifest::InvalidLinkTarget),
+
+    #[error("failed to safely create symlink at endpoint ('{0}')\n{1}")]
+    SafeSymlink(Utf8PathBuf, safe_fs::SafeFsError),
 
-    #[error("failed to set owner '{1}' on '{0}'\n{2}")]
-    ChownFail(Utf8PathBuf, String, String),
+    #[error("failed to restore the secret through its command\n{0}")]
+    ToCommand(hooks::HookError),
+
+    #[error("failed to replace the sidecar at '{0}'\n{1}")]
+    ReplaceSidecar(Utf8PathBuf, std::io::Error),
+
+    #[error("the sealed checksums of the snapshot have no checksum for '{0}'")]
+    MissingSealedChecksum(Utf8PathBuf),
 }
 impl ImportFileError {
     fn read_fail(source: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
@@ -56,6 +127,10 @@ impl ImportFileError {
         |e| Self::DecryptionFail(source.clone(), e)
     }
 
+    fn validation(source: &Utf8PathBuf) -> impl Fn(kind::ValidationError) -> Self {
+        |e| Self::Validation(source.clone(), e)
+    }
+
     fn create_parent(target: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
         |e| Self::CreateParent(target.clone(), e)
     }
@@ -72,8 +147,28 @@ impl ImportFileError {
         |e| Self::VerifyImport(target.clone(), e)
     }
 
-    fn chown_spawn(target: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
-        |e| Self::ChownSpawn(target.clone(), e)
+    fn chown(target: &Utf8PathBuf) -> impl Fn(chown::ChownError) -> Self {
+        |e| Self::Chown(target.clone(), e)
+    }
+
+    fn set_mtime(target: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::SetMtime(target.clone(), e)
+    }
+
+    fn read_link(source: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::ReadLink(source.clone(), e)
+    }
+
+    fn link_target(source: &Utf8PathBuf) -> impl Fn(manifest::InvalidLinkTarget) -> Self {
+        |e| Self::LinkTarget(source.clone(), e)
+    }
+
+    fn safe_symlink(target: &Utf8PathBuf) -> impl Fn(safe_fs::SafeFsError) -> Self {
+        |e| Self::SafeSymlink(target.clone(), e)
+    }
+
+    fn replace_sidecar(target: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::ReplaceSidecar(target.clone(), e)
     }
 }
 fn chmod_file(path: &Utf8PathBuf, mode: u32) -> Result<(), ImportFileError> {
@@ -88,52 +183,41 @@ fn chmod_dir(path: &Utf8PathBuf) -> Result<(), ImportFileError> {
 
     Ok(())
 }
-fn chown(path: &Utf8PathBuf, spec: &ChownSpec) -> Result<(), ImportFileError> {
-    let output = std::process::Command::new("chown")
-        .arg("--")
-        .arg(spec.as_str())
-        .arg(path)
-        .output()
-        .map_err(ImportFileError::chown_spawn(path))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
-        return Err(ImportFileError::ChownFail(
-            path.clone(),
-            spec.as_str().to_string(),
-            stderr,
-        ));
+fn chown_file(
+    path: &Utf8PathBuf,
+    spec: &ChownSpec,
+    db: &users::Database,
+) -> Result<(), ImportFileError> {
+    let owner = chown::resolve(spec, db).map_err(ImportFileError::chown(path))?;
+    chown::chown(path, spec, owner).map_err(ImportFileError::chown(path))?;
+
+    Ok(())
+}
+fn set_mtime(path: &Utf8PathBuf, mtime: i64) -> Result<(), ImportFileError> {
+    let offset = Duration::from_secs(mtime.unsigned_abs());
+    let time = if mtime >= 0 {
+        UNIX_EPOCH + offset
+    } else {
+        UNIX_EPOCH - offset
+    };
+    fs::File::open(path)
+        .and_then(|file| file.set_modified(time))
+        .map_err(ImportFileError::set_mtime(path))?;
+
+    Ok(())
+}
+fn set_xattrs(path: &Utf8PathBuf, xattrs: &[(String, Vec<u8>)]) -> Result<(), ImportFileError> {
+    for (name, value) in xattrs {
+        xattr::set(path, name, value)
+            .map_err(|e| ImportFileError::SetXattr(path.clone(), name.clone(), e))?;
     }
 
     Ok(())
 }
-fn import_file(
-    secret: &manifest::Secret,
-    source: &Utf8PathBuf,
+fn create_parents(
     target: &Utf8PathBuf,
-    source_type: &SourceType,
-    skip_chown_chmod: bool,
+    file_rel_path: &Utf8PathBuf,
 ) -> Result<(), ImportFileError> {
-    let file_rel_path = &secret.path;
-    let file_target = target.join(file_rel_path);
-
-    let sha_source = source.join(file_rel_path).add_extension("sha256");
-    let sha_target = target.join(file_rel_path).add_extension("sha256");
-
-    let file_content = match source_type {
-        SourceType::Encrypted { passphrase } => {
-            let file_source = source.join(file_rel_path).add_extension("age");
-            let encrypted_content =
-                fs::read(&file_source).map_err(ImportFileError::read_fail(&file_source))?;
-            crypto::decrypt(encrypted_content, passphrase)
-                .map_err(ImportFileError::decryption_fail(&file_source))?
-        }
-        SourceType::Plaintext => {
-            let file_source = source.join(file_rel_path);
-            fs::read(&file_source).map_err(ImportFileError::read_fail(&file_source))?
-        }
-    };
-
     if let Some(parent) = file_rel_path.parent() {
         let ancestors = {
             // For some reason calling directly .rev() after .ancestors() doesn't work
@@ -157,43 +241,462 @@ fn import_file(
         }
     }
 
-    safe_fs::safe_write(&file_target, file_content)
-        .map_err(ImportFileError::safe_write(&file_target))?;
+    Ok(())
+}
+
+fn deploy_dir_exists(location: &Utf8Path) -> bool {
+    location.parent().is_some_and(|parent| parent.is_dir())
+}
+
+// The target of a symlink, from the `<path>.symlink` file holding it (or from the symlink itself,
+// for plaintext sources)
+fn link_destination(
+    secret: &manifest::Secret,
+    source: &Utf8PathBuf,
+    source_type: &SourceType,
+) -> Result<Utf8PathBuf, ImportFileError> {
+    let file_rel_path = &secret.path;
+
+    let destination = match source_type {
+        // the target of a link of a private layout is encrypted, as it names another secret
+        SourceType::Encrypted { passphrase } if secret.captured.stored.is_some() => {
+            let link_source = source.join(secret.stored_path()).add_extension("age");
+            let encrypted_content =
+                fs::read(&link_source).map_err(ImportFileError::read_fail(&link_source))?;
+            let content = crypto::decrypt(encrypted_content, passphrase)
+                .map_err(ImportFileError::decryption_fail(&link_source))?;
+            Utf8PathBuf::from(String::from_utf8_lossy(&content).to_string())
+        }
+        SourceType::Encrypted { .. } => {
+            let link_source = source.join(file_rel_path).add_extension("symlink");
+            let content = fs::read_to_string(&link_source)
+                .map_err(ImportFileError::read_fail(&link_source))?;
+            Utf8PathBuf::from(content)
+        }
+        SourceType::Plaintext => {
+            let link_source = source.join(file_rel_path);
+            link_source
+                .read_link_utf8()
+                .map_err(ImportFileError::read_link(&link_source))?
+        }
+    };
+    // the links of a snapshot don't exist as links yet, only a plaintext source can be resolved
+    match source_type {
+        SourceType::Plaintext => {
+            manifest::check_resolved_link_target(source, file_rel_path, &destination)
+        }
+        SourceType::Encrypted { .. } => manifest::check_link_target(file_rel_path, &destination),
+    }
+    .map_err(ImportFileError::link_target(file_rel_path))?;
+
+    Ok(destination)
+}
+
+fn import_symlink(
+    secret: &manifest::Secret,
+    source: &Utf8PathBuf,
+    target: &Utf8PathBuf,
+    source_type: &SourceType,
+    skip_chown_chmod: bool,
+    db: &users::Database,
+) -> Result<safe_fs::Written, ImportFileError> {
+    let file_rel_path = &secret.path;
+    let file_target = target.join(file_rel_path);
+
+    let destination = link_destination(secret, source, source_type)?;
+    create_parents(target, file_rel_path)?;
+
+    let written = safe_fs::safe_symlink(&file_target, &destination)
+        .map_err(ImportFileError::safe_symlink(&file_target))?;
+    if !skip_chown_chmod && let Some(owner) = &secret.owner {
+        chown_file(&file_target, owner, db)?;
+    }
+
+    Ok(written)
+}
+
+// Writes the content of a secret at one of its destinations, with its metadata
+fn place_file(
+    secret: &manifest::Secret,
+    file_target: &Utf8PathBuf,
+    file_content: &[u8],
+    skip_chown_chmod: bool,
+    db: &users::Database,
+) -> Result<safe_fs::Written, ImportFileError> {
+    let written = safe_fs::safe_write(file_target, file_content)
+        .map_err(ImportFileError::safe_write(file_target))?;
+    // restored while the file is still owned by the runner, chmod and chown leave it untouched
+    if let Some(mtime) = secret.captured.mtime {
+        set_mtime(file_target, mtime)?;
+    }
     if !skip_chown_chmod {
         if let Some(mode) = secret.mode {
-            chmod_file(&file_target, mode)?;
+            chmod_file(file_target, mode)?;
         }
+        // after chmod, which would otherwise overwrite the mask of a restored ACL
+        set_xattrs(file_target, &secret.captured.xattrs)?;
         if let Some(owner) = &secret.owner {
-            chown(&file_target, owner)?;
+            chown_file(file_target, owner, db)?;
+        }
+    }
+
+    Ok(written)
+}
+
+// The plaintext of a secret, decrypted from the snapshot (or read from a plaintext source)
+fn secret_content(secret: &manifest::Secret, source: &Source) -> Result<Vec<u8>, ImportFileError> {
+    match source.source_type {
+        SourceType::Encrypted { passphrase } => {
+            let file_source = source.dir.join(secret.stored_path()).add_extension("age");
+            let encrypted_content =
+                fs::read(&file_source).map_err(ImportFileError::read_fail(&file_source))?;
+            crypto::decrypt(encrypted_content, passphrase)
+                .map_err(ImportFileError::decryption_fail(&file_source))
+        }
+        SourceType::Plaintext => {
+            let file_source = source.dir.join(&secret.path);
+            fs::read(&file_source).map_err(ImportFileError::read_fail(&file_source))
         }
     }
+}
 
-    let sha_content = fs::read(&sha_source).map_err(ImportFileError::read_fail(&sha_source))?;
+// Checks the ssh key pairs of the secrets to import, and warns about the certificates not issued
+// for the private key next to them, before anything is written. Only the secrets of a pair are
+// decrypted here
+fn check_key_pairs(
+    secrets: &[manifest::Secret],
+    source: &Source,
+    skip_validation: bool,
+) -> Result<(), ImportError> {
+    let content = |secret: &manifest::Secret| {
+        secret_content(secret, source).map_err(ImportError::import_file(&secret.path))
+    };
+
+    if !skip_validation {
+        for (private, public) in kind::ssh_pairs(secrets) {
+            kind::check_ssh_pair(
+                &private.path,
+                &content(private)?,
+                &public.path,
+                &content(public)?,
+            )
+            .map_err(ImportError::KeyPair)?;
+        }
+    }
 
+    let mut mismatches = Vec::new();
+    for (certificate, key) in cert::key_pairs(secrets) {
+        let certificate_content = content(certificate)?;
+        if !cert::is_certificate(certificate, &certificate_content) {
+            continue;
+        }
+        if let cert::KeyMatch::Mismatch = cert::key_matches(&certificate_content, &content(key)?) {
+            mismatches.push((&certificate.path, &key.path));
+        }
+    }
+    cert::warn_key_mismatches(&mismatches);
+
+    Ok(())
+}
+
+// The sidecar of a secret, from the snapshot or from its sealed checksums
+fn sidecar_content(secret: &manifest::Secret, source: &Source) -> Result<Vec<u8>, ImportFileError> {
+    let file_rel_path = &secret.path;
+    let sha_source = source.dir.join(file_rel_path).add_extension("sha256");
+
+    match &source.sealed {
+        Some(sealed) => Ok(sealed
+            .sidecar(file_rel_path)
+            .ok_or(ImportFileError::MissingSealedChecksum(
+                file_rel_path.clone(),
+            ))?
+            .into_bytes()),
+        None => fs::read(&sha_source).map_err(ImportFileError::read_fail(&sha_source)),
+    }
+}
+
+fn piped_unchanged(sha_target: &Utf8PathBuf, sha_content: &[u8]) -> bool {
+    fs::read(sha_target).is_ok_and(|existing| existing == sha_content)
+}
+
+// The secrets directory keeps the sidecar of a secret piped to its command, so that the next import
+// of the same content is told apart and triggers nothing. Unlike the content of a file, the one
+// fed to a command is replaced on every import, and so is its sidecar
+fn record_piped(
+    secret: &manifest::Secret,
+    target: &Utf8PathBuf,
+    sha_content: &[u8],
+    skip_chown_chmod: bool,
+    db: &users::Database,
+) -> Result<safe_fs::Written, ImportFileError> {
+    let file_rel_path = &secret.path;
+    let sha_target = target.join(file_rel_path).add_extension("sha256");
+    if piped_unchanged(&sha_target, sha_content) {
+        return Ok(safe_fs::Written::Unchanged);
+    }
+
+    create_parents(target, file_rel_path)?;
+    if sha_target.exists() {
+        fs::remove_file(&sha_target).map_err(ImportFileError::replace_sidecar(&sha_target))?;
+    }
     safe_fs::safe_write(&sha_target, sha_content)
         .map_err(ImportFileError::safe_write(&sha_target))?;
     if !skip_chown_chmod {
         chmod_file(&sha_target, 0o600)?;
         if let Some(owner) = &secret.owner {
-            chown(&sha_target, owner)?;
+            chown_file(&sha_target, owner, db)?;
         }
     }
 
-    checksum::verify_file_checksum(&file_target)
-        .map_err(ImportFileError::verify_import(&file_target))?;
+    Ok(safe_fs::Written::Created)
+}
+
+fn import_file(
+    secret: &manifest::Secret,
+    source: &Source,
+    target: &Utf8PathBuf,
+    options: &ImportOptions,
+    db: &users::Database,
+    config: &config::Config,
+) -> Result<safe_fs::Written, ImportFileError> {
+    let file_rel_path = &secret.path;
+
+    let sha_source = source.dir.join(file_rel_path).add_extension("sha256");
+    let sha_target = target.join(file_rel_path).add_extension("sha256");
+
+    let file_content = secret_content(secret, source)?;
+    let sha_content = sidecar_content(secret, source)?;
+
+    if let Some(kind) = secret.kind
+        && !options.skip_validation
+    {
+        kind::validate(kind, &file_content).map_err(ImportFileError::validation(file_rel_path))?;
+    }
+
+    // piped to its command rather than written anywhere, so checked before the command sees it
+    if let Some(name) = &secret.to_cmd {
+        checksum::parse_digest(&String::from_utf8_lossy(&sha_content), &sha_source)
+            .and_then(|digest| {
+                checksum::verify_digest(&file_content, &digest, file_rel_path, &sha_source)
+            })
+            .map_err(ImportFileError::verify_import(file_rel_path))?;
+        let command = config
+            .command(name)
+            .expect("commands are checked before import");
+        let env = [
+            ("SECS_MAN_SECRETS_DIR", target.to_string()),
+            ("SECS_MAN_SECRET", file_rel_path.to_string()),
+        ];
+        hooks::feed(name, command, &env, &file_content).map_err(ImportFileError::ToCommand)?;
+        return record_piped(secret, target, &sha_content, options.skip_chown_chmod, db);
+    }
+
+    create_parents(target, file_rel_path)?;
+
+    // deployed secrets are written to their at= locations instead of the secrets directory, which
+    // only keeps their sidecar
+    let destinations = if options.deploy && !secret.at.is_empty() {
+        // creating a missing system directory would leave it with whatever owner and mode the
+        // import gives it, so it has to exist already
+        if let Some(location) = secret.at.iter().find(|at| !deploy_dir_exists(at)) {
+            return Err(ImportFileError::MissingDeployDir(location.clone()));
+        }
+        secret.at.clone()
+    } else {
+        vec![target.join(file_rel_path)]
+    };
+
+    // the secret changed if any of its destinations did
+    let mut written = safe_fs::Written::Unchanged;
+    for destination in &destinations {
+        if place_file(
+            secret,
+            destination,
+            &file_content,
+            options.skip_chown_chmod,
+            db,
+        )? == safe_fs::Written::Created
+        {
+            written = safe_fs::Written::Created;
+        }
+    }
+
+    safe_fs::safe_write(&sha_target, sha_content)
+        .map_err(ImportFileError::safe_write(&sha_target))?;
+    if !options.skip_chown_chmod {
+        chmod_file(&sha_target, 0o600)?;
+        if let Some(owner) = &secret.owner {
+            chown_file(&sha_target, owner, db)?;
+        }
+    }
+
+    for destination in &destinations {
+        checksum::verify_checksum_at(destination, &sha_target)
+            .map_err(ImportFileError::verify_import(destination))?;
+    }
+
+    Ok(written)
+}
+
+// The owner and mode to restore a secret with: the ones of the manifest, or else the ones captured
+// at export. Captured names are preferred, falling back to the captured ids when a name does not
+// exist on this host. Owners are renamed through the owner map first
+fn resolve_owner(
+    secret: &manifest::Secret,
+    db: &users::Database,
+    owner_map: &OwnerMap,
+) -> manifest::Secret {
+    let mut resolved = secret.clone();
+    let captured = &secret.captured;
+
+    if let Some(owner) = &secret.owner {
+        resolved.owner = Some(owner_map.apply(owner));
+    } else {
+        let known_names = captured
+            .owner
+            .as_ref()
+            .map(|owner| owner_map.apply(owner))
+            .filter(|owner| {
+                let known_user = owner
+                    .user()
+                    .is_none_or(|u| users::is_numeric_id(u) || db.has_user(u));
+                let known_group = owner
+                    .group()
+                    .is_none_or(|g| users::is_numeric_id(g) || db.has_group(g));
+                known_user && known_group
+            });
+        resolved.owner = known_names.or_else(|| {
+            captured.ids.map(|(uid, gid)| {
+                let ids = format!("{uid}:{gid}")
+                    .parse()
+                    .expect("numeric ids are a valid owner spec");
+                owner_map.apply(&ids)
+            })
+        });
+    }
+    if resolved.mode.is_none() {
+        resolved.mode = captured.mode;
+    }
+
+    resolved
+}
+
+// Records the on-import commands of a secret that changed, each listed once (in manifest order)
+// with the secrets that triggered it
+fn record_triggers(
+    triggered: &mut Vec<(String, Vec<Utf8PathBuf>)>,
+    secret: &manifest::Secret,
+    written: safe_fs::Written,
+) {
+    if written == safe_fs::Written::Unchanged {
+        return;
+    }
+    for name in &secret.on_import {
+        match triggered.iter_mut().find(|(n, _)| n == name) {
+            Some((_, paths)) => paths.push(secret.path.clone()),
+            None => triggered.push((name.clone(), vec![secret.path.clone()])),
+        }
+    }
+}
+
+// Runs the triggered on-import commands, once all the secrets are written. The secrets that
+// triggered a command are given to it in SECS_MAN_CHANGED, one per line
+fn run_hooks(
+    config: &config::Config,
+    triggered: &[(String, Vec<Utf8PathBuf>)],
+    options: &ImportOptions,
+) -> Result<(), ImportError> {
+    if triggered.is_empty() {
+        return Ok(());
+    }
+
+    if options.no_hooks {
+        println!("Skipped on-import commands (--no-hooks):");
+        for (name, paths) in triggered {
+            println!("  - {name} (for {})", join_paths(paths));
+        }
+        println!();
+        return Ok(());
+    }
+
+    if options.dry_run_hooks {
+        println!("On-import commands that would run:");
+        for (name, paths) in triggered {
+            let command = config
+                .command(name)
+                .expect("commands are checked before import");
+            println!("  - {name}: {command} (for {})", join_paths(paths));
+        }
+        println!();
+        return Ok(());
+    }
+
+    println!("Running on-import commands... ");
+    let mut failed = 0;
+    for (name, paths) in triggered {
+        let command = config
+            .command(name)
+            .expect("commands are checked before import");
+        println!("running '{name}' ({command})");
+        let changed = paths
+            .iter()
+            .map(|p| p.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        match hooks::run(name, command, &[("SECS_MAN_CHANGED", changed)]) {
+            Ok(()) => println!("'{name}' ok"),
+            Err(e) if options.hook_failure == hooks::FailurePolicy::Warn => {
+                println!("Warning: {e}");
+                failed += 1;
+            }
+            Err(e) => return Err(ImportError::Hook(e)),
+        }
+    }
+    println!();
+
+    if failed > 0 {
+        println!("Warning: {failed} on-import commands failed.");
+        println!();
+    }
 
     Ok(())
 }
 
+fn join_paths(paths: &[Utf8PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|p| format!("'{p}'"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// The owner an imported secret was given, if any
+fn effective_owner(secret: &manifest::Secret, skip_chown_chmod: bool) -> Option<&str> {
+    secret
+        .owner
+        .as_ref()
+        .filter(|_| !skip_chown_chmod)
+        .map(|owner| owner.as_str())
+}
+
+// Restores the manifest of the snapshot, or the decrypted index of a snapshot with a private layout
 fn restore_manifest(
     source: &Utf8PathBuf,
+    index: Option<&str>,
     target: &Utf8PathBuf,
 ) -> Result<(), ImportFileError> {
     let name = Utf8PathBuf::from(manifest::MANIFEST_FILENAME);
     let manifest_source = source.join(&name);
     let manifest_target = target.join(&name);
 
-    let content = fs::read(&manifest_source).map_err(ImportFileError::read_fail(&manifest_source))?;
+    let content = match index {
+        Some(index) => index.to_string(),
+        None => fs::read_to_string(&manifest_source)
+            .map_err(ImportFileError::read_fail(&manifest_source))?,
+    };
+    // the captured metadata (and the random names of a private layout) are the ones of the
+    // snapshot, not of the secrets directory
+    let content = manifest_edit::without_captured(&content);
     safe_fs::safe_write(&manifest_target, content)
         .map_err(ImportFileError::safe_write(&manifest_target))?;
     chmod_file(&manifest_target, 0o600)?;
@@ -201,14 +704,165 @@ fn restore_manifest(
     Ok(())
 }
 
-fn confirm(prompt: &str) -> std::io::Result<bool> {
-    print!("{prompt} [y/N]: ");
-    std::io::stdout().flush()?;
+// The chown import would make, or the conflict it would stop at when the owner does not exist
+fn plan_chown(plan: &mut Plan, path: &Utf8PathBuf, owner: &ChownSpec, db: &users::Database) {
+    match chown::resolve(owner, db) {
+        Ok(_) => plan.push(Action::Chown(path.clone(), owner.as_str().to_string())),
+        Err(e) => plan.push(Action::Conflict(path.clone(), e.to_string())),
+    }
+}
+
+// What import_symlink or import_file would do for a secret. Files are compared with the digest of
+// their sidecar rather than decrypted
+fn plan_secret(
+    plan: &mut Plan,
+    secret: &manifest::Secret,
+    source: &Source,
+    target: &Utf8PathBuf,
+    options: &ImportOptions,
+    db: &users::Database,
+) -> Result<safe_fs::Written, ImportFileError> {
+    let file_rel_path = &secret.path;
+    let owner = secret.owner.as_ref().filter(|_| !options.skip_chown_chmod);
+
+    if secret.kind == Some(kind::Kind::Symlink) {
+        let destination = link_destination(secret, source.dir, source.source_type)?;
+        plan.create_parents(target, file_rel_path);
+        let file_target = target.join(file_rel_path);
+        let written = match safe_fs::check_symlink(&file_target, &destination) {
+            Ok(safe_fs::Written::Created) => {
+                plan.push(Action::Symlink(file_target.clone(), destination));
+                safe_fs::Written::Created
+            }
+            checked => plan.record(&file_target, checked),
+        };
+        if let Some(owner) = owner {
+            plan_chown(plan, &file_target, owner, db);
+        }
+        return Ok(written);
+    }
+
+    let sha_source = source.dir.join(file_rel_path).add_extension("sha256");
+    let sha_target = target.join(file_rel_path).add_extension("sha256");
+    let sha_content = sidecar_content(secret, source)?;
+    let digest = checksum::parse_digest(&String::from_utf8_lossy(&sha_content), &sha_source)
+        .map_err(ImportFileError::verify_import(file_rel_path))?;
+
+    if let Some(name) = &secret.to_cmd {
+        plan.push(Action::Pipe(file_rel_path.clone(), name.clone()));
+        if piped_unchanged(&sha_target, &sha_content) {
+            return Ok(safe_fs::Written::Unchanged);
+        }
+        plan.create_parents(target, file_rel_path);
+        plan.push(Action::Create(sha_target.clone()));
+        return Ok(safe_fs::Written::Created);
+    }
+
+    plan.create_parents(target, file_rel_path);
+    let destinations = if options.deploy && !secret.at.is_empty() {
+        // the real import stops at the first missing directory, before writing anything
+        let missing: Vec<_> = secret
+            .at
+            .iter()
+            .filter(|at| !deploy_dir_exists(at))
+            .collect();
+        for location in &missing {
+            let e = ImportFileError::MissingDeployDir((*location).clone());
+            plan.push(Action::Conflict((*location).clone(), e.to_string()));
+        }
+        if !missing.is_empty() {
+            return Ok(safe_fs::Written::Unchanged);
+        }
+        secret.at.clone()
+    } else {
+        vec![target.join(file_rel_path)]
+    };
+
+    let mut written = safe_fs::Written::Unchanged;
+    for destination in &destinations {
+        let checked =
+            safe_fs::check_write(destination, |content| sha256::digest(content) == digest);
+        if plan.record(destination, checked) == safe_fs::Written::Created {
+            written = safe_fs::Written::Created;
+        }
+        if !options.skip_chown_chmod {
+            if let Some(mode) = secret.mode {
+                plan.push(Action::Chmod(destination.clone(), mode));
+            }
+            if let Some(owner) = owner {
+                plan_chown(plan, destination, owner, db);
+            }
+        }
+    }
+
+    let checked = safe_fs::check_write(&sha_target, |content| content == sha_content);
+    plan.record(&sha_target, checked);
+    if !options.skip_chown_chmod {
+        plan.push(Action::Chmod(sha_target.clone(), 0o600));
+        if let Some(owner) = owner {
+            plan_chown(plan, &sha_target, owner, db);
+        }
+    }
+
+    Ok(written)
+}
+
+// Lists what the import would do, in the order it would do it, hooks and on-import commands
+// included. Nothing is written and nothing is run
+fn plan_import(
+    secrets: &[manifest::Secret],
+    source: &Source,
+    target: &Utf8PathBuf,
+    options: &ImportOptions,
+    db: &users::Database,
+    config: &config::Config,
+    restore_manifest: bool,
+) -> Result<(), ImportError> {
+    let mut plan = Plan::default();
+    if let Some(command) = config.hook(hooks::Hook::PreImport) {
+        plan.push(Action::Run(
+            "pre-import hook".to_string(),
+            command.to_string(),
+        ));
+    }
 
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input)?;
+    let mut triggered = Vec::new();
+    for secret in secrets {
+        // a secret that can't be read from the snapshot is one more conflict, the plan goes on
+        // with the others
+        match plan_secret(&mut plan, secret, source, target, options, db) {
+            Ok(written) => record_triggers(&mut triggered, secret, written),
+            Err(e) => plan.push(Action::Conflict(secret.path.clone(), e.to_string())),
+        }
+    }
+
+    if restore_manifest {
+        let manifest_target = target.join(manifest::MANIFEST_FILENAME);
+        plan.push(Action::Create(manifest_target.clone()));
+        plan.push(Action::Chmod(manifest_target, 0o600));
+    }
 
-    Ok(matches!(input.trim(), "y" | "Y"))
+    if !options.no_hooks {
+        for (name, paths) in &triggered {
+            let command = config
+                .command(name)
+                .expect("commands are checked before import");
+            let what = format!("on-import command '{name}' (for {})", join_paths(paths));
+            plan.push(Action::Run(what, command.to_string()));
+        }
+    }
+    if let Some(command) = config.hook(hooks::Hook::PostImport) {
+        plan.push(Action::Run(
+            "post-import hook".to_string(),
+            command.to_string(),
+        ));
+    }
+
+    plan.print("Import plan (dry run, nothing was written):");
+    match plan.conflicts() {
+        0 => Ok(()),
+        conflicts => Err(ImportError::PlanConflicts(conflicts)),
+    }
 }
 
 #[derive(Error, Debug)]
@@ -225,6 +879,36 @@ pub enum ImportError {
     #[error("failed to read confirmation from stdin\n{0}")]
     Confirm(std::io::Error),
 
+    #[error("failed to read the users and groups of this host\n{0}")]
+    LoadUsers(std::io::Error),
+
+    #[error("invalid owner mapping\n{0}")]
+    OwnerMap(owner_map::OwnerMapError),
+
+    #[error("failed to load config\n{0}")]
+    LoadConfig(config::ConfigError),
+
+    #[error("pre-import hook failed, nothing was imported\n{0}")]
+    PreHook(hooks::HookError),
+
+    #[error("the secrets were imported, but the post-import hook failed\n{0}")]
+    PostHook(hooks::HookError),
+
+    #[error(
+        "secret '{0}' asks for on-import command '{1}', which is not defined in the [commands] of the config (use --no-hooks to import anyway)"
+    )]
+    UnknownCommand(Utf8PathBuf, String),
+
+    #[error(
+        "secret '{0}' is restored through command '{1}', which is not defined in the [commands] of the config"
+    )]
+    UnknownToCommand(Utf8PathBuf, String),
+
+    #[error(
+        "on-import command failed, the secrets are imported but the remaining commands did not run\n{0}"
+    )]
+    Hook(hooks::HookError),
+
     #[error("source path '{0}' does not exist")]
     MissingSourcePath(Utf8PathBuf),
     #[error("source path '{0}' is not a directory")]
@@ -236,9 +920,27 @@ pub enum ImportError {
     #[error("container '{0}' holds no snapshots to import")]
     EmptyContainer(Utf8PathBuf),
 
+    #[error("--label selects a snapshot of a container, but '{0}' is a snapshot")]
+    LabelNeedsContainer(Utf8PathBuf),
+
+    #[error("container '{0}' holds no snapshot labelled '{1}'")]
+    NoLabelledSnapshot(Utf8PathBuf, String),
+
+    #[error(transparent)]
+    ReadInfo(info::InfoError),
+
     #[error("source '{0}' is neither a snapshot nor a container of snapshots")]
     NotSnapshotOrContainer(Utf8PathBuf),
 
+    #[error(transparent)]
+    OpenSnapshot(snapshot::OpenError),
+
+    #[error(transparent)]
+    Unseal(sealed::SealedError),
+
+    #[error(transparent)]
+    ReadIndex(layout::IndexError),
+
     #[error("target path '{0}' does not exist")]
     MissingTargetPath(Utf8PathBuf),
     #[error("target path '{0}' is not a directory")]
@@ -255,6 +957,12 @@ pub enum ImportError {
 
     #[error("failed to restore manifest to target\n{0}")]
     RestoreManifest(ImportFileError),
+
+    #[error("key pair check failed (use --skip-validation to import anyway)\n{0}")]
+    KeyPair(kind::ValidationError),
+
+    #[error("the import would stop at {0} conflicts, see the plan above")]
+    PlanConflicts(usize),
 }
 impl ImportError {
     fn import_file(file: &Utf8PathBuf) -> impl FnOnce(ImportFileError) -> Self {
@@ -270,17 +978,109 @@ pub fn import(
     target: String,
     paths: Vec<String>,
     source_type: SourceType,
-    skip_chown_chmod: bool,
+    options: ImportOptions,
 ) -> Result<(), ImportError> {
-    let source = {
-        let path = Utf8PathBuf::from(&source);
-        if !path.exists() {
-            return Err(ImportError::MissingSourcePath(path));
-        } else if !path.is_dir() {
-            return Err(ImportError::SourceNotDir(path));
+    // loaded even with --no-hooks, for the to-cmd= commands
+    let mut config = config::load(options.config.as_deref()).map_err(ImportError::LoadConfig)?;
+    if options.no_hooks {
+        config.hooks.clear();
+    }
+    // the hooks are listed in the plan instead
+    if options.dry_run {
+        return import_snapshot(source, target, paths, source_type, &options, &config).map(|_| ());
+    }
+    let mut env = vec![
+        ("SECS_MAN_SECRETS_DIR", target.clone()),
+        ("SECS_MAN_SOURCE", source.clone()),
+    ];
+
+    hooks::run_hook(&config, hooks::Hook::PreImport, &env).map_err(ImportError::PreHook)?;
+
+    let result = import_snapshot(source, target, paths, source_type, &options, &config);
+    let status = match &result {
+        Ok(ImportOutcome::Imported(snapshot)) => {
+            env.push(("SECS_MAN_SNAPSHOT", snapshot.to_string()));
+            if let Some(container) = snapshot.parent() {
+                env.push(("SECS_MAN_CONTAINER", container.to_string()));
+            }
+            "ok"
         }
-        path
+        Ok(ImportOutcome::Planned) => unreachable!("dry runs return before the hooks"),
+        Ok(ImportOutcome::Aborted) => "aborted",
+        Err(_) => "failed",
     };
+    hooks::run_post_hook_with_status(
+        &config,
+        hooks::Hook::PostImport,
+        env,
+        status,
+        result,
+        ImportError::PostHook,
+    )?;
+
+    Ok(())
+}
+
+pub enum ImportOutcome {
+    // the path of the snapshot imported
+    Imported(Utf8PathBuf),
+    // the user declined to import a snapshot whose manifest differs from the local one
+    Aborted,
+    // dry run, nothing was imported
+    Planned,
+}
+
+// The snapshot at source, or the newest snapshot of the container at source (the newest one with
+// the label, if given)
+fn resolve_snapshot(source: &str, label: Option<&String>) -> Result<Utf8PathBuf, ImportError> {
+    let source = Utf8PathBuf::from(source);
+    if !source.exists() {
+        return Err(ImportError::MissingSourcePath(source));
+    } else if !source.is_dir() && !snapshot::is_tar(&source) {
+        return Err(ImportError::SourceNotDir(source));
+    }
+
+    match snapshot::classify(&source) {
+        snapshot::SourceKind::Snapshot if label.is_some() => {
+            Err(ImportError::LabelNeedsContainer(source))
+        }
+        snapshot::SourceKind::Snapshot => Ok(source),
+        snapshot::SourceKind::Container if let Some(label) = label => {
+            match info::newest_labelled(&source, label).map_err(ImportError::ReadInfo)? {
+                Some(name) => Ok(source.join(name)),
+                None => Err(ImportError::NoLabelledSnapshot(source, label.clone())),
+            }
+        }
+        snapshot::SourceKind::Container => {
+            match snapshot::newest(&source).map_err(ImportError::list_snapshots(&source))? {
+                Some(name) => Ok(source.join(name)),
+                None => Err(ImportError::EmptyContainer(source)),
+            }
+        }
+        snapshot::SourceKind::Neither => Err(ImportError::NotSnapshotOrContainer(source)),
+    }
+}
+
+// Whether the dry run of an import needs the passphrase: it decrypts nothing but the sealed
+// checksums and the index of a private layout
+pub fn dry_run_needs_passphrase(source: &str, label: Option<&String>) -> Result<bool, ImportError> {
+    let snapshot = resolve_snapshot(source, label)?;
+
+    Ok(sealed::is_sealed(&snapshot) || layout::is_private(&snapshot))
+}
+
+// Imports the snapshot (or the newest snapshot of the container) at source
+fn import_snapshot(
+    source: String,
+    target: String,
+    paths: Vec<String>,
+    source_type: SourceType,
+    options: &ImportOptions,
+    config: &config::Config,
+) -> Result<ImportOutcome, ImportError> {
+    let skip_chown_chmod = options.skip_chown_chmod;
+
+    let snapshot = resolve_snapshot(&source, options.label.as_ref())?;
 
     let target = {
         let path = Utf8PathBuf::from(&target);
@@ -292,21 +1092,30 @@ pub fn import(
         path
     };
 
-    let source = match snapshot::classify(&source) {
-        snapshot::SourceKind::Snapshot => source,
-        snapshot::SourceKind::Container => {
-            match snapshot::newest(&source).map_err(ImportError::list_snapshots(&source))? {
-                Some(name) => {
-                    println!("Using snapshot {name}");
-                    println!();
-                    source.join(name)
-                }
-                None => return Err(ImportError::EmptyContainer(source)),
-            }
+    let owner_map = {
+        let mut map = OwnerMap::default();
+        if let Some(path) = &options.owner_map {
+            map.add_file(&Utf8PathBuf::from(path))
+                .map_err(ImportError::OwnerMap)?;
+        }
+        for rule in &options.map_owner {
+            map.add_rule(rule).map_err(ImportError::OwnerMap)?;
         }
-        snapshot::SourceKind::Neither => return Err(ImportError::NotSnapshotOrContainer(source)),
+        map
     };
 
+    if snapshot.as_str() != source {
+        let name = snapshot.file_name().unwrap_or(snapshot.as_str());
+        match &options.label {
+            Some(label) => println!("Using snapshot {name}, labelled '{label}'"),
+            None => println!("Using snapshot {name}"),
+        }
+        println!();
+    }
+    // archives are extracted for the time of the import
+    let opened = snapshot::open(&snapshot).map_err(ImportError::OpenSnapshot)?;
+    let source = opened.dir.clone();
+
     if matches!(source_type, SourceType::Encrypted { .. }) {
         print!("Verifying source integrity... ");
         std::io::stdout().flush().unwrap();
@@ -317,7 +1126,33 @@ pub fn import(
         println!();
     }
 
-    let available = manifest::load(&source).map_err(ImportError::LoadManifest)?;
+    // the plaintext of the secrets of a sealed snapshot is checked against its decrypted checksums
+    let sealed = match &source_type {
+        SourceType::Encrypted { passphrase } if sealed::is_sealed(&source) => {
+            print!("Unsealing checksums... ");
+            std::io::stdout().flush().unwrap();
+            let sealed = sealed::read(&source, passphrase)
+                .map_err(ImportError::Unseal)
+                .inspect_err(|_| println!("error"))?;
+            println!("ok");
+            println!();
+            sealed
+        }
+        _ => None,
+    };
+
+    // the manifest of a private layout is only readable with the passphrase
+    let index = match &source_type {
+        SourceType::Encrypted { passphrase } if layout::is_private(&source) => {
+            Some(layout::read_index(&source, passphrase).map_err(ImportError::ReadIndex)?)
+        }
+        _ => None,
+    };
+    let available = match &index {
+        Some(index) => manifest::parse(&source.join(layout::INDEX_FILENAME), index),
+        None => manifest::load_snapshot(&source),
+    }
+    .map_err(ImportError::LoadManifest)?;
 
     let is_full = paths.is_empty();
     let secrets: Vec<manifest::Secret> = if is_full {
@@ -369,9 +1204,11 @@ pub fn import(
                 }
                 println!();
 
-                if !confirm("Proceed with the import?").map_err(ImportError::Confirm)? {
+                if !options.dry_run
+                    && !prompt::confirm("Proceed with the import?").map_err(ImportError::Confirm)?
+                {
                     println!("Import aborted.");
-                    return Ok(());
+                    return Ok(ImportOutcome::Aborted);
                 }
                 println!();
             }
@@ -391,28 +1228,179 @@ pub fn import(
         }
     }
 
+    // checked before anything is written, rather than once the secrets needing the command are
+    for secret in &secrets {
+        if let Some(name) = &secret.to_cmd
+            && config.command(name).is_none()
+        {
+            return Err(ImportError::UnknownToCommand(
+                secret.path.clone(),
+                name.clone(),
+            ));
+        }
+    }
+    if !options.no_hooks {
+        for secret in &secrets {
+            if let Some(name) = secret
+                .on_import
+                .iter()
+                .find(|name| config.command(name).is_none())
+            {
+                return Err(ImportError::UnknownCommand(
+                    secret.path.clone(),
+                    name.clone(),
+                ));
+            }
+        }
+    }
+
+    // names are only needed to resolve owners
+    let has_owners = secrets
+        .iter()
+        .any(|s| s.owner.is_some() || s.captured.owner.is_some());
+    let db = if !skip_chown_chmod && has_owners {
+        users::Database::load().map_err(ImportError::LoadUsers)?
+    } else {
+        users::Database::default()
+    };
+
+    let import_source = Source {
+        dir: &source,
+        source_type: &source_type,
+        sealed,
+    };
+    let restore = is_full && !local_manifest_path.exists();
+    if options.dry_run {
+        let secrets: Vec<_> = secrets
+            .iter()
+            .map(|secret| resolve_owner(secret, &db, &owner_map))
+            .collect();
+        plan_import(
+            &secrets,
+            &import_source,
+            &target,
+            options,
+            &db,
+            config,
+            restore,
+        )?;
+        return Ok(ImportOutcome::Planned);
+    }
+    check_key_pairs(&secrets, &import_source, options.skip_validation)?;
+
+    let mut triggered = Vec::new();
     println!("Importing secrets... ");
     for secret in &secrets {
+        let secret = &resolve_owner(secret, &db, &owner_map);
         let file = &secret.path;
         print!("importing '{file}'... ");
         std::io::stdout().flush().unwrap();
 
-        import_file(secret, &source, &target, &source_type, skip_chown_chmod)
+        if secret.kind == Some(kind::Kind::Symlink) {
+            let written = import_symlink(
+                secret,
+                &source,
+                &target,
+                &source_type,
+                skip_chown_chmod,
+                &db,
+            )
             .map_err(ImportError::import_file(file))
             .inspect_err(|_| println!("error"))?;
-        println!("ok");
+            match effective_owner(secret, skip_chown_chmod) {
+                Some(owner) => println!("ok (symlink, owner {owner})"),
+                None => println!("ok (symlink)"),
+            }
+            record_triggers(&mut triggered, secret, written);
+            continue;
+        }
+
+        let written = import_file(secret, &import_source, &target, options, &db, config)
+            .map_err(ImportError::import_file(file))
+            .inspect_err(|_| println!("error"))?;
+        match (&secret.to_cmd, effective_owner(secret, skip_chown_chmod)) {
+            (Some(name), _) => println!("ok (piped to '{name}')"),
+            (None, Some(owner)) => println!("ok (owner {owner})"),
+            (None, None) => println!("ok"),
+        }
+        if options.deploy {
+            for location in &secret.at {
+                println!("  deployed to '{location}'");
+            }
+        }
+        record_triggers(&mut triggered, secret, written);
     }
     println!();
 
-    if is_full && !local_manifest_path.exists() {
+    if restore {
         print!("restoring manifest... ");
         std::io::stdout().flush().unwrap();
-        restore_manifest(&source, &target)
+        restore_manifest(&source, index.as_deref(), &target)
             .map_err(ImportError::RestoreManifest)
             .inspect_err(|_| println!("error"))?;
         println!("ok");
         println!();
     }
 
-    Ok(())
+    run_hooks(config, &triggered, options)?;
+
+    Ok(ImportOutcome::Imported(snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TempDir};
+
+    const FILES: &[(&str, &str)] = &[("a", "first\n"), ("dir/b", "second\n")];
+
+    fn assert_restored(dir: &Utf8PathBuf) {
+        for (path, content) in FILES {
+            assert_eq!(&fs::read_to_string(dir.join(path)).unwrap(), content);
+        }
+    }
+
+    // The manifest restored from a snapshot with a private layout must not carry its random names
+    // (nor the rest of its captured metadata) into the next export, which would store the secrets
+    // under them without an index to find them
+    #[test]
+    fn private_layout_restore_then_plain_export() {
+        let tmp = TempDir::new();
+        let source = tmp.secrets_dir("source", FILES);
+
+        let mut options = tmp.export_options();
+        options.private_layout = true;
+        options.seal_checksums = true;
+        options.capture_metadata = true;
+        test_support::export(&source, &tmp.dir("private"), options);
+
+        let restored = tmp.dir("restored");
+        import(
+            tmp.path().join("private").to_string(),
+            restored.to_string(),
+            Vec::new(),
+            test_support::encrypted(),
+            tmp.import_options(),
+        )
+        .unwrap();
+        assert_restored(&restored);
+        let manifest = fs::read_to_string(restored.join(manifest::MANIFEST_FILENAME)).unwrap();
+        assert!(!manifest.contains("captured-"));
+
+        let snapshot = test_support::export(&restored, &tmp.dir("plain"), tmp.export_options());
+        assert!(snapshot.join("a.age").is_file());
+        let manifest = fs::read_to_string(snapshot.join(manifest::MANIFEST_FILENAME)).unwrap();
+        assert!(!manifest.contains("captured-stored="));
+
+        let reimported = tmp.dir("reimported");
+        import(
+            snapshot.to_string(),
+            reimported.to_string(),
+            Vec::new(),
+            test_support::encrypted(),
+            tmp.import_options(),
+        )
+        .unwrap();
+        assert_restored(&reimported);
+    }
 }
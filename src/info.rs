@@ -0,0 +1,134 @@
+use std::io;
+
+use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::snapshot;
+use crate::users;
+
+// Plaintext metadata of a snapshot, covered by its sha256sums.txt, telling snapshots apart without
+// the passphrase. Snapshots written before it existed have none
+pub const INFO_FILENAME: &str = "snapshot.json";
+
+// Version of the layout of the snapshots, for a secs-man reading a snapshot written by another one
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum InfoError {
+    #[error("failed to read the metadata of snapshot '{0}'\n{1}")]
+    Read(Utf8PathBuf, io::Error),
+
+    #[error("failed to parse the metadata of snapshot '{0}'\n{1}")]
+    Parse(Utf8PathBuf, serde_json::Error),
+
+    #[error("failed to list snapshots in container '{0}'\n{1}")]
+    ListSnapshots(Utf8PathBuf, io::Error),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Info {
+    pub format_version: u32,
+    pub label: Option<String>,
+    pub note: Option<String>,
+    pub host: Option<String>,
+    // the user who ran the export, behind sudo if it was run through it
+    pub user: Option<String>,
+    pub secs_man_version: String,
+    pub secret_count: usize,
+    // plaintext size of the secrets, symlinks excluded
+    pub total_size: u64,
+    pub encryption: Encryption,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Encryption {
+    pub scheme: String,
+    pub sealed_checksums: bool,
+    pub private_layout: bool,
+}
+
+fn hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return None;
+    }
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+
+    String::from_utf8(buffer[..end].to_vec()).ok()
+}
+
+fn user() -> Option<String> {
+    if let Ok(user) = std::env::var("SUDO_USER") {
+        return Some(user);
+    }
+    let euid = unsafe { libc::geteuid() };
+    let db = users::Database::load().ok()?;
+
+    db.user_name(euid).map(str::to_string)
+}
+
+impl Info {
+    // Metadata of a snapshot being exported on this host
+    pub fn new(
+        label: Option<String>,
+        note: Option<String>,
+        secret_count: usize,
+        total_size: u64,
+        encryption: Encryption,
+    ) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            label,
+            note,
+            host: hostname(),
+            user: user(),
+            secs_man_version: env!("CARGO_PKG_VERSION").to_string(),
+            secret_count,
+            total_size,
+            encryption,
+        }
+    }
+}
+
+// Reads the metadata of a snapshot, directory or archive. Snapshots written before snapshot.json
+// existed read as None
+pub fn read(snapshot: &Utf8PathBuf) -> Result<Option<Info>, InfoError> {
+    let content = match snapshot::read_file(snapshot, &Utf8PathBuf::from(INFO_FILENAME)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(InfoError::Read(snapshot.clone(), e)),
+    };
+
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|e| InfoError::Parse(snapshot.clone(), e))
+}
+
+// Newest snapshot of a container labelled with the given label. A snapshot whose metadata can't be
+// read is skipped with a warning rather than making every other snapshot unreachable
+pub fn newest_labelled(
+    container: &Utf8PathBuf,
+    label: &str,
+) -> Result<Option<Utf8PathBuf>, InfoError> {
+    let mut snapshots = snapshot::list_snapshots(container)
+        .map_err(|e| InfoError::ListSnapshots(container.clone(), e))?;
+    snapshots.sort();
+
+    for name in snapshots.into_iter().rev() {
+        let info = match read(&container.join(&name)) {
+            Ok(info) => info,
+            Err(e) => {
+                println!("Warning: skipping snapshot '{name}'\n{e}");
+                println!();
+                continue;
+            }
+        };
+        if info.is_some_and(|info| info.label.as_deref() == Some(label)) {
+            return Ok(Some(name));
+        }
+    }
+
+    Ok(None)
+}
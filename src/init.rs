@@ -0,0 +1,248 @@
+use std::{
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+};
+
+use camino::Utf8PathBuf;
+use regex::Regex;
+use thiserror::Error;
+
+use crate::checksum;
+use crate::export;
+use crate::manifest;
+use crate::prompt;
+use crate::safe_fs;
+use crate::users;
+use crate::utf8path_ext::ExtraUtf8Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Preset {
+    Ssh,
+    Wireguard,
+    Gnupg,
+}
+impl Preset {
+    // Patterns (matched against the path relative to the secrets directory) of the files usually
+    // worth backing up for each preset, with the mode they are expected to have
+    fn rules(&self) -> &'static [(&'static str, u32)] {
+        match self {
+            Preset::Ssh => &[
+                (r"(^|/)id_[^/.]+$", 0o600),
+                (r"(^|/)id_[^/]+\.pub$", 0o644),
+                (r"(^|/)ssh_host_[^/]+_key$", 0o600),
+                (r"(^|/)ssh_host_[^/]+_key\.pub$", 0o644),
+                (r"(^|/)authorized_keys$", 0o600),
+                (r"(^|/)known_hosts$", 0o644),
+                (r"(^|/)\.?ssh/config$", 0o600),
+            ],
+            Preset::Wireguard => &[
+                // other tools keep their .conf files next to wireguard's, in /etc
+                (r"(^|/)(wireguard/[^/]+|wg[^/]*)\.conf$", 0o600),
+                (r"(^|/)[^/]+\.(private|key|psk)$", 0o600),
+                (r"(^|/)(private|preshared)key$", 0o600),
+                (r"(^|/)[^/]+\.(public|pub)$", 0o644),
+                (r"(^|/)publickey$", 0o644),
+            ],
+            Preset::Gnupg => &[
+                (r"(^|/)private-keys-v1\.d/[^/]+\.key$", 0o600),
+                (r"(^|/)openpgp-revocs\.d/[^/]+\.rev$", 0o600),
+                (r"(^|/)pubring\.kbx$", 0o600),
+                (r"(^|/)trustdb\.gpg$", 0o600),
+                (r"(^|/)gpg(-agent)?\.conf$", 0o600),
+            ],
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum InitError {
+    #[error("secrets path '{0}' does not exist")]
+    MissingSourcePath(Utf8PathBuf),
+    #[error("secrets path '{0}' is not a directory")]
+    SourceNotDir(Utf8PathBuf),
+
+    #[error("a manifest already exists at '{0}', edit it with `secs-man manifest` instead")]
+    ManifestExists(Utf8PathBuf),
+
+    #[error("failed to scan secrets directory\n{0}")]
+    ScanSource(std::io::Error),
+
+    #[error("failed to read metadata of '{0}'\n{1}")]
+    ReadMetadata(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to read the users and groups of this host\n{0}")]
+    LoadUsers(std::io::Error),
+
+    #[error("failed to read confirmation from stdin\n{0}")]
+    Confirm(std::io::Error),
+
+    #[error("refusing to write manifest, the generated manifest would be invalid\n{0}")]
+    ValidateManifest(manifest::ManifestError),
+
+    #[error("failed to write manifest at '{0}'\n{1}")]
+    WriteManifest(Utf8PathBuf, safe_fs::SafeFsError),
+
+    #[error("failed to generate checksum of '{0}'\n{1}")]
+    GenerateChecksum(Utf8PathBuf, checksum::ChecksumError),
+}
+impl InitError {
+    fn read_metadata(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::ReadMetadata(path.clone(), e)
+    }
+
+    fn write_manifest(path: &Utf8PathBuf) -> impl Fn(safe_fs::SafeFsError) -> Self {
+        |e| Self::WriteManifest(path.clone(), e)
+    }
+
+    fn generate_checksum(path: &Utf8PathBuf) -> impl Fn(checksum::ChecksumError) -> Self {
+        |e| Self::GenerateChecksum(path.clone(), e)
+    }
+}
+
+struct Proposal {
+    path: Utf8PathBuf,
+    owner: String,
+    current_mode: u32,
+    mode: u32,
+    symlink: bool,
+    selected: bool,
+}
+impl Proposal {
+    fn entry(&self) -> String {
+        if self.symlink {
+            return format!("{} kind=symlink", self.path);
+        }
+        format!("{} owner={} mode={:04o}", self.path, self.owner, self.mode)
+    }
+}
+
+const HEADER: &str = "\
+# Generated by `secs-man init`.
+#
+# One secret per line, given as a path relative to the secrets directory,
+# optionally followed by annotations (see .secrets-manifest.example):
+#
+#   <path> [owner=<user[:group]>] [mode=<octal>] [kind=<kind>]
+#
+# The owner and mode of each entry were taken from the file as found on disk
+# (or from the preset it matched). Symlinks are listed as kind=symlink, so that
+# the link is backed up rather than a copy of its target.";
+
+const UNSELECTED_HEADER: &str = "\
+# Files that did not match any preset, and symlinks pointing outside of the
+# secrets directory (which can't be backed up). Uncomment the ones that should
+# be backed up as well (their .sha256 sidecars are generated at the next
+# export), or adopt them with `secs-man manifest add --unlisted`.";
+
+fn render(proposals: &[Proposal]) -> String {
+    let mut lines = vec![HEADER.to_string(), String::new()];
+
+    for proposal in proposals.iter().filter(|p| p.selected) {
+        if proposal.mode != proposal.current_mode && !proposal.symlink {
+            lines.push(format!(
+                "# currently {:04o} on disk, {:04o} suggested by preset",
+                proposal.current_mode, proposal.mode
+            ));
+        }
+        lines.push(proposal.entry());
+    }
+
+    if proposals.iter().any(|p| !p.selected) {
+        lines.push(String::new());
+        lines.push(UNSELECTED_HEADER.to_string());
+        for proposal in proposals.iter().filter(|p| !p.selected) {
+            lines.push(format!("# {}", proposal.entry()));
+        }
+    }
+
+    lines.join("\n") + "\n"
+}
+
+pub fn init(source: String, presets: Vec<Preset>, yes: bool) -> Result<(), InitError> {
+    let source = {
+        let path = Utf8PathBuf::from(&source);
+        if !path.exists() {
+            return Err(InitError::MissingSourcePath(path));
+        } else if !path.is_dir() {
+            return Err(InitError::SourceNotDir(path));
+        }
+        path
+    };
+
+    let manifest_path = source.join(manifest::MANIFEST_FILENAME);
+    if manifest_path.exists() {
+        return Err(InitError::ManifestExists(manifest_path));
+    }
+
+    let db = users::Database::load().map_err(InitError::LoadUsers)?;
+    let rules: Vec<(Regex, u32)> = presets
+        .iter()
+        .flat_map(|p| p.rules())
+        .map(|(pattern, mode)| (Regex::new(pattern).unwrap(), *mode))
+        .collect();
+
+    let files = export::unlisted_files(&source, &[]).map_err(InitError::ScanSource)?;
+    let mut proposals = Vec::new();
+    for path in files {
+        let file = source.join(&path);
+        let metadata = fs::symlink_metadata(&file).map_err(InitError::read_metadata(&file))?;
+        let current_mode = metadata.permissions().mode() & 0o7777;
+
+        let matched = rules
+            .iter()
+            .find(|(re, _)| re.is_match(path.as_str()))
+            .map(|(_, mode)| *mode);
+
+        // links escaping the secrets directory can't be exported, so they are only proposed
+        // commented out
+        let escaping_link = metadata.is_symlink()
+            && file
+                .read_link_utf8()
+                .map_err(InitError::read_metadata(&file))
+                .map(|destination| {
+                    manifest::check_resolved_link_target(&source, &path, &destination).is_err()
+                })?;
+
+        proposals.push(Proposal {
+            owner: db.owner_spec(metadata.uid(), metadata.gid()),
+            current_mode,
+            mode: matched.unwrap_or(current_mode),
+            symlink: metadata.is_symlink(),
+            selected: (presets.is_empty() || matched.is_some()) && !escaping_link,
+            path,
+        });
+    }
+
+    let content = render(&proposals);
+    manifest::parse(&manifest_path, &content).map_err(InitError::ValidateManifest)?;
+
+    println!("Proposed manifest for '{source}':");
+    println!();
+    print!("{content}");
+    println!();
+
+    if !yes && !prompt::confirm("Write this manifest?").map_err(InitError::Confirm)? {
+        println!("Init aborted.");
+        return Ok(());
+    }
+    println!();
+
+    // born 0600, as there is no manifest yet
+    safe_fs::safe_write(&manifest_path, content)
+        .map_err(InitError::write_manifest(&manifest_path))?;
+
+    println!("Generating checksums... ");
+    for proposal in proposals.iter().filter(|p| p.selected && !p.symlink) {
+        let file = source.join(&proposal.path);
+        if file.add_extension("sha256").exists() {
+            continue;
+        }
+        checksum::generate_file_checksum(&file).map_err(InitError::generate_checksum(&file))?;
+        println!("generated '{}.sha256'", proposal.path);
+    }
+    println!();
+
+    println!("Manifest written to '{manifest_path}'");
+
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use std::{fmt, str::FromStr};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use crate::manifest;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    SshPrivateKey,
+    SshPublicKey,
+    WireguardKey,
+    PemCert,
+    AgeIdentity,
+    Symlink,
+    Raw,
+}
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::SshPrivateKey => "ssh-private-key",
+            Kind::SshPublicKey => "ssh-public-key",
+            Kind::WireguardKey => "wireguard-key",
+            Kind::PemCert => "pem-cert",
+            Kind::AgeIdentity => "age-identity",
+            Kind::Symlink => "symlink",
+            Kind::Raw => "raw",
+        }
+    }
+}
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "'{0}' is not a valid kind (expected ssh-private-key, ssh-public-key, wireguard-key, pem-cert, age-identity, symlink or raw)"
+)]
+pub struct InvalidKind(String);
+
+impl FromStr for Kind {
+    type Err = InvalidKind;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "ssh-private-key" => Ok(Kind::SshPrivateKey),
+            "ssh-public-key" => Ok(Kind::SshPublicKey),
+            "wireguard-key" => Ok(Kind::WireguardKey),
+            "pem-cert" => Ok(Kind::PemCert),
+            "age-identity" => Ok(Kind::AgeIdentity),
+            "symlink" => Ok(Kind::Symlink),
+            "raw" => Ok(Kind::Raw),
+            _ => Err(InvalidKind(kind.to_string())),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ValidationError {
+    #[error("content is not valid utf8 text")]
+    NotText,
+
+    #[error("content is not a valid OpenSSH private key: {0}")]
+    SshPrivateKey(&'static str),
+
+    #[error("content is not a valid OpenSSH public key: {0}")]
+    SshPublicKey(&'static str),
+
+    #[error("content is not a valid WireGuard key: {0}")]
+    WireguardKey(&'static str),
+
+    #[error("content is not a valid PEM certificate: {0}")]
+    PemCert(&'static str),
+
+    #[error("content is not a valid age identity file: {0}")]
+    AgeIdentity(String),
+
+    #[error("public key '{0}' does not match private key '{1}'")]
+    KeyPairMismatch(Utf8PathBuf, Utf8PathBuf),
+}
+
+const OPENSSH_MAGIC: &[u8] = b"openssh-key-v1\0";
+
+// The legacy PEM encodings that OpenSSH still accepts for private keys. Their public key can't be
+// extracted without implementing each algorithm, so they are only checked to be well-formed
+const LEGACY_PRIVATE_KEY_LABELS: &[&str] = &[
+    "RSA PRIVATE KEY",
+    "EC PRIVATE KEY",
+    "DSA PRIVATE KEY",
+    "PRIVATE KEY",
+    "ENCRYPTED PRIVATE KEY",
+];
+
+// Extracts the base64-decoded bodies of every `-----BEGIN <label>-----` block in a PEM text.
+// Returns None if a block is unterminated or its body is not valid base64
+pub fn pem_blocks(text: &str, label: &str) -> Option<Vec<Vec<u8>>> {
+    let begin = format!("-----BEGIN {label}-----");
+    let end = format!("-----END {label}-----");
+
+    let mut blocks = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(&begin) {
+        let after_begin = &rest[start + begin.len()..];
+        let stop = after_begin.find(&end)?;
+        let body: String = after_begin[..stop]
+            .lines()
+            // encrypted legacy keys carry `Proc-Type:`/`DEK-Info:` headers before the body
+            .filter(|line| !line.contains(':'))
+            .flat_map(|line| line.split_whitespace())
+            .collect();
+        blocks.push(BASE64.decode(body).ok()?);
+        rest = &after_begin[stop + end.len()..];
+    }
+
+    Some(blocks)
+}
+
+// Minimal reader for the length-prefixed fields of the SSH wire format (RFC 4251)
+struct SshReader<'a>(&'a [u8]);
+impl<'a> SshReader<'a> {
+    fn u32(&mut self) -> Option<u32> {
+        let (head, tail) = self.0.split_first_chunk::<4>()?;
+        self.0 = tail;
+        Some(u32::from_be_bytes(*head))
+    }
+
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+}
+
+// Parses an OpenSSH private key and returns the blob of its (first) public key, or None for the
+// legacy PEM encodings
+fn parse_ssh_private_key(text: &str) -> Result<Option<Vec<u8>>, ValidationError> {
+    let invalid = ValidationError::SshPrivateKey;
+
+    let blocks = pem_blocks(text, "OPENSSH PRIVATE KEY").ok_or(invalid("malformed PEM block"))?;
+    let data = match blocks.as_slice() {
+        [data] => data,
+        [] => {
+            return LEGACY_PRIVATE_KEY_LABELS
+                .iter()
+                .find_map(|label| pem_blocks(text, label).filter(|b| b.len() == 1))
+                .map(|_| None)
+                .ok_or(invalid("no private key block found"));
+        }
+        _ => return Err(invalid("more than one private key block")),
+    };
+
+    let data = data
+        .strip_prefix(OPENSSH_MAGIC)
+        .ok_or(invalid("missing openssh-key-v1 header"))?;
+    let mut reader = SshReader(data);
+    let _cipher = reader.string().ok_or(invalid("truncated cipher name"))?;
+    let _kdf = reader.string().ok_or(invalid("truncated kdf name"))?;
+    let _kdf_options = reader.string().ok_or(invalid("truncated kdf options"))?;
+    let count = reader.u32().ok_or(invalid("truncated key count"))?;
+    if count == 0 {
+        return Err(invalid("holds no keys"));
+    }
+
+    let mut public_keys = Vec::new();
+    for _ in 0..count {
+        public_keys.push(reader.string().ok_or(invalid("truncated public key"))?);
+    }
+    let private_section = reader
+        .string()
+        .ok_or(invalid("truncated private section"))?;
+    if private_section.is_empty() || !reader.0.is_empty() {
+        return Err(invalid("malformed private section"));
+    }
+
+    Ok(Some(public_keys[0].to_vec()))
+}
+
+// Parses a single-line OpenSSH public key (`<type> <base64> [comment]`) and returns its blob
+fn parse_ssh_public_key(text: &str) -> Result<Vec<u8>, ValidationError> {
+    let invalid = ValidationError::SshPublicKey;
+
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let line = lines.next().ok_or(invalid("empty"))?;
+    if lines.next().is_some() {
+        return Err(invalid("more than one line"));
+    }
+
+    let mut fields = line.split_whitespace();
+    let key_type = fields.next().ok_or(invalid("missing key type"))?;
+    let encoded = fields.next().ok_or(invalid("missing key data"))?;
+    let blob = BASE64
+        .decode(encoded)
+        .map_err(|_| invalid("key data is not valid base64"))?;
+
+    let embedded_type = SshReader(&blob)
+        .string()
+        .ok_or(invalid("truncated key data"))?;
+    if embedded_type != key_type.as_bytes() {
+        return Err(invalid("key type does not match key data"));
+    }
+
+    Ok(blob)
+}
+
+fn validate_wireguard_key(text: &str) -> Result<(), ValidationError> {
+    let invalid = ValidationError::WireguardKey;
+
+    let key = BASE64
+        .decode(text.trim())
+        .map_err(|_| invalid("not valid base64"))?;
+    if key.len() != 32 {
+        return Err(invalid("does not decode to 32 bytes"));
+    }
+
+    Ok(())
+}
+
+fn validate_pem_cert(text: &str) -> Result<(), ValidationError> {
+    let invalid = ValidationError::PemCert;
+
+    let blocks = pem_blocks(text, "CERTIFICATE").ok_or(invalid("malformed PEM block"))?;
+    if blocks.is_empty() {
+        return Err(invalid("no certificate block found"));
+    }
+    // every DER certificate is a SEQUENCE
+    if blocks.iter().any(|der| der.first() != Some(&0x30)) {
+        return Err(invalid("certificate block is not DER encoded"));
+    }
+
+    Ok(())
+}
+
+fn validate_age_identity(text: &str) -> Result<(), ValidationError> {
+    let mut count = 0;
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !line.starts_with("AGE-PLUGIN-") {
+            line.parse::<age::x25519::Identity>()
+                .map_err(|e| ValidationError::AgeIdentity(e.to_string()))?;
+        }
+        count += 1;
+    }
+    if count == 0 {
+        return Err(ValidationError::AgeIdentity(
+            "no identity found".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn validate(kind: Kind, content: &[u8]) -> Result<(), ValidationError> {
+    // the target of a symlink is checked against the secrets directory instead, see
+    // `manifest::check_link_target`
+    if kind == Kind::Raw || kind == Kind::Symlink {
+        return Ok(());
+    }
+    let text = std::str::from_utf8(content).map_err(|_| ValidationError::NotText)?;
+
+    match kind {
+        Kind::SshPrivateKey => parse_ssh_private_key(text).map(|_| ()),
+        Kind::SshPublicKey => parse_ssh_public_key(text).map(|_| ()),
+        Kind::WireguardKey => validate_wireguard_key(text),
+        Kind::PemCert => validate_pem_cert(text),
+        Kind::AgeIdentity => validate_age_identity(text),
+        Kind::Symlink | Kind::Raw => Ok(()),
+    }
+}
+
+// The ssh key pairs listed in a manifest, as (private, public): a `X.pub` of kind ssh-public-key next
+// to an `X` of kind ssh-private-key
+pub fn ssh_pairs(secrets: &[manifest::Secret]) -> Vec<(&manifest::Secret, &manifest::Secret)> {
+    let mut pairs = Vec::new();
+    for public in secrets {
+        if public.kind != Some(Kind::SshPublicKey) || public.path.extension() != Some("pub") {
+            continue;
+        }
+        let private_path = public.path.with_extension("");
+        if let Some(private) = secrets
+            .iter()
+            .find(|s| s.path == private_path && s.kind == Some(Kind::SshPrivateKey))
+        {
+            pairs.push((private, public));
+        }
+    }
+
+    pairs
+}
+
+// Checks that an OpenSSH public key is the one embedded in the given private key. Keys in the
+// legacy PEM encodings are accepted as they are, since their public key can't be extracted
+pub fn check_ssh_pair(
+    private_path: &Utf8PathBuf,
+    private: &[u8],
+    public_path: &Utf8PathBuf,
+    public: &[u8],
+) -> Result<(), ValidationError> {
+    let private = std::str::from_utf8(private).map_err(|_| ValidationError::NotText)?;
+    let public = std::str::from_utf8(public).map_err(|_| ValidationError::NotText)?;
+
+    let Some(embedded) = parse_ssh_private_key(private)? else {
+        return Ok(());
+    };
+    if parse_ssh_public_key(public)? != embedded {
+        return Err(ValidationError::KeyPairMismatch(
+            public_path.clone(),
+            private_path.clone(),
+        ));
+    }
+
+    Ok(())
+}
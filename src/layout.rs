@@ -0,0 +1,46 @@
+use std::{fs::File, io, io::Read};
+
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use crate::crypto;
+use crate::snapshot;
+
+// Encrypted manifest of a snapshot exported with --private-layout, which holds no plaintext
+// manifest and stores its secrets under random names. The manifest records the name of each
+// secret as a `captured-stored=` annotation
+pub const INDEX_FILENAME: &str = "index.age";
+
+#[derive(Error, Debug)]
+pub enum IndexError {
+    #[error("failed to read the index of snapshot '{0}'\n{1}")]
+    Read(Utf8PathBuf, io::Error),
+
+    #[error("failed to decrypt the index of snapshot '{0}'\n{1}")]
+    Decrypt(Utf8PathBuf, age::DecryptError),
+
+    #[error("the index of snapshot '{0}' is not valid utf8")]
+    NotUtf8(Utf8PathBuf),
+}
+
+pub fn is_private(snapshot: &Utf8PathBuf) -> bool {
+    snapshot::read_file(snapshot, &Utf8PathBuf::from(INDEX_FILENAME)).is_ok()
+}
+
+// A name that tells nothing about the secret stored under it
+pub fn random_name() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+// Decrypts the manifest of a snapshot with a private layout, directory or archive
+pub fn read_index(snapshot: &Utf8PathBuf, passphrase: &str) -> Result<String, IndexError> {
+    let encrypted = snapshot::read_file(snapshot, &Utf8PathBuf::from(INDEX_FILENAME))
+        .map_err(|e| IndexError::Read(snapshot.clone(), e))?;
+    let content = crypto::decrypt(encrypted, passphrase)
+        .map_err(|e| IndexError::Decrypt(snapshot.clone(), e))?;
+
+    String::from_utf8(content).map_err(|_| IndexError::NotUtf8(snapshot.clone()))
+}
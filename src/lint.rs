@@ -0,0 +1,381 @@
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::cert;
+use crate::checksum;
+use crate::export;
+use crate::info;
+use crate::kind;
+use crate::layout;
+use crate::manifest;
+use crate::objects;
+use crate::recovery;
+use crate::sealed;
+use crate::users;
+use crate::utf8path_ext::ExtraUtf8Path;
+
+const DEFAULT_MODE: u32 = 0o600;
+
+// Names that sit next to the secrets inside a snapshot, or inside its container
+const RESERVED_NAMES: &[&str] = &[
+    manifest::MANIFEST_FILENAME,
+    "sha256sums.txt",
+    cert::EXPIRY_FILENAME,
+    sealed::SEALED_FILENAME,
+    layout::INDEX_FILENAME,
+    info::INFO_FILENAME,
+    recovery::README_FILENAME,
+    recovery::SCRIPT_FILENAME,
+    recovery::ENCRYPTED_SCRIPT_FILENAME,
+    objects::OBJECTS_DIRNAME,
+];
+
+#[derive(Error, Debug)]
+pub enum LintError {
+    #[error("secrets path '{0}' does not exist")]
+    MissingSourcePath(Utf8PathBuf),
+    #[error("secrets path '{0}' is not a directory")]
+    SourceNotDir(Utf8PathBuf),
+
+    #[error("failed to load manifest\n{0}")]
+    LoadManifest(manifest::ManifestError),
+
+    #[error("failed to scan secrets directory\n{0}")]
+    ScanSource(std::io::Error),
+
+    #[error("failed to read the users and groups of this host\n{0}")]
+    LoadUsers(std::io::Error),
+
+    #[error("failed to serialize report to json\n{0}")]
+    Serialize(serde_json::Error),
+
+    #[error("lint found {errors} errors and {warnings} warnings")]
+    Failed { errors: usize, warnings: usize },
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize)]
+struct Finding {
+    severity: Severity,
+    check: &'static str,
+    path: Utf8PathBuf,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct Report {
+    errors: usize,
+    warnings: usize,
+    findings: Vec<Finding>,
+}
+
+struct Linter {
+    findings: Vec<Finding>,
+}
+impl Linter {
+    fn error(&mut self, check: &'static str, path: &Utf8Path, message: String) {
+        self.findings.push(Finding {
+            severity: Severity::Error,
+            check,
+            path: path.to_path_buf(),
+            message,
+        });
+    }
+
+    fn warning(&mut self, check: &'static str, path: &Utf8Path, message: String) {
+        self.findings.push(Finding {
+            severity: Severity::Warning,
+            check,
+            path: path.to_path_buf(),
+            message,
+        });
+    }
+}
+
+fn check_symlink(linter: &mut Linter, source: &Utf8PathBuf, secret: &manifest::Secret) {
+    let path = &secret.path;
+    let file = source.join(path);
+    if !file.is_symlink() {
+        linter.error(
+            "missing-file",
+            path,
+            "listed path is not a symlink, but is annotated kind=symlink".to_string(),
+        );
+        return;
+    }
+
+    match file.read_link_utf8() {
+        Ok(destination) => {
+            if let Err(e) = manifest::check_resolved_link_target(source, path, &destination) {
+                linter.error("link-escape", path, e.to_string());
+            } else if !file.exists() {
+                linter.warning(
+                    "dangling-link",
+                    path,
+                    format!("link target '{destination}' does not exist"),
+                );
+            }
+        }
+        Err(e) => linter.error("missing-file", path, e.to_string()),
+    }
+}
+
+fn check_file(linter: &mut Linter, source: &Utf8PathBuf, secret: &manifest::Secret) {
+    let path = &secret.path;
+    let file = secret.location(source);
+    if secret.kind == Some(kind::Kind::Symlink) {
+        check_symlink(linter, source, secret);
+        return;
+    }
+    // read from a command at export, there is no file to check
+    if secret.from_cmd.is_some() {
+        return;
+    }
+    if file.is_symlink() {
+        linter.warning(
+            "followed-symlink",
+            path,
+            "listed path is a symlink, its target gets backed up as a copy (annotate it kind=symlink to keep the link)".to_string(),
+        );
+    }
+    let Ok(metadata) = fs::metadata(&file) else {
+        linter.error(
+            "missing-file",
+            path,
+            "listed file does not exist".to_string(),
+        );
+        return;
+    };
+    if !metadata.is_file() {
+        linter.error(
+            "missing-file",
+            path,
+            "listed path is not a regular file".to_string(),
+        );
+        return;
+    }
+
+    let sidecar = secret.sidecar(source);
+    if !sidecar.exists() {
+        linter.warning(
+            "missing-sidecar",
+            path,
+            "no .sha256 sidecar, export will generate it".to_string(),
+        );
+    } else if let Err(e) = checksum::verify_checksum_at(&file, &sidecar) {
+        linter.error("sidecar-mismatch", path, e.to_string());
+    } else {
+        // only the first location gets exported, the others are expected to be copies of it
+        for location in secret.at.iter().skip(1) {
+            if checksum::verify_checksum_at(location, &sidecar).is_err() {
+                linter.warning(
+                    "diverged-location",
+                    path,
+                    format!(
+                        "'{location}' differs from '{file}' (or is missing), which gets exported"
+                    ),
+                );
+            }
+        }
+    }
+
+    // with mode=auto, whatever is on disk is the mode meant to be backed up
+    if secret.capture_mode {
+        return;
+    }
+    let expected = secret.mode.unwrap_or(DEFAULT_MODE);
+    let actual = metadata.permissions().mode() & 0o7777;
+    let extra = actual & !expected;
+    if extra != 0 {
+        let message =
+            format!("on-disk mode {actual:04o} is looser than the manifest mode {expected:04o}");
+        // a secret meant to be private to its owner is exposed, anything else is only drift
+        if expected & 0o077 == 0 {
+            linter.error("loose-mode", path, message);
+        } else {
+            linter.warning("loose-mode", path, message);
+        }
+    }
+}
+
+fn check_owner(linter: &mut Linter, db: &users::Database, secret: &manifest::Secret) {
+    let Some(owner) = &secret.owner else {
+        return;
+    };
+
+    if let Some(user) = owner.user()
+        && !users::is_numeric_id(user)
+        && !db.has_user(user)
+    {
+        linter.error(
+            "unknown-user",
+            &secret.path,
+            format!("user '{user}' does not exist on this host"),
+        );
+    }
+    if let Some(group) = owner.group()
+        && !users::is_numeric_id(group)
+        && !db.has_group(group)
+    {
+        linter.error(
+            "unknown-group",
+            &secret.path,
+            format!("group '{group}' does not exist on this host"),
+        );
+    }
+}
+
+fn check_collisions(linter: &mut Linter, secrets: &[manifest::Secret]) {
+    for secret in secrets {
+        let path = &secret.path;
+        if RESERVED_NAMES.contains(&path.as_str()) {
+            linter.error(
+                "name-collision",
+                path,
+                "path collides with a file generated in snapshots".to_string(),
+            );
+        }
+
+        for extension in ["sha256", "age", "symlink"] {
+            let generated = path.add_extension(extension);
+            if RESERVED_NAMES.contains(&generated.as_str()) {
+                linter.error(
+                    "name-collision",
+                    path,
+                    format!(
+                        "path would be written as '{generated}', which collides with a file of the snapshot"
+                    ),
+                );
+            }
+            if secrets.iter().any(|s| s.path == generated) {
+                linter.error(
+                    "name-collision",
+                    &generated,
+                    format!("path collides with the generated .{extension} file of '{path}'"),
+                );
+            }
+        }
+    }
+}
+
+fn check_stale_sidecars(
+    linter: &mut Linter,
+    source: &Utf8PathBuf,
+    secrets: &[manifest::Secret],
+) -> std::io::Result<()> {
+    for file in export::discover_files(source)? {
+        if file.extension() != Some("sha256") {
+            continue;
+        }
+        let base = file.with_extension("");
+        if secrets.iter().any(|s| s.path == base || s.path == file) {
+            continue;
+        }
+
+        let message = if source.join(&base).exists() {
+            format!("sidecar of '{base}', which is not in the manifest")
+        } else {
+            format!("sidecar of '{base}', which does not exist")
+        };
+        linter.warning("stale-sidecar", &file, message);
+    }
+
+    Ok(())
+}
+
+fn check_unlisted(
+    linter: &mut Linter,
+    source: &Utf8PathBuf,
+    secrets: &[manifest::Secret],
+) -> std::io::Result<()> {
+    for file in export::unlisted_files(source, secrets)? {
+        linter.warning(
+            "unlisted-file",
+            &file,
+            "file is not in the manifest and will not be exported".to_string(),
+        );
+    }
+
+    Ok(())
+}
+
+fn print_human(report: &Report) {
+    for finding in &report.findings {
+        let severity = match finding.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        println!(
+            "{severity}[{}]: '{}': {}",
+            finding.check, finding.path, finding.message
+        );
+    }
+    if !report.findings.is_empty() {
+        println!();
+    }
+    println!("{} errors, {} warnings", report.errors, report.warnings);
+}
+
+pub fn lint(source: String, json: bool, strict: bool) -> Result<(), LintError> {
+    let source = {
+        let path = Utf8PathBuf::from(&source);
+        if !path.exists() {
+            return Err(LintError::MissingSourcePath(path));
+        } else if !path.is_dir() {
+            return Err(LintError::SourceNotDir(path));
+        }
+        path
+    };
+
+    let secrets = manifest::load(&source).map_err(LintError::LoadManifest)?;
+    let db = users::Database::load().map_err(LintError::LoadUsers)?;
+
+    let mut linter = Linter {
+        findings: Vec::new(),
+    };
+    for secret in &secrets {
+        check_file(&mut linter, &source, secret);
+        check_owner(&mut linter, &db, secret);
+    }
+    check_collisions(&mut linter, &secrets);
+    check_stale_sidecars(&mut linter, &source, &secrets).map_err(LintError::ScanSource)?;
+    check_unlisted(&mut linter, &source, &secrets).map_err(LintError::ScanSource)?;
+
+    let count = |severity| {
+        linter
+            .findings
+            .iter()
+            .filter(|f| f.severity == severity)
+            .count()
+    };
+    let report = Report {
+        errors: count(Severity::Error),
+        warnings: count(Severity::Warning),
+        findings: linter.findings,
+    };
+
+    if json {
+        let out = serde_json::to_string_pretty(&report).map_err(LintError::Serialize)?;
+        println!("{out}");
+    } else {
+        print_human(&report);
+    }
+
+    if report.errors > 0 || (strict && report.warnings > 0) {
+        return Err(LintError::Failed {
+            errors: report.errors,
+            warnings: report.warnings,
+        });
+    }
+
+    Ok(())
+}
@@ -2,18 +2,44 @@
 
 use anyhow::{Result, anyhow};
 
+mod cert;
 mod checksum;
+mod chown;
 mod chown_spec;
+mod config;
 mod crypto;
+mod date;
+mod info;
+mod kind;
+mod layout;
 mod manifest;
+mod recovery;
+mod sealed;
 mod snapshot;
 
+mod cert_expiry;
 mod cli;
+mod diff;
 mod export;
+mod hooks;
 mod import;
+mod init;
+mod lint;
+mod manifest_edit;
+mod objects;
+mod owner_map;
+mod plan;
+mod prompt;
+mod prune;
+mod rotation;
 mod safe_fs;
+mod users;
 mod utf8path_ext;
 mod verify_export;
+mod xattr;
+
+#[cfg(test)]
+mod test_support;
 
 fn execute() -> Result<()> {
     let args = cli::args();
@@ -22,18 +48,71 @@ fn execute() -> Result<()> {
         cli::Command::Export {
             secrets_dir,
             export_dir,
+            skip_validation,
+            capture_metadata,
+            capture_xattrs,
+            config,
+            no_hooks,
+            dedup,
+            format,
+            seal_checksums,
+            private_layout,
+            label,
+            note,
+            best_effort,
+            dry_run,
         } => {
-            let passphrase = rpassword::prompt_password("Enter passphrase: ")?;
-            let passphrase_check = rpassword::prompt_password("Enter passphrase again: ")?;
-            if passphrase != passphrase_check {
-                return Err(anyhow!("passphrases do not match"));
-            }
-            println!();
+            // nothing is encrypted by a dry run
+            let passphrase = if dry_run {
+                String::new()
+            } else {
+                let passphrase = rpassword::prompt_password("Enter passphrase: ")?;
+                let passphrase_check = rpassword::prompt_password("Enter passphrase again: ")?;
+                if passphrase != passphrase_check {
+                    return Err(anyhow!("passphrases do not match"));
+                }
+                println!();
+                passphrase
+            };
 
-            export::export(secrets_dir, export_dir, passphrase)?;
+            let options = export::ExportOptions {
+                skip_validation,
+                capture_metadata,
+                capture_xattrs,
+                config,
+                no_hooks,
+                dedup,
+                format,
+                // sidecars would tell the names of the secrets
+                seal_checksums: seal_checksums || private_layout,
+                private_layout,
+                label,
+                note,
+                best_effort,
+                dry_run,
+            };
+            export::export(secrets_dir, export_dir, passphrase, options)?;
         }
-        cli::Command::VerifyExport { export_dir } => {
-            verify_export::verify_export(export_dir)?;
+        cli::Command::VerifyExport {
+            export_dir,
+            cert_window,
+            config,
+            no_hooks,
+            decrypt,
+            sample,
+        } => {
+            let decrypt = if decrypt {
+                let passphrase = rpassword::prompt_password("Enter passphrase: ")?;
+                println!();
+                Some(verify_export::Decrypt {
+                    passphrase,
+                    sample: sample.map(|n| n as usize),
+                })
+            } else {
+                None
+            };
+
+            verify_export::verify_export(export_dir, cert_window, config, no_hooks, decrypt)?;
         }
         cli::Command::Import {
             export_dir,
@@ -41,17 +120,150 @@ fn execute() -> Result<()> {
             pick,
             from_plaintext,
             skip_chown_chmod,
+            skip_validation,
+            map_owner,
+            owner_map,
+            deploy,
+            config,
+            no_hooks,
+            dry_run_hooks,
+            hook_failure,
+            label,
+            dry_run,
         } => {
             let source_type = if from_plaintext {
                 import::SourceType::Plaintext
+            } else if dry_run && !import::dry_run_needs_passphrase(&export_dir, label.as_ref())? {
+                import::SourceType::Encrypted {
+                    passphrase: String::new(),
+                }
             } else {
                 let passphrase = rpassword::prompt_password("Enter passphrase: ")?;
                 println!();
                 import::SourceType::Encrypted { passphrase }
             };
 
-            import::import(export_dir, secrets_dir, pick, source_type, skip_chown_chmod)?;
+            import::import(
+                export_dir,
+                secrets_dir,
+                pick,
+                source_type,
+                import::ImportOptions {
+                    skip_chown_chmod,
+                    skip_validation,
+                    map_owner,
+                    owner_map,
+                    deploy,
+                    config,
+                    no_hooks,
+                    dry_run_hooks,
+                    hook_failure,
+                    dry_run,
+                    label,
+                },
+            )?;
         }
+        cli::Command::RotationStatus {
+            secrets_dir,
+            container,
+            within,
+            strict,
+        } => {
+            rotation::rotation_status(secrets_dir, container, within, strict)?;
+        }
+        cli::Command::CertExpiry {
+            source,
+            within,
+            strict,
+        } => {
+            cert_expiry::cert_expiry(source, within, strict)?;
+        }
+        cli::Command::Diff {
+            from,
+            to,
+            content,
+            reveal,
+        } => {
+            let passphrase = if content {
+                let passphrase = rpassword::prompt_password("Enter passphrase: ")?;
+                println!();
+                Some(passphrase)
+            } else {
+                None
+            };
+
+            diff::diff(from, to, passphrase, reveal)?;
+        }
+        cli::Command::Prune {
+            container,
+            keep_last,
+            keep_daily,
+            keep_weekly,
+            keep_monthly,
+            older_than,
+            dry_run,
+        } => {
+            let retention = prune::Retention {
+                last: keep_last,
+                daily: keep_daily,
+                weekly: keep_weekly,
+                monthly: keep_monthly,
+            };
+            prune::prune(container, retention, older_than, dry_run)?;
+        }
+        cli::Command::Pin {
+            container,
+            snapshot,
+        } => prune::pin(container, snapshot)?,
+        cli::Command::Unpin {
+            container,
+            snapshot,
+        } => prune::unpin(container, snapshot)?,
+        cli::Command::Init {
+            secrets_dir,
+            preset,
+            yes,
+        } => {
+            init::init(secrets_dir, preset, yes)?;
+        }
+        cli::Command::Manifest { command } => match command {
+            cli::ManifestCommand::Add {
+                secrets_dir,
+                paths,
+                owner,
+                mode,
+                kind,
+                unlisted,
+            } => manifest_edit::add(secrets_dir, paths, owner, mode, kind, unlisted)?,
+            cli::ManifestCommand::Remove { secrets_dir, paths } => {
+                manifest_edit::remove(secrets_dir, paths)?
+            }
+            cli::ManifestCommand::Set {
+                secrets_dir,
+                path,
+                owner,
+                mode,
+                kind,
+                no_owner,
+                no_mode,
+                no_kind,
+            } => {
+                let updates = [
+                    manifest_edit::update("owner", owner, no_owner),
+                    manifest_edit::update("mode", mode, no_mode),
+                    manifest_edit::update("kind", kind, no_kind),
+                ];
+                manifest_edit::set(secrets_dir, path, updates.into_iter().flatten().collect())?
+            }
+            cli::ManifestCommand::Lint {
+                secrets_dir,
+                json,
+                strict,
+            } => lint::lint(secrets_dir, json, strict)?,
+            cli::ManifestCommand::List { secrets_dir, json } => {
+                manifest_edit::list(secrets_dir, json)?
+            }
+        },
     };
 
     Ok(())
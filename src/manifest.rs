@@ -1,16 +1,106 @@
-use camino::{Utf8Component, Utf8PathBuf};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
 use std::fs;
 use thiserror::Error;
 
 use crate::chown_spec::{ChownSpec, InvalidChownSpec};
+use crate::config;
+use crate::date;
+use crate::kind::{InvalidKind, Kind};
+use crate::utf8path_ext::ExtraUtf8Path;
 
 pub const MANIFEST_FILENAME: &str = ".secrets-manifest";
 
+// Value of `owner=` and `mode=` asking for the metadata to be captured from the filesystem at export
+pub const AUTO: &str = "auto";
+
+// Metadata of a secret as found on the filesystem at export, recorded by export in the manifest of
+// the snapshot as `captured-*=` annotations so that import can restore it
+#[derive(Debug, Clone, Default)]
+pub struct Captured {
+    // user and group names, as a chown spec
+    pub owner: Option<ChownSpec>,
+    pub ids: Option<(u32, u32)>,
+    pub mode: Option<u32>,
+    // seconds since the epoch
+    pub mtime: Option<i64>,
+    // `user.*` extended attributes and the POSIX access ACL, by name
+    pub xattrs: Vec<(String, Vec<u8>)>,
+    // name the secret is stored under in a snapshot with a private layout, instead of its path
+    pub stored: Option<String>,
+}
+impl Captured {
+    pub fn annotations(&self) -> Vec<(&'static str, String)> {
+        let mut annotations = Vec::new();
+        if let Some(owner) = &self.owner {
+            annotations.push(("captured-owner", owner.as_str().to_string()));
+        }
+        if let Some((uid, gid)) = self.ids {
+            annotations.push(("captured-ids", format!("{uid}:{gid}")));
+        }
+        if let Some(mode) = self.mode {
+            annotations.push(("captured-mode", format!("{mode:04o}")));
+        }
+        if let Some(mtime) = self.mtime {
+            annotations.push(("captured-mtime", mtime.to_string()));
+        }
+        for (name, value) in &self.xattrs {
+            annotations.push(("captured-xattr", format!("{name}={}", BASE64.encode(value))));
+        }
+        if let Some(stored) = &self.stored {
+            annotations.push(("captured-stored", stored.clone()));
+        }
+        annotations
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Secret {
     pub path: Utf8PathBuf,
     pub owner: Option<ChownSpec>,
     pub mode: Option<u32>,
+    // `owner=auto` and `mode=auto`
+    pub capture_owner: bool,
+    pub capture_mode: bool,
+    pub kind: Option<Kind>,
+    // rotation period, in days
+    pub rotate_every: Option<i64>,
+    // hard deadline, in seconds since the epoch
+    pub expires: Option<i64>,
+    // absolute locations the secret actually lives at, outside of the secrets directory
+    pub at: Vec<Utf8PathBuf>,
+    // names of the commands of the host config to run after import changed the secret
+    pub on_import: Vec<String>,
+    // name of the command of the host config whose output is the secret, instead of a file
+    pub from_cmd: Option<String>,
+    // name of the command of the host config the secret is piped to on import, instead of a file
+    pub to_cmd: Option<String>,
+    pub captured: Captured,
+}
+impl Secret {
+    // Where the live content of the secret is read from: its first `at=` location, or else its
+    // path inside the secrets directory
+    pub fn location(&self, source: &Utf8PathBuf) -> Utf8PathBuf {
+        match self.at.first() {
+            Some(at) => at.clone(),
+            None => source.join(&self.path),
+        }
+    }
+
+    // The `.sha256` sidecar of the secret, which stays in the secrets directory even for secrets
+    // living elsewhere
+    pub fn sidecar(&self, source: &Utf8PathBuf) -> Utf8PathBuf {
+        source.join(&self.path).add_extension("sha256")
+    }
+
+    // Where the secret is stored inside a snapshot (before the `.age` extension): its path, or the
+    // name it got in a snapshot with a private layout
+    pub fn stored_path(&self) -> Utf8PathBuf {
+        match &self.captured.stored {
+            Some(stored) => Utf8PathBuf::from(stored),
+            None => self.path.clone(),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -62,6 +152,115 @@ pub fn normalize_selection_path(path: &str) -> Result<Utf8PathBuf, InvalidPath>
     Ok(normalized)
 }
 
+#[derive(Error, Debug)]
+pub enum InvalidLocation {
+    #[error("at= locations must be absolute paths, but '{0}' is relative")]
+    Relative(Utf8PathBuf),
+
+    #[error("at= locations must be normalized paths, but '{0}' contains '.' or '..'")]
+    NotNormalized(Utf8PathBuf),
+}
+fn to_valid_location(path: &str) -> Result<Utf8PathBuf, InvalidLocation> {
+    let path = Utf8PathBuf::from(path);
+    if !path.is_absolute() {
+        return Err(InvalidLocation::Relative(path));
+    }
+    if path
+        .components()
+        .any(|c| matches!(c, Utf8Component::CurDir | Utf8Component::ParentDir))
+        || path.file_name().is_none()
+    {
+        return Err(InvalidLocation::NotNormalized(path));
+    }
+
+    Ok(path)
+}
+
+#[derive(Error, Debug)]
+pub enum InvalidLinkTarget {
+    #[error("link targets must be relative paths, but '{0}' is absolute")]
+    Absolute(Utf8PathBuf),
+
+    #[error("link target '{0}' points outside of the secrets directory")]
+    Escapes(Utf8PathBuf),
+
+    #[error("link target '{0}' goes through a symlink to '{1}', outside of the secrets directory")]
+    ResolvesOutside(Utf8PathBuf, Utf8PathBuf),
+
+    #[error("could not resolve link target '{0}': {1}")]
+    Resolve(Utf8PathBuf, std::io::Error),
+}
+
+// Checks that the target of the symlink at `path` (relative to the secrets directory) resolves
+// inside the secrets directory, so that restoring the link can't expose or depend on anything
+// outside of the backup
+pub fn check_link_target(
+    path: &Utf8PathBuf,
+    target: &Utf8PathBuf,
+) -> Result<(), InvalidLinkTarget> {
+    let mut depth = path.components().count() as i64 - 1;
+    for component in target.components() {
+        match component {
+            Utf8Component::Normal(_) => depth += 1,
+            Utf8Component::CurDir => {}
+            Utf8Component::ParentDir => depth -= 1,
+
+            Utf8Component::RootDir => return Err(InvalidLinkTarget::Absolute(target.clone())),
+            Utf8Component::Prefix(_) => unreachable!("Utf8Component::Prefix cannot occur in Unix"),
+        }
+        if depth < 0 {
+            return Err(InvalidLinkTarget::Escapes(target.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+// Same as check_link_target, but also follows the symlinks that the target goes through inside
+// `source`: a symlinked directory can lead outside of the secrets directory while the target stays
+// inside of it lexically
+pub fn check_resolved_link_target(
+    source: &Utf8Path,
+    path: &Utf8PathBuf,
+    target: &Utf8PathBuf,
+) -> Result<(), InvalidLinkTarget> {
+    check_link_target(path, target)?;
+
+    let resolve = |p: &Utf8Path| {
+        p.canonicalize_utf8()
+            .map_err(|e| InvalidLinkTarget::Resolve(target.clone(), e))
+    };
+    let root = resolve(source)?;
+    let joined = source
+        .join(path)
+        .parent()
+        .expect("joined paths have a parent")
+        .join(target);
+
+    // the deepest ancestor that exists is resolved by the filesystem, the rest of the target
+    // doesn't exist (yet) and can only be followed lexically
+    let existing = joined
+        .ancestors()
+        .find(|a| a.exists())
+        .expect("the secrets directory exists");
+    let mut resolved = resolve(existing)?;
+    for component in joined.strip_prefix(existing).unwrap().components() {
+        match component {
+            Utf8Component::ParentDir => {
+                resolved.pop();
+            }
+            Utf8Component::Normal(name) => resolved.push(name),
+            _ => {}
+        }
+    }
+
+    if !resolved.starts_with(&root) {
+        return Err(InvalidLinkTarget::ResolvesOutside(target.clone(), resolved));
+    }
+
+    Ok(())
+}
+
 #[derive(Error, Debug)]
 pub enum InvalidEntry {
     #[error("invalid path: {0}")]
@@ -73,7 +272,61 @@ pub enum InvalidEntry {
     #[error("'{0}' is not a valid mode (expected 3-4 octal digits, e.g. 0600)")]
     Mode(String),
 
-    #[error("'{0}' is not a recognized annotation (expected owner=... or mode=...)")]
+    #[error(transparent)]
+    Kind(#[from] InvalidKind),
+
+    #[error(
+        "'{0}' is not a valid rotation period (expected a number of days, weeks, months or years, e.g. 90d)"
+    )]
+    RotateEvery(String),
+
+    #[error("'{0}' is not a valid expiry date (expected YYYY-MM-DD)")]
+    Expires(String),
+
+    #[error("invalid location: {0}")]
+    At(#[from] InvalidLocation),
+
+    #[error(
+        "'{0}' is not a valid command name (expected letters, digits, '-' and '_', naming a command of the host config)"
+    )]
+    OnImport(String),
+
+    #[error(
+        "'{0}' is not a valid from-cmd command name (expected letters, digits, '-' and '_', naming a command of the host config)"
+    )]
+    FromCmd(String),
+
+    #[error(
+        "'{0}' is not a valid to-cmd command name (expected letters, digits, '-' and '_', naming a command of the host config)"
+    )]
+    ToCmd(String),
+
+    #[error(
+        "symlinks can only be backed up from inside the secrets directory, they can't have at="
+    )]
+    SymlinkAt,
+
+    #[error("secrets read from a command have no file to back up, they can't have {0}")]
+    FromCmdConflict(&'static str),
+
+    #[error("to-cmd= can only be given to secrets read from a command (with from-cmd=)")]
+    ToCmdWithoutFromCmd,
+
+    #[error("'{0}' is not a valid captured-ids value (expected <uid>:<gid>)")]
+    CapturedIds(String),
+
+    #[error("'{0}' is not a valid captured-mtime value (expected seconds since the epoch)")]
+    CapturedMtime(String),
+
+    #[error("'{0}' is not a valid captured-xattr value (expected <name>=<base64 value>)")]
+    CapturedXattr(String),
+
+    #[error("'{0}' is not a valid captured-stored value (expected lowercase hexadecimal digits)")]
+    CapturedStored(String),
+
+    #[error(
+        "'{0}' is not a recognized annotation (expected owner=..., mode=..., kind=..., rotate-every=..., expires=..., at=..., on-import=..., from-cmd=..., to-cmd=... or the captured-*=... annotations written by export)"
+    )]
     UnknownAttribute(String),
 
     #[error("owner specified more than once")]
@@ -81,36 +334,224 @@ pub enum InvalidEntry {
 
     #[error("mode specified more than once")]
     DuplicateMode,
+
+    #[error("kind specified more than once")]
+    DuplicateKind,
+
+    #[error("rotate-every specified more than once")]
+    DuplicateRotateEvery,
+
+    #[error("expires specified more than once")]
+    DuplicateExpires,
+
+    #[error("location '{0}' specified more than once")]
+    DuplicateAt(Utf8PathBuf),
+
+    #[error("on-import command '{0}' specified more than once")]
+    DuplicateOnImport(String),
+
+    #[error("from-cmd specified more than once")]
+    DuplicateFromCmd,
+
+    #[error("to-cmd specified more than once")]
+    DuplicateToCmd,
+
+    #[error("captured-owner specified more than once")]
+    DuplicateCapturedOwner,
+
+    #[error("captured-ids specified more than once")]
+    DuplicateCapturedIds,
+
+    #[error("captured-mode specified more than once")]
+    DuplicateCapturedMode,
+
+    #[error("captured-mtime specified more than once")]
+    DuplicateCapturedMtime,
+
+    #[error("captured-stored specified more than once")]
+    DuplicateCapturedStored,
 }
 fn is_mode(value: &str) -> bool {
     (3..=4).contains(&value.len()) && value.bytes().all(|b| (b'0'..=b'7').contains(&b))
 }
-fn parse_entry(line: &str) -> Result<Secret, InvalidEntry> {
+fn parse_mode(value: &str) -> Result<u32, InvalidEntry> {
+    if !is_mode(value) {
+        return Err(InvalidEntry::Mode(value.to_string()));
+    }
+    Ok(u32::from_str_radix(value, 8).expect("validated octal mode"))
+}
+fn parse_ids(value: &str) -> Option<(u32, u32)> {
+    let (uid, gid) = value.split_once(':')?;
+    Some((uid.parse().ok()?, gid.parse().ok()?))
+}
+fn parse_xattr(value: &str) -> Option<(String, Vec<u8>)> {
+    let (name, encoded) = value.split_once('=')?;
+    if name.is_empty() {
+        return None;
+    }
+    Some((name.to_string(), BASE64.decode(encoded).ok()?))
+}
+pub fn parse_entry(line: &str) -> Result<Secret, InvalidEntry> {
     let mut tokens = line.split_whitespace();
     let path = to_valid_path(tokens.next().expect("non-blank line has a first token"))?;
 
     let mut owner: Option<ChownSpec> = None;
     let mut mode: Option<u32> = None;
+    let mut capture_owner = false;
+    let mut capture_mode = false;
+    let mut kind: Option<Kind> = None;
+    let mut rotate_every: Option<i64> = None;
+    let mut expires: Option<i64> = None;
+    let mut at: Vec<Utf8PathBuf> = Vec::new();
+    let mut on_import: Vec<String> = Vec::new();
+    let mut from_cmd: Option<String> = None;
+    let mut to_cmd: Option<String> = None;
+    let mut captured = Captured::default();
     for token in tokens {
         if let Some(spec) = token.strip_prefix("owner=") {
-            if owner.is_some() {
+            if owner.is_some() || capture_owner {
                 return Err(InvalidEntry::DuplicateOwner);
             }
-            owner = Some(spec.parse()?);
+            if spec == AUTO {
+                capture_owner = true;
+            } else {
+                owner = Some(spec.parse()?);
+            }
         } else if let Some(value) = token.strip_prefix("mode=") {
-            if mode.is_some() {
+            if mode.is_some() || capture_mode {
                 return Err(InvalidEntry::DuplicateMode);
             }
-            if !is_mode(value) {
-                return Err(InvalidEntry::Mode(value.to_string()));
+            if value == AUTO {
+                capture_mode = true;
+            } else {
+                mode = Some(parse_mode(value)?);
             }
-            mode = Some(u32::from_str_radix(value, 8).expect("validated octal mode"));
+        } else if let Some(value) = token.strip_prefix("kind=") {
+            if kind.is_some() {
+                return Err(InvalidEntry::DuplicateKind);
+            }
+            kind = Some(value.parse()?);
+        } else if let Some(value) = token.strip_prefix("rotate-every=") {
+            if rotate_every.is_some() {
+                return Err(InvalidEntry::DuplicateRotateEvery);
+            }
+            let days = date::parse_days(value)
+                .filter(|days| *days > 0)
+                .ok_or(InvalidEntry::RotateEvery(value.to_string()))?;
+            rotate_every = Some(days);
+        } else if let Some(value) = token.strip_prefix("expires=") {
+            if expires.is_some() {
+                return Err(InvalidEntry::DuplicateExpires);
+            }
+            let date = date::parse_date(value).ok_or(InvalidEntry::Expires(value.to_string()))?;
+            expires = Some(date);
+        } else if let Some(value) = token.strip_prefix("at=") {
+            let location = to_valid_location(value)?;
+            if at.contains(&location) {
+                return Err(InvalidEntry::DuplicateAt(location));
+            }
+            at.push(location);
+        } else if let Some(name) = token.strip_prefix("on-import=") {
+            if !config::is_valid_name(name) {
+                return Err(InvalidEntry::OnImport(name.to_string()));
+            }
+            if on_import.iter().any(|n| n == name) {
+                return Err(InvalidEntry::DuplicateOnImport(name.to_string()));
+            }
+            on_import.push(name.to_string());
+        } else if let Some(name) = token.strip_prefix("from-cmd=") {
+            if from_cmd.is_some() {
+                return Err(InvalidEntry::DuplicateFromCmd);
+            }
+            if !config::is_valid_name(name) {
+                return Err(InvalidEntry::FromCmd(name.to_string()));
+            }
+            from_cmd = Some(name.to_string());
+        } else if let Some(name) = token.strip_prefix("to-cmd=") {
+            if to_cmd.is_some() {
+                return Err(InvalidEntry::DuplicateToCmd);
+            }
+            if !config::is_valid_name(name) {
+                return Err(InvalidEntry::ToCmd(name.to_string()));
+            }
+            to_cmd = Some(name.to_string());
+        } else if let Some(spec) = token.strip_prefix("captured-owner=") {
+            if captured.owner.is_some() {
+                return Err(InvalidEntry::DuplicateCapturedOwner);
+            }
+            captured.owner = Some(spec.parse()?);
+        } else if let Some(value) = token.strip_prefix("captured-ids=") {
+            if captured.ids.is_some() {
+                return Err(InvalidEntry::DuplicateCapturedIds);
+            }
+            let ids = parse_ids(value).ok_or(InvalidEntry::CapturedIds(value.to_string()))?;
+            captured.ids = Some(ids);
+        } else if let Some(value) = token.strip_prefix("captured-mode=") {
+            if captured.mode.is_some() {
+                return Err(InvalidEntry::DuplicateCapturedMode);
+            }
+            captured.mode = Some(parse_mode(value)?);
+        } else if let Some(value) = token.strip_prefix("captured-mtime=") {
+            if captured.mtime.is_some() {
+                return Err(InvalidEntry::DuplicateCapturedMtime);
+            }
+            let mtime = value
+                .parse()
+                .map_err(|_| InvalidEntry::CapturedMtime(value.to_string()))?;
+            captured.mtime = Some(mtime);
+        } else if let Some(value) = token.strip_prefix("captured-xattr=") {
+            let xattr = parse_xattr(value).ok_or(InvalidEntry::CapturedXattr(value.to_string()))?;
+            captured.xattrs.push(xattr);
+        } else if let Some(value) = token.strip_prefix("captured-stored=") {
+            if captured.stored.is_some() {
+                return Err(InvalidEntry::DuplicateCapturedStored);
+            }
+            // joined to the snapshot directory, so it must not be able to point outside of it
+            if value.is_empty()
+                || !value
+                    .bytes()
+                    .all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+            {
+                return Err(InvalidEntry::CapturedStored(value.to_string()));
+            }
+            captured.stored = Some(value.to_string());
         } else {
             return Err(InvalidEntry::UnknownAttribute(token.to_string()));
         }
     }
 
-    Ok(Secret { path, owner, mode })
+    if kind == Some(Kind::Symlink) && !at.is_empty() {
+        return Err(InvalidEntry::SymlinkAt);
+    }
+    if from_cmd.is_some() {
+        if kind == Some(Kind::Symlink) {
+            return Err(InvalidEntry::FromCmdConflict("kind=symlink"));
+        } else if !at.is_empty() {
+            return Err(InvalidEntry::FromCmdConflict("at="));
+        } else if capture_owner {
+            return Err(InvalidEntry::FromCmdConflict("owner=auto"));
+        } else if capture_mode {
+            return Err(InvalidEntry::FromCmdConflict("mode=auto"));
+        }
+    } else if to_cmd.is_some() {
+        return Err(InvalidEntry::ToCmdWithoutFromCmd);
+    }
+
+    Ok(Secret {
+        path,
+        owner,
+        mode,
+        capture_owner,
+        capture_mode,
+        kind,
+        rotate_every,
+        expires,
+        at,
+        on_import,
+        from_cmd,
+        to_cmd,
+        captured,
+    })
 }
 
 #[derive(Error, Debug)]
@@ -126,6 +567,14 @@ pub enum ManifestError {
 
     #[error("manifest at '{0}' declares secret '{1}' multiple times")]
     Duplicate(Utf8PathBuf, Utf8PathBuf),
+
+    #[error("manifest at '{0}' places secrets '{1}' and '{2}' at the same location '{3}'")]
+    SharedLocation(Utf8PathBuf, Utf8PathBuf, Utf8PathBuf, Utf8PathBuf),
+
+    #[error(
+        "manifest at '{0}' has a {2}= annotation on secret '{1}', which only the manifest of a snapshot can hold"
+    )]
+    Captured(Utf8PathBuf, Utf8PathBuf, &'static str),
 }
 impl ManifestError {
     fn read(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
@@ -133,14 +582,7 @@ impl ManifestError {
     }
 }
 
-pub fn load(dir: &Utf8PathBuf) -> Result<Vec<Secret>, ManifestError> {
-    let path = dir.join(MANIFEST_FILENAME);
-    if !path.exists() {
-        return Err(ManifestError::Missing(path));
-    }
-
-    let content = fs::read_to_string(&path).map_err(ManifestError::read(&path))?;
-
+pub fn parse(path: &Utf8PathBuf, content: &str) -> Result<Vec<Secret>, ManifestError> {
     let mut secrets: Vec<Secret> = Vec::new();
     for line in content.lines() {
         let line = line.trim();
@@ -153,8 +595,60 @@ pub fn load(dir: &Utf8PathBuf) -> Result<Vec<Secret>, ManifestError> {
         if secrets.iter().any(|s| s.path == secret.path) {
             return Err(ManifestError::Duplicate(path.clone(), secret.path));
         }
+        for location in &secret.at {
+            if let Some(other) = secrets.iter().find(|s| s.at.contains(location)) {
+                return Err(ManifestError::SharedLocation(
+                    path.clone(),
+                    other.path.clone(),
+                    secret.path.clone(),
+                    location.clone(),
+                ));
+            }
+        }
         secrets.push(secret);
     }
 
     Ok(secrets)
 }
+
+// Parses the manifest of a secrets directory. The captured-*= annotations are written by export
+// into the manifest of a snapshot, a secrets directory can't hold them
+pub fn parse_source(path: &Utf8PathBuf, content: &str) -> Result<Vec<Secret>, ManifestError> {
+    let secrets = parse(path, content)?;
+    for secret in &secrets {
+        if let Some((key, _)) = secret.captured.annotations().first() {
+            return Err(ManifestError::Captured(
+                path.clone(),
+                secret.path.clone(),
+                key,
+            ));
+        }
+    }
+
+    Ok(secrets)
+}
+
+fn read(dir: &Utf8PathBuf) -> Result<(Utf8PathBuf, String), ManifestError> {
+    let path = dir.join(MANIFEST_FILENAME);
+    if !path.exists() {
+        return Err(ManifestError::Missing(path));
+    }
+
+    let content = fs::read_to_string(&path).map_err(ManifestError::read(&path))?;
+
+    Ok((path, content))
+}
+
+// Loads the manifest of a secrets directory
+pub fn load(dir: &Utf8PathBuf) -> Result<Vec<Secret>, ManifestError> {
+    let (path, content) = read(dir)?;
+
+    parse_source(&path, &content)
+}
+
+// Loads the manifest of a snapshot directory, captured-*= annotations included
+pub fn load_snapshot(dir: &Utf8PathBuf) -> Result<Vec<Secret>, ManifestError> {
+    let (path, content) = read(dir)?;
+
+    parse(&path, &content)
+}
@@ -0,0 +1,505 @@
+use std::fs;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::checksum;
+use crate::export;
+use crate::kind;
+use crate::manifest;
+use crate::prompt;
+use crate::utf8path_ext::ExtraUtf8Path;
+
+#[derive(Error, Debug)]
+pub enum ManifestEditError {
+    #[error("secrets path '{0}' does not exist")]
+    MissingSourcePath(Utf8PathBuf),
+    #[error("secrets path '{0}' is not a directory")]
+    SourceNotDir(Utf8PathBuf),
+
+    #[error("failed to load manifest\n{0}")]
+    LoadManifest(manifest::ManifestError),
+
+    #[error("refusing to write manifest, the edited manifest would be invalid\n{0}")]
+    ValidateManifest(manifest::ManifestError),
+
+    #[error("failed to write manifest at '{0}'\n{1}")]
+    WriteManifest(Utf8PathBuf, std::io::Error),
+
+    #[error("invalid secret path: {0}")]
+    InvalidPath(manifest::InvalidPath),
+
+    #[error("'{0}' is not a valid manifest entry\n{1}")]
+    InvalidEntry(String, manifest::InvalidEntry),
+
+    #[error("secret '{0}' is already listed in the manifest")]
+    AlreadyListed(Utf8PathBuf),
+
+    #[error("secret '{0}' is not listed in the manifest")]
+    NotListed(Utf8PathBuf),
+
+    #[error("'{0}' does not exist or is not a regular file")]
+    MissingFile(Utf8PathBuf),
+
+    #[error("'{0}' is annotated kind=symlink but is not a symlink")]
+    NotASymlink(Utf8PathBuf),
+
+    #[error("failed to read target of symlink '{0}'\n{1}")]
+    ReadLink(Utf8PathBuf, std::io::Error),
+
+    #[error("refusing to add symlink '{0}'\n{1}")]
+    LinkTarget(Utf8PathBuf, manifest::InvalidLinkTarget),
+
+    #[error("failed to prepare checksum of '{0}'\n{1}")]
+    Checksum(Utf8PathBuf, checksum::ChecksumError),
+
+    #[error("failed to scan secrets directory for unlisted files\n{0}")]
+    ScanSource(std::io::Error),
+
+    #[error("failed to read confirmation from stdin\n{0}")]
+    Confirm(std::io::Error),
+
+    #[error("failed to serialize manifest to json\n{0}")]
+    Serialize(serde_json::Error),
+}
+impl ManifestEditError {
+    fn write_manifest(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::WriteManifest(path.clone(), e)
+    }
+
+    fn checksum(path: &Utf8PathBuf) -> impl Fn(checksum::ChecksumError) -> Self {
+        |e| Self::Checksum(path.clone(), e)
+    }
+}
+
+// Byte ranges of the whitespace-separated tokens of a line. Used to edit single annotations in
+// place, so that the alignment of the rest of the line is left untouched
+fn token_ranges(line: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (i, c) in line.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                ranges.push((s, i));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        ranges.push((s, line.len()));
+    }
+
+    ranges
+}
+
+fn set_annotation(line: &str, key: &str, value: Option<&str>) -> String {
+    let prefix = format!("{key}=");
+    let ranges = token_ranges(line);
+
+    let found = ranges
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find(|(_, (s, e))| line[*s..*e].starts_with(&prefix));
+
+    match (found, value) {
+        (Some((_, (s, e))), Some(value)) => {
+            format!("{}{prefix}{value}{}", &line[..*s], &line[*e..])
+        }
+        (Some((i, (_, e))), None) => {
+            let (_, previous_end) = ranges[i - 1];
+            format!("{}{}", &line[..previous_end], &line[*e..])
+        }
+        (None, Some(value)) => format!("{} {prefix}{value}", line.trim_end()),
+        (None, None) => line.to_string(),
+    }
+}
+
+// Removes every annotation whose key starts with the given prefix
+fn remove_annotations(line: &str, key_prefix: &str) -> String {
+    let ranges = token_ranges(line);
+    let Some((_, path_end)) = ranges.first() else {
+        return line.to_string();
+    };
+
+    let mut kept = line[..*path_end].to_string();
+    let mut previous_end = *path_end;
+    for (s, e) in ranges.iter().skip(1) {
+        if !line[*s..*e].starts_with(key_prefix) {
+            kept.push_str(&line[previous_end..*e]);
+        }
+        previous_end = *e;
+    }
+    kept.push_str(&line[previous_end..]);
+
+    kept
+}
+
+fn entry_path(line: &str) -> Option<&Utf8Path> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    line.split_whitespace().next().map(Utf8Path::new)
+}
+
+// A manifest loaded as raw lines, so that it can be edited and written back without losing
+// comments, blank lines or the ordering of its entries
+struct ManifestFile {
+    path: Utf8PathBuf,
+    lines: Vec<String>,
+}
+impl ManifestFile {
+    fn open(dir: &Utf8PathBuf) -> Result<Self, ManifestEditError> {
+        let path = dir.join(manifest::MANIFEST_FILENAME);
+        if !path.exists() {
+            return Err(ManifestEditError::LoadManifest(
+                manifest::ManifestError::Missing(path),
+            ));
+        }
+        let content = fs::read_to_string(&path).map_err(|e| {
+            ManifestEditError::LoadManifest(manifest::ManifestError::Read(path.clone(), e))
+        })?;
+
+        Ok(Self {
+            path,
+            lines: content.lines().map(String::from).collect(),
+        })
+    }
+
+    fn content(&self) -> String {
+        self.lines.join("\n") + "\n"
+    }
+
+    fn secrets(&self) -> Result<Vec<manifest::Secret>, ManifestEditError> {
+        manifest::parse_source(&self.path, &self.content()).map_err(ManifestEditError::LoadManifest)
+    }
+
+    fn find(&self, path: &Utf8Path) -> Option<usize> {
+        self.lines
+            .iter()
+            .position(|line| entry_path(line) == Some(path))
+    }
+
+    fn save(&self) -> Result<(), ManifestEditError> {
+        let content = self.content();
+        manifest::parse_source(&self.path, &content)
+            .map_err(ManifestEditError::ValidateManifest)?;
+
+        let tmp = self.path.add_extension("partial-edit");
+        fs::write(&tmp, content).map_err(ManifestEditError::write_manifest(&tmp))?;
+        fs::rename(&tmp, &self.path).map_err(ManifestEditError::write_manifest(&self.path))?;
+
+        Ok(())
+    }
+}
+
+// Renders the manifest of a secrets directory with the metadata captured at export recorded on
+// each entry, replacing the one recorded by a previous export (if the manifest was restored from
+// a snapshot)
+pub fn with_captured(
+    dir: &Utf8PathBuf,
+    captured: &[(Utf8PathBuf, manifest::Captured)],
+) -> Result<String, ManifestEditError> {
+    let mut manifest_file = ManifestFile::open(dir)?;
+
+    for line in manifest_file.lines.iter_mut() {
+        if entry_path(line).is_some() {
+            *line = remove_annotations(line, "captured-");
+        }
+    }
+    for (path, metadata) in captured {
+        let index = manifest_file
+            .find(path)
+            .ok_or(ManifestEditError::NotListed(path.clone()))?;
+        let line = &mut manifest_file.lines[index];
+        for (key, value) in metadata.annotations() {
+            *line = format!("{} {key}={value}", line.trim_end());
+        }
+    }
+
+    let content = manifest_file.content();
+    manifest::parse(&manifest_file.path, &content).map_err(ManifestEditError::ValidateManifest)?;
+
+    Ok(content)
+}
+
+// Drops the `captured-*=` annotations of the manifest (or the index) of a snapshot, for it to be
+// restored as the manifest of a secrets directory
+pub fn without_captured(manifest_content: &str) -> String {
+    manifest_content
+        .lines()
+        .map(|line| match entry_path(line) {
+            Some(_) => remove_annotations(line, "captured-"),
+            None => line.to_string(),
+        })
+        .map(|line| line + "\n")
+        .collect()
+}
+
+fn validate_source(source: String) -> Result<Utf8PathBuf, ManifestEditError> {
+    let path = Utf8PathBuf::from(&source);
+    if !path.exists() {
+        return Err(ManifestEditError::MissingSourcePath(path));
+    } else if !path.is_dir() {
+        return Err(ManifestEditError::SourceNotDir(path));
+    }
+
+    Ok(path)
+}
+
+fn to_secret_path(path: &str) -> Result<Utf8PathBuf, ManifestEditError> {
+    manifest::normalize_selection_path(path).map_err(ManifestEditError::InvalidPath)
+}
+
+fn validate_entry(line: &str) -> Result<manifest::Secret, ManifestEditError> {
+    manifest::parse_entry(line).map_err(|e| ManifestEditError::InvalidEntry(line.to_string(), e))
+}
+
+// Symlinks carry no sidecar, only their target has to be checked
+fn check_symlink(source: &Utf8PathBuf, path: &Utf8PathBuf) -> Result<(), ManifestEditError> {
+    let file = source.join(path);
+    if !file.is_symlink() {
+        return Err(ManifestEditError::NotASymlink(file));
+    }
+
+    let destination = file
+        .read_link_utf8()
+        .map_err(|e| ManifestEditError::ReadLink(file.clone(), e))?;
+    manifest::check_resolved_link_target(source, path, &destination)
+        .map_err(|e| ManifestEditError::LinkTarget(file.clone(), e))
+}
+
+// Checks a file to add against its sidecar, if it already has one
+fn check_file(source: &Utf8PathBuf, path: &Utf8PathBuf) -> Result<(), ManifestEditError> {
+    let file = source.join(path);
+    if !file.is_file() {
+        return Err(ManifestEditError::MissingFile(file));
+    }
+
+    if file.add_extension("sha256").exists() {
+        checksum::verify_file_checksum(&file).map_err(ManifestEditError::checksum(&file))?;
+    }
+
+    Ok(())
+}
+
+fn generate_missing_checksum(
+    source: &Utf8PathBuf,
+    path: &Utf8PathBuf,
+) -> Result<(), ManifestEditError> {
+    let file = source.join(path);
+    if !file.add_extension("sha256").exists() {
+        checksum::generate_file_checksum(&file).map_err(ManifestEditError::checksum(&file))?;
+    }
+
+    Ok(())
+}
+
+pub fn add(
+    source: String,
+    paths: Vec<String>,
+    owner: Option<String>,
+    mode: Option<String>,
+    kind: Option<String>,
+    unlisted: bool,
+) -> Result<(), ManifestEditError> {
+    let source = validate_source(source)?;
+    let mut manifest_file = ManifestFile::open(&source)?;
+    let secrets = manifest_file.secrets()?;
+
+    let mut candidates = Vec::new();
+    for path in &paths {
+        candidates.push(to_secret_path(path)?);
+    }
+    if unlisted {
+        let unlisted_files =
+            export::unlisted_files(&source, &secrets).map_err(ManifestEditError::ScanSource)?;
+        for path in unlisted_files {
+            if candidates.contains(&path) {
+                continue;
+            }
+            if prompt::confirm(&format!("Adopt unlisted file '{path}'?"))
+                .map_err(ManifestEditError::Confirm)?
+            {
+                candidates.push(path);
+            }
+        }
+        println!();
+    }
+
+    if candidates.is_empty() {
+        println!("Nothing to add.");
+        return Ok(());
+    }
+
+    let mut entries: Vec<(manifest::Secret, String)> = Vec::new();
+    for path in &candidates {
+        if secrets.iter().any(|s| &s.path == path) || entries.iter().any(|(s, _)| &s.path == path) {
+            return Err(ManifestEditError::AlreadyListed(path.clone()));
+        }
+
+        let mut line = path.to_string();
+        if let Some(owner) = &owner {
+            line = set_annotation(&line, "owner", Some(owner));
+        }
+        if let Some(mode) = &mode {
+            line = set_annotation(&line, "mode", Some(mode));
+        }
+        match &kind {
+            Some(kind) => line = set_annotation(&line, "kind", Some(kind)),
+            // a symlink added without a kind would be backed up as a copy of its target
+            None if source.join(path).is_symlink() => {
+                line = set_annotation(&line, "kind", Some(kind::Kind::Symlink.as_str()));
+            }
+            None => {}
+        }
+        let secret = validate_entry(&line)?;
+        if secret.kind == Some(kind::Kind::Symlink) {
+            check_symlink(&source, path)?;
+        } else {
+            check_file(&source, path)?;
+        }
+
+        entries.push((secret, line));
+    }
+
+    // written once every entry is valid, so that a rejected one leaves no sidecar behind
+    for (secret, line) in entries {
+        let path = secret.path;
+        if secret.kind != Some(kind::Kind::Symlink) {
+            generate_missing_checksum(&source, &path)?;
+        }
+        manifest_file.lines.push(line);
+        println!("added '{path}'");
+    }
+    manifest_file.save()?;
+
+    Ok(())
+}
+
+pub fn remove(source: String, paths: Vec<String>) -> Result<(), ManifestEditError> {
+    let source = validate_source(source)?;
+    let mut manifest_file = ManifestFile::open(&source)?;
+
+    for path in &paths {
+        let path = to_secret_path(path)?;
+        let index = manifest_file
+            .find(&path)
+            .ok_or(ManifestEditError::NotListed(path.clone()))?;
+        manifest_file.lines.remove(index);
+        println!("removed '{path}' (the file and its sidecar are left in place)");
+    }
+    manifest_file.save()?;
+
+    Ok(())
+}
+
+// A single annotation change requested through `manifest set`: the new value of the annotation,
+// or None to remove it
+pub type AnnotationUpdate = (&'static str, Option<String>);
+
+pub fn update(key: &'static str, value: Option<String>, unset: bool) -> Option<AnnotationUpdate> {
+    match (value, unset) {
+        (Some(value), _) => Some((key, Some(value))),
+        (None, true) => Some((key, None)),
+        (None, false) => None,
+    }
+}
+
+pub fn set(
+    source: String,
+    path: String,
+    updates: Vec<AnnotationUpdate>,
+) -> Result<(), ManifestEditError> {
+    let source = validate_source(source)?;
+    let mut manifest_file = ManifestFile::open(&source)?;
+
+    let path = to_secret_path(&path)?;
+    let index = manifest_file
+        .find(&path)
+        .ok_or(ManifestEditError::NotListed(path.clone()))?;
+
+    let mut line = manifest_file.lines[index].clone();
+    for (key, value) in &updates {
+        line = set_annotation(&line, key, value.as_deref());
+    }
+    validate_entry(line.trim())?;
+
+    println!("{}", line.trim());
+    manifest_file.lines[index] = line;
+    manifest_file.save()?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ListedSecret {
+    path: Utf8PathBuf,
+    owner: Option<String>,
+    mode: Option<String>,
+    kind: Option<String>,
+    at: Vec<Utf8PathBuf>,
+    from_cmd: Option<String>,
+    to_cmd: Option<String>,
+}
+impl From<&manifest::Secret> for ListedSecret {
+    fn from(secret: &manifest::Secret) -> Self {
+        Self {
+            path: secret.path.clone(),
+            owner: match &secret.owner {
+                Some(owner) => Some(owner.as_str().to_string()),
+                None => secret.capture_owner.then(|| manifest::AUTO.to_string()),
+            },
+            mode: match secret.mode {
+                Some(mode) => Some(format!("{mode:04o}")),
+                None => secret.capture_mode.then(|| manifest::AUTO.to_string()),
+            },
+            kind: secret.kind.map(|k| k.to_string()),
+            at: secret.at.clone(),
+            from_cmd: secret.from_cmd.clone(),
+            to_cmd: secret.to_cmd.clone(),
+        }
+    }
+}
+
+pub fn list(source: String, json: bool) -> Result<(), ManifestEditError> {
+    let source = validate_source(source)?;
+    let secrets = manifest::load(&source).map_err(ManifestEditError::LoadManifest)?;
+    let listed: Vec<ListedSecret> = secrets.iter().map(ListedSecret::from).collect();
+
+    if json {
+        let out = serde_json::to_string_pretty(&listed).map_err(ManifestEditError::Serialize)?;
+        println!("{out}");
+        return Ok(());
+    }
+
+    let width = listed
+        .iter()
+        .map(|s| s.path.as_str().len())
+        .max()
+        .unwrap_or(0);
+    for secret in &listed {
+        let owner = secret.owner.as_deref().unwrap_or("-");
+        let mode = secret.mode.as_deref().unwrap_or("-");
+        let kind = secret.kind.as_deref().unwrap_or("-");
+        let at: String = secret.at.iter().map(|at| format!("  at={at}")).collect();
+        let from_cmd = match &secret.from_cmd {
+            Some(name) => format!("  from-cmd={name}"),
+            None => String::new(),
+        };
+        let to_cmd = match &secret.to_cmd {
+            Some(name) => format!("  to-cmd={name}"),
+            None => String::new(),
+        };
+        println!(
+            "{:width$}  owner={owner}  mode={mode}  kind={kind}{at}{from_cmd}{to_cmd}",
+            secret.path.as_str()
+        );
+    }
+
+    Ok(())
+}
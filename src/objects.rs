@@ -0,0 +1,110 @@
+use std::{fs, io, os::unix::fs::symlink};
+
+use camino::Utf8PathBuf;
+
+use crate::snapshot;
+use crate::utf8path_ext::ExtraUtf8Path;
+
+// Directory of a container holding the encrypted files shared between its snapshots, each named by
+// the sha256 of its content. A container is deduplicated as soon as it has one
+pub const OBJECTS_DIRNAME: &str = ".objects";
+
+pub fn is_enabled(container: &Utf8PathBuf) -> bool {
+    container.join(OBJECTS_DIRNAME).is_dir()
+}
+
+pub fn enable(container: &Utf8PathBuf) -> io::Result<()> {
+    fs::create_dir_all(container.join(OBJECTS_DIRNAME))
+}
+
+// Stores content as an object of the container, unless an object with the same content is already
+// there, and returns the path of the object. An existing object is checked against its name first,
+// and replaced if corrupted, so that linking it can't spread the corruption to a new snapshot
+pub fn store(container: &Utf8PathBuf, content: &[u8]) -> io::Result<Utf8PathBuf> {
+    let digest = sha256::digest(content);
+    let object = container.join(OBJECTS_DIRNAME).join(&digest);
+    if object.exists() && sha256::digest(fs::read(&object)?) == digest {
+        return Ok(object);
+    }
+
+    // written aside and renamed, so that an interrupted export never leaves a truncated object
+    let tmp = object.add_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, &object)?;
+
+    Ok(object)
+}
+
+// Makes an object appear at a path of a snapshot, where `sha256sum -c` and `cp` see it as any other
+// file: as a hardlink, or as a relative symlink into the objects directory on filesystems without
+// hardlinks, or as a plain copy (which saves nothing, but keeps the export working) on filesystems
+// with neither
+pub fn link(object: &Utf8PathBuf, destination: &Utf8PathBuf) -> io::Result<()> {
+    if fs::hard_link(object, destination).is_ok() {
+        return Ok(());
+    }
+
+    // relative, so that the container can be moved or mounted elsewhere
+    let depth = destination.components().count() - 1;
+    let container_depth = object.components().count() - 2;
+    let mut relative = Utf8PathBuf::new();
+    for _ in container_depth..depth {
+        relative.push("..");
+    }
+    relative.push(OBJECTS_DIRNAME);
+    relative.push(object.file_name().expect("objects have a file name"));
+    if symlink(&relative, destination).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(object, destination)?;
+    Ok(())
+}
+
+// Lists the objects of a container whose content does not match their name
+pub fn corrupted(container: &Utf8PathBuf) -> io::Result<Vec<String>> {
+    let mut corrupted = Vec::new();
+    for entry in fs::read_dir(container.join(OBJECTS_DIRNAME))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        // leftovers of an interrupted export, never linked anywhere
+        if name.ends_with(".tmp") {
+            continue;
+        }
+
+        let content = fs::read(entry.path())?;
+        if sha256::digest(content) != name {
+            corrupted.push(name);
+        }
+    }
+    corrupted.sort();
+
+    Ok(corrupted)
+}
+
+// Lists the objects of a container that none of the given snapshots holds anymore, and the
+// leftovers of interrupted exports. The checksums of a snapshot name the objects it holds, whether
+// they are linked or copied into it, an object being named by the checksum of its content
+pub fn unreferenced(container: &Utf8PathBuf, snapshots: &[Utf8PathBuf]) -> io::Result<Vec<String>> {
+    let mut referenced = Vec::new();
+    for name in snapshots {
+        let sums =
+            snapshot::read_file(&container.join(name), &Utf8PathBuf::from("sha256sums.txt"))?;
+        for line in String::from_utf8_lossy(&sums).lines() {
+            if let Some((digest, _)) = line.split_once("  ") {
+                referenced.push(digest.to_string());
+            }
+        }
+    }
+
+    let mut unreferenced = Vec::new();
+    for entry in fs::read_dir(container.join(OBJECTS_DIRNAME))? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if !referenced.contains(&name) {
+            unreferenced.push(name);
+        }
+    }
+    unreferenced.sort();
+
+    Ok(unreferenced)
+}
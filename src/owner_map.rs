@@ -0,0 +1,109 @@
+use std::fs;
+
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use crate::chown_spec::ChownSpec;
+
+#[derive(Error, Debug)]
+pub enum OwnerMapError {
+    #[error(
+        "'{0}' is not a valid owner mapping (expected from=to, with both sides a user, a :group or a user:group, e.g. alice=asmith or 1000:1000=2001:2001)"
+    )]
+    InvalidRule(String),
+
+    #[error("{0} '{1}' is mapped more than once")]
+    DuplicateRule(&'static str, String),
+
+    #[error("failed to read owner map at '{0}'\n{1}")]
+    Read(Utf8PathBuf, std::io::Error),
+
+    #[error("invalid owner map at '{0}', line {1}\n{2}")]
+    InvalidFile(Utf8PathBuf, usize, Box<OwnerMapError>),
+}
+
+// Renames applied to the users and groups of owner specs, so that a snapshot can be restored onto a
+// host where the same people have other names or ids
+#[derive(Default)]
+pub struct OwnerMap {
+    users: Vec<(String, String)>,
+    groups: Vec<(String, String)>,
+}
+impl OwnerMap {
+    fn insert(
+        rules: &mut Vec<(String, String)>,
+        what: &'static str,
+        from: &str,
+        to: &str,
+    ) -> Result<(), OwnerMapError> {
+        if rules.iter().any(|(f, _)| f == from) {
+            return Err(OwnerMapError::DuplicateRule(what, from.to_string()));
+        }
+        rules.push((from.to_string(), to.to_string()));
+        Ok(())
+    }
+
+    // Adds a `from=to` rule. Both sides must have the same shape: a user, a `:group`, or a
+    // `user:group` (which maps the user and the group independently)
+    pub fn add_rule(&mut self, rule: &str) -> Result<(), OwnerMapError> {
+        let invalid = || OwnerMapError::InvalidRule(rule.to_string());
+
+        let (from, to) = rule.split_once('=').ok_or_else(invalid)?;
+        let from: ChownSpec = from.parse().map_err(|_| invalid())?;
+        let to: ChownSpec = to.parse().map_err(|_| invalid())?;
+        if from.user().is_some() != to.user().is_some()
+            || from.group().is_some() != to.group().is_some()
+        {
+            return Err(invalid());
+        }
+
+        if let (Some(from), Some(to)) = (from.user(), to.user()) {
+            Self::insert(&mut self.users, "user", from, to)?;
+        }
+        if let (Some(from), Some(to)) = (from.group(), to.group()) {
+            Self::insert(&mut self.groups, "group", from, to)?;
+        }
+
+        Ok(())
+    }
+
+    // Adds the rules of a mapping file: one `from=to` rule per line, blank lines and '#' comments
+    // being ignored
+    pub fn add_file(&mut self, path: &Utf8PathBuf) -> Result<(), OwnerMapError> {
+        let content = fs::read_to_string(path).map_err(|e| OwnerMapError::Read(path.clone(), e))?;
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            self.add_rule(line)
+                .map_err(|e| OwnerMapError::InvalidFile(path.clone(), i + 1, Box::new(e)))?;
+        }
+
+        Ok(())
+    }
+
+    fn lookup<'a>(rules: &'a [(String, String)], name: &'a str) -> &'a str {
+        rules
+            .iter()
+            .find(|(from, _)| from == name)
+            .map_or(name, |(_, to)| to.as_str())
+    }
+
+    pub fn apply(&self, spec: &ChownSpec) -> ChownSpec {
+        let user = spec.user().map(|u| Self::lookup(&self.users, u));
+        let group = spec.group().map(|g| Self::lookup(&self.groups, g));
+
+        let mapped = match (user, group) {
+            (Some(user), Some(group)) => format!("{user}:{group}"),
+            (Some(user), None) if spec.login_group() => format!("{user}:"),
+            (Some(user), None) => user.to_string(),
+            (None, Some(group)) => format!(":{group}"),
+            (None, None) => unreachable!("owner specs name a user or a group"),
+        };
+        mapped
+            .parse()
+            .expect("names taken from owner specs form a valid owner spec")
+    }
+}
@@ -0,0 +1,131 @@
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::safe_fs::{self, SafeFsError};
+
+// What an import or an export run with --dry-run would do, in the order it would do it
+pub enum Action {
+    Mkdir(Utf8PathBuf),
+    Create(Utf8PathBuf),
+    // already there with the same content, left as it is
+    Identical(Utf8PathBuf),
+    // something the real run would stop at
+    Conflict(Utf8PathBuf, String),
+    Chmod(Utf8PathBuf, u32),
+    Chown(Utf8PathBuf, String),
+    Symlink(Utf8PathBuf, Utf8PathBuf),
+    Pipe(Utf8PathBuf, String),
+    Encrypt(Utf8PathBuf, String),
+    GenerateSidecar(Utf8PathBuf),
+    // a hook or a command, with its command line
+    Run(String, String),
+    Snapshot(Utf8PathBuf),
+}
+impl Action {
+    fn describe(&self) -> String {
+        match self {
+            Action::Mkdir(path) => format!("mkdir     '{path}'"),
+            Action::Create(path) => format!("create    '{path}'"),
+            Action::Identical(path) => format!("identical '{path}' (left as it is)"),
+            Action::Conflict(path, reason) => {
+                format!("CONFLICT  '{path}'\n    {}", reason.replace('\n', "\n    "))
+            }
+            Action::Chmod(path, mode) => format!("chmod     {mode:04o} '{path}'"),
+            Action::Chown(path, owner) => format!("chown     {owner} '{path}'"),
+            Action::Symlink(path, destination) => format!("symlink   '{path}' -> '{destination}'"),
+            Action::Pipe(path, name) => format!("pipe      '{path}' to command '{name}'"),
+            Action::Encrypt(path, stored) => format!("encrypt   '{path}' as '{stored}'"),
+            Action::GenerateSidecar(path) => format!("generate  '{path}' (missing sidecar)"),
+            Action::Run(what, command) => format!("run       {what} ({command})"),
+            Action::Snapshot(path) => format!("snapshot  '{path}'"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Plan {
+    actions: Vec<Action>,
+    // directories the plan already creates, so that the secrets sharing one list it once
+    dirs: Vec<Utf8PathBuf>,
+}
+impl Plan {
+    pub fn push(&mut self, action: Action) {
+        self.actions.push(action);
+    }
+
+    // The missing ancestors of a path relative to root, created with mode 755
+    pub fn create_parents(&mut self, root: &Utf8PathBuf, rel_path: &Utf8Path) {
+        let Some(parent) = rel_path.parent() else {
+            return;
+        };
+        let mut ancestors: Vec<_> = parent
+            .ancestors()
+            .filter(|a| !a.as_str().is_empty())
+            .collect();
+        ancestors.reverse();
+
+        for ancestor in ancestors {
+            let path = root.join(ancestor);
+            if path.exists() || self.dirs.contains(&path) {
+                continue;
+            }
+            self.dirs.push(path.clone());
+            self.push(Action::Mkdir(path.clone()));
+            self.push(Action::Chmod(path, 0o755));
+        }
+    }
+
+    // Records what safe_fs would do at path: create it, leave it as it is, or refuse to
+    pub fn record(
+        &mut self,
+        path: &Utf8PathBuf,
+        checked: Result<safe_fs::Written, SafeFsError>,
+    ) -> safe_fs::Written {
+        let (action, written) = match checked {
+            Ok(written @ safe_fs::Written::Created) => (Action::Create(path.clone()), written),
+            Ok(written @ safe_fs::Written::Unchanged) => (Action::Identical(path.clone()), written),
+            // nothing changes at a conflicting path
+            Err(e) => (
+                Action::Conflict(path.clone(), e.to_string()),
+                safe_fs::Written::Unchanged,
+            ),
+        };
+        self.push(action);
+
+        written
+    }
+
+    pub fn conflicts(&self) -> usize {
+        self.actions
+            .iter()
+            .filter(|a| matches!(a, Action::Conflict(..)))
+            .count()
+    }
+
+    pub fn print(&self, title: &str) {
+        println!("{title}");
+        for action in &self.actions {
+            println!("  {}", action.describe());
+        }
+        println!();
+
+        let count = |f: fn(&Action) -> bool| self.actions.iter().filter(|a| f(a)).count();
+        let summary = [
+            (count(|a| matches!(a, Action::Create(_))), "to create"),
+            (count(|a| matches!(a, Action::Identical(_))), "identical"),
+            (count(|a| matches!(a, Action::Encrypt(..))), "to encrypt"),
+            (
+                count(|a| matches!(a, Action::GenerateSidecar(_))),
+                "sidecars to generate",
+            ),
+            (self.conflicts(), "conflicts"),
+        ]
+        .into_iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, what)| format!("{n} {what}"))
+        .collect::<Vec<_>>();
+        if !summary.is_empty() {
+            println!("{}", summary.join(", "));
+            println!();
+        }
+    }
+}
@@ -0,0 +1,11 @@
+use std::io::Write;
+
+pub fn confirm(prompt: &str) -> std::io::Result<bool> {
+    print!("{prompt} [y/N]: ");
+    std::io::stdout().flush()?;
+
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+
+    Ok(matches!(input.trim(), "y" | "Y"))
+}
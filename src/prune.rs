@@ -0,0 +1,409 @@
+use std::{fs, io::Write};
+
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use crate::checksum;
+use crate::date;
+use crate::objects;
+use crate::snapshot;
+
+// Snapshots of a container that prune never removes, one name per line
+pub const PINS_FILENAME: &str = ".pins";
+
+#[derive(Error, Debug)]
+pub enum PruneError {
+    #[error("container path '{0}' does not exist")]
+    MissingContainerPath(Utf8PathBuf),
+    #[error("container path '{0}' is not a directory")]
+    ContainerNotDir(Utf8PathBuf),
+
+    #[error("'{0}' is not a valid age (expected e.g. 90d, 12w, 6m or 1y)")]
+    InvalidAge(String),
+
+    #[error(
+        "no retention rule given, pass at least one of --keep-last, --keep-daily, --keep-weekly, --keep-monthly or --older-than"
+    )]
+    NoRule,
+
+    #[error("failed to list snapshots in container '{0}'\n{1}")]
+    ListSnapshots(Utf8PathBuf, std::io::Error),
+
+    #[error("no snapshot of container '{0}' passes verification, refusing to prune it")]
+    NoVerifiedSnapshot(Utf8PathBuf),
+
+    #[error("failed to read the pinned snapshots of container '{0}'\n{1}")]
+    ReadPins(Utf8PathBuf, std::io::Error),
+    #[error("failed to write the pinned snapshots of container '{0}'\n{1}")]
+    WritePins(Utf8PathBuf, std::io::Error),
+
+    #[error("container '{0}' holds no snapshot named '{1}'")]
+    UnknownSnapshot(Utf8PathBuf, String),
+    #[error("snapshot '{1}' of container '{0}' is not pinned")]
+    NotPinned(Utf8PathBuf, String),
+
+    #[error("failed to remove snapshot '{0}'\n{1}")]
+    RemoveSnapshot(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to list the partial snapshots of container '{0}'\n{1}")]
+    ListPartials(Utf8PathBuf, std::io::Error),
+    #[error("failed to remove partial snapshot '{0}'\n{1}")]
+    RemovePartial(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to list the unreferenced objects of container '{0}'\n{1}")]
+    ListObjects(Utf8PathBuf, std::io::Error),
+    #[error("failed to remove object '{0}'\n{1}")]
+    RemoveObject(Utf8PathBuf, std::io::Error),
+}
+impl PruneError {
+    fn list_snapshots(container: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::ListSnapshots(container.clone(), e)
+    }
+
+    fn remove_snapshot(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::RemoveSnapshot(path.clone(), e)
+    }
+
+    fn remove_partial(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::RemovePartial(path.clone(), e)
+    }
+
+    fn remove_object(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::RemoveObject(path.clone(), e)
+    }
+}
+
+// How many snapshots to keep: the newest ones, and the newest of each of the most recent days,
+// weeks and months holding snapshots
+pub struct Retention {
+    pub last: Option<usize>,
+    pub daily: Option<usize>,
+    pub weekly: Option<usize>,
+    pub monthly: Option<usize>,
+}
+impl Retention {
+    fn is_empty(&self) -> bool {
+        self.last.is_none()
+            && self.daily.is_none()
+            && self.weekly.is_none()
+            && self.monthly.is_none()
+    }
+}
+
+struct Candidate {
+    name: Utf8PathBuf,
+    at: i64,
+    reasons: Vec<&'static str>,
+}
+
+fn check_container(container: String) -> Result<Utf8PathBuf, PruneError> {
+    let path = Utf8PathBuf::from(&container);
+    if !path.exists() {
+        return Err(PruneError::MissingContainerPath(path));
+    } else if !path.is_dir() {
+        return Err(PruneError::ContainerNotDir(path));
+    }
+
+    Ok(path)
+}
+
+fn read_pins(container: &Utf8PathBuf) -> Result<Vec<String>, PruneError> {
+    match fs::read_to_string(container.join(PINS_FILENAME)) {
+        Ok(content) => Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(PruneError::ReadPins(container.clone(), e)),
+    }
+}
+
+fn write_pins(container: &Utf8PathBuf, pins: &[String]) -> Result<(), PruneError> {
+    let path = container.join(PINS_FILENAME);
+    let result = match pins.is_empty() {
+        true => fs::remove_file(&path),
+        false => fs::write(
+            &path,
+            pins.iter()
+                .map(|pin| format!("{pin}\n"))
+                .collect::<String>(),
+        ),
+    };
+
+    result.map_err(|e| PruneError::WritePins(container.clone(), e))
+}
+
+// Marks the newest snapshot of each of the `count` most recent periods holding snapshots, the
+// candidates being sorted newest first
+fn keep_per_period(
+    candidates: &mut [Candidate],
+    count: usize,
+    period: impl Fn(i64) -> (i64, i64),
+    reason: &'static str,
+) {
+    let mut last_period = None;
+    let mut kept = 0;
+    for candidate in candidates.iter_mut() {
+        if kept == count {
+            break;
+        }
+        let candidate_period = period(candidate.at);
+        if last_period != Some(candidate_period) {
+            last_period = Some(candidate_period);
+            candidate.reasons.push(reason);
+            kept += 1;
+        }
+    }
+}
+
+fn day(seconds: i64) -> (i64, i64) {
+    (seconds.div_euclid(86400), 0)
+}
+
+// Weeks start on monday, the epoch being a thursday
+fn week(seconds: i64) -> (i64, i64) {
+    ((seconds.div_euclid(86400) + 3).div_euclid(7), 0)
+}
+
+fn month(seconds: i64) -> (i64, i64) {
+    let (year, month, _) = date::civil_from_days(seconds.div_euclid(86400));
+    (year, month)
+}
+
+// Checks the outer `.sha256` of an archive, then the checksums of the files it holds
+fn verify(snapshot: &Utf8PathBuf) -> Result<(), String> {
+    let opened = snapshot::open(snapshot).map_err(|e| e.to_string())?;
+    checksum::verify_checksums(&opened.dir).map_err(|e| e.to_string())
+}
+
+// Marks the newest snapshot that passes verification, which is never removed: a corrupted newer
+// snapshot must not leave the container without a single restorable one
+fn keep_newest_verified(
+    container: &Utf8PathBuf,
+    candidates: &mut [Candidate],
+) -> Result<(), PruneError> {
+    for candidate in candidates.iter_mut() {
+        print!("Verifying {}... ", candidate.name);
+        std::io::stdout().flush().unwrap();
+        match verify(&container.join(&candidate.name)) {
+            Ok(()) => {
+                println!("ok");
+                candidate.reasons.push("newest verified");
+                return Ok(());
+            }
+            Err(e) => {
+                println!("FAILED");
+                println!("  {e}");
+            }
+        }
+    }
+
+    Err(PruneError::NoVerifiedSnapshot(container.clone()))
+}
+
+fn prune_partials(container: &Utf8PathBuf, dry_run: bool) -> Result<(), PruneError> {
+    let partials = snapshot::list_partials(container)
+        .map_err(|e| PruneError::ListPartials(container.clone(), e))?;
+    for partial in &partials {
+        let name = partial.file_name().unwrap_or(partial.as_str());
+        if dry_run {
+            println!("would remove partial {name}");
+            continue;
+        }
+
+        print!("Removing partial {name}... ");
+        std::io::stdout().flush().unwrap();
+        snapshot::remove(partial)
+            .map_err(PruneError::remove_partial(partial))
+            .inspect_err(|_| println!("error"))?;
+        println!("ok");
+    }
+
+    Ok(())
+}
+
+// Removes the objects only held by the removed snapshots, once these are gone
+fn prune_objects(
+    container: &Utf8PathBuf,
+    kept: &[Utf8PathBuf],
+    dry_run: bool,
+) -> Result<(), PruneError> {
+    let unreferenced = objects::unreferenced(container, kept)
+        .map_err(|e| PruneError::ListObjects(container.clone(), e))?;
+    if unreferenced.is_empty() {
+        return Ok(());
+    }
+
+    if dry_run {
+        println!(
+            "would remove {} objects no kept snapshot holds",
+            unreferenced.len()
+        );
+        return Ok(());
+    }
+
+    print!("Removing {} unreferenced objects... ", unreferenced.len());
+    std::io::stdout().flush().unwrap();
+    for name in &unreferenced {
+        let path = container.join(objects::OBJECTS_DIRNAME).join(name);
+        fs::remove_file(&path)
+            .map_err(PruneError::remove_object(&path))
+            .inspect_err(|_| println!("error"))?;
+    }
+    println!("ok");
+
+    Ok(())
+}
+
+pub fn prune(
+    container: String,
+    retention: Retention,
+    older_than: Option<String>,
+    dry_run: bool,
+) -> Result<(), PruneError> {
+    let container = check_container(container)?;
+
+    let older_than = match older_than {
+        Some(age) => Some(date::parse_days(&age).ok_or(PruneError::InvalidAge(age))?),
+        None => None,
+    };
+    if retention.is_empty() && older_than.is_none() {
+        return Err(PruneError::NoRule);
+    }
+
+    let pins = read_pins(&container)?;
+    let mut candidates: Vec<Candidate> = snapshot::list_snapshots(&container)
+        .map_err(PruneError::list_snapshots(&container))?
+        .into_iter()
+        .filter_map(|name| {
+            let at = snapshot::timestamp(name.as_str())?;
+            Some(Candidate {
+                name,
+                at,
+                reasons: Vec::new(),
+            })
+        })
+        .collect();
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.at));
+
+    if !candidates.is_empty() {
+        keep_newest_verified(&container, &mut candidates)?;
+        println!();
+    }
+
+    if let Some(last) = retention.last {
+        for candidate in candidates.iter_mut().take(last) {
+            candidate.reasons.push("last");
+        }
+    }
+    if let Some(daily) = retention.daily {
+        keep_per_period(&mut candidates, daily, day, "daily");
+    }
+    if let Some(weekly) = retention.weekly {
+        keep_per_period(&mut candidates, weekly, week, "weekly");
+    }
+    if let Some(monthly) = retention.monthly {
+        keep_per_period(&mut candidates, monthly, month, "monthly");
+    }
+    // snapshots younger than the age are kept whatever the other rules say
+    if let Some(older_than) = older_than {
+        let limit = date::now() - older_than * 86400;
+        for candidate in candidates.iter_mut().filter(|c| c.at > limit) {
+            candidate.reasons.push("recent");
+        }
+    }
+    for candidate in candidates.iter_mut() {
+        if pins.iter().any(|pin| pin == candidate.name.as_str()) {
+            candidate.reasons.push("pinned");
+        }
+    }
+
+    let (kept, removed): (Vec<_>, Vec<_>) =
+        candidates.into_iter().partition(|c| !c.reasons.is_empty());
+
+    for candidate in &kept {
+        println!(
+            "keep    {}  ({})",
+            candidate.name,
+            candidate.reasons.join(", ")
+        );
+    }
+    for candidate in &removed {
+        match dry_run {
+            true => println!("remove  {}", candidate.name),
+            false => {
+                let path = container.join(&candidate.name);
+                print!("Removing {}... ", candidate.name);
+                std::io::stdout().flush().unwrap();
+                snapshot::remove(&path)
+                    .map_err(PruneError::remove_snapshot(&path))
+                    .inspect_err(|_| println!("error"))?;
+                println!("ok");
+            }
+        }
+    }
+
+    prune_partials(&container, dry_run)?;
+
+    if objects::is_enabled(&container) {
+        let kept: Vec<Utf8PathBuf> = kept.into_iter().map(|c| c.name).collect();
+        prune_objects(&container, &kept, dry_run)?;
+    }
+
+    println!();
+    match dry_run {
+        true => println!("Dry run, {} snapshots would be removed", removed.len()),
+        false => println!("Pruned {} snapshots", removed.len()),
+    }
+
+    Ok(())
+}
+
+// Snapshots are given by name, or by their path inside the container
+fn snapshot_name(container: &Utf8PathBuf, snapshot: String) -> Result<String, PruneError> {
+    let path = Utf8PathBuf::from(&snapshot);
+    let name = path.file_name().unwrap_or(snapshot.as_str()).to_string();
+    let snapshots =
+        snapshot::list_snapshots(container).map_err(PruneError::list_snapshots(container))?;
+    if !snapshots.iter().any(|s| s.as_str() == name) {
+        return Err(PruneError::UnknownSnapshot(container.clone(), name));
+    }
+
+    Ok(name)
+}
+
+pub fn pin(container: String, snapshot: String) -> Result<(), PruneError> {
+    let container = check_container(container)?;
+    let name = snapshot_name(&container, snapshot)?;
+
+    let mut pins = read_pins(&container)?;
+    if !pins.contains(&name) {
+        pins.push(name.clone());
+        pins.sort();
+        write_pins(&container, &pins)?;
+    }
+    println!("Pinned {name}, prune will keep it");
+
+    Ok(())
+}
+
+pub fn unpin(container: String, snapshot: String) -> Result<(), PruneError> {
+    let container = check_container(container)?;
+    let name = Utf8PathBuf::from(&snapshot)
+        .file_name()
+        .unwrap_or(snapshot.as_str())
+        .to_string();
+
+    // a pinned snapshot removed by hand can still be unpinned
+    let mut pins = read_pins(&container)?;
+    if !pins.contains(&name) {
+        return Err(PruneError::NotPinned(container, name));
+    }
+    pins.retain(|pin| pin != &name);
+    write_pins(&container, &pins)?;
+    println!("Unpinned {name}");
+
+    Ok(())
+}
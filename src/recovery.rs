@@ -0,0 +1,469 @@
+use camino::Utf8PathBuf;
+
+use crate::manifest;
+use crate::utf8path_ext::ExtraUtf8Path;
+
+// Recovery kit written into every snapshot, for the day secs-man (and its README) are not at hand:
+// a RECOVERY.md telling how to restore the snapshot, and a POSIX restore.sh doing what import does
+// with nothing but sha256sum, age, chmod, chown and mkdir (and ln and readlink, for symlinks). The
+// script of a
+// snapshot with sealed checksums holds their plaintext digests, so it is encrypted as restore.sh.age
+pub const README_FILENAME: &str = "RECOVERY.md";
+pub const SCRIPT_FILENAME: &str = "restore.sh";
+pub const ENCRYPTED_SCRIPT_FILENAME: &str = "restore.sh.age";
+
+pub enum Content {
+    // decrypted into the secrets directory, next to its sidecar
+    File { digest: String, sidecar: String },
+    Link { destination: Utf8PathBuf },
+    // piped to a command of the host config, which the script can't run
+    Command { name: String },
+}
+
+// A secret as import restores it
+pub struct Entry {
+    pub path: Utf8PathBuf,
+    // where it is stored in the snapshot, before the `.age` extension
+    pub stored: Utf8PathBuf,
+    pub content: Content,
+    pub mode: Option<u32>,
+    pub owner: Option<String>,
+    // numeric ids to fall back to when the names of a captured owner do not exist on the host
+    pub fallback_owner: Option<String>,
+    // captured metadata that import restores, but the script can't
+    pub unrestored: Vec<&'static str>,
+}
+impl Entry {
+    // The owner and mode import restores a secret with: the ones of the manifest, or else the ones
+    // captured at export (see import's resolve_owner)
+    pub fn new(
+        secret: &manifest::Secret,
+        captured: Option<&manifest::Captured>,
+        content: Content,
+    ) -> Self {
+        let default = manifest::Captured::default();
+        let captured = captured.unwrap_or(&default);
+        let captured_ids = captured.ids.map(|(uid, gid)| format!("{uid}:{gid}"));
+        let (owner, fallback_owner) = match (&secret.owner, &captured.owner) {
+            (Some(owner), _) => (Some(owner.as_str().to_string()), None),
+            (None, Some(names)) => (Some(names.as_str().to_string()), captured_ids),
+            (None, None) => (captured_ids, None),
+        };
+
+        let mut unrestored = Vec::new();
+        if captured.mtime.is_some() {
+            unrestored.push("modification time");
+        }
+        if !captured.xattrs.is_empty() {
+            unrestored.push("extended attributes");
+        }
+
+        Self {
+            path: secret.path.clone(),
+            stored: secret.stored_path(),
+            content,
+            mode: secret.mode.or(captured.mode),
+            owner,
+            fallback_owner,
+            unrestored,
+        }
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn in_target(path: &Utf8PathBuf) -> String {
+    format!("\"$TARGET\"/{}", quote(path.as_str()))
+}
+
+const SCRIPT_HEADER: &str = r#"#!/bin/sh
+# Restores the secrets of this snapshot as `secs-man import` would, with nothing but sha256sum, age,
+# chmod, chown and mkdir (and ln and readlink, for symlinks). See RECOVERY.md
+#
+# usage: sh restore.sh /path/to/secrets
+set -eu
+umask 077
+
+fail() {
+    echo "restore.sh: $1" >&2
+    exit 1
+}
+
+# Creates a missing parent directory of a secret
+dir() {
+    [ -d "$1" ] || { mkdir "$1" && chmod 755 "$1"; }
+}
+
+# Checks a restored secret against the checksum it was exported with
+check() {
+    printf '%s  %s\n' "$1" "$2" | sha256sum -c --status - || fail "'$2' does not match its checksum"
+}
+
+# Gives a file the owner names captured at export, or their numeric ids when the names do not exist
+# on this host
+own() {
+    chown "$1" "$3" 2>/dev/null || [ -z "$2" ] || chown "$2" "$3"
+}
+
+# Creates a symlink, unless the same one is already there
+link() {
+    if [ -L "$2" ]; then
+        [ "$(readlink "$2")" = "$1" ] || fail "'$2' already exists and is not a symlink to '$1'"
+    elif [ -e "$2" ]; then
+        fail "'$2' already exists and is not a symlink to '$1'"
+    else
+        ln -s "$1" "$2"
+    fi
+}
+
+# Writes the sidecar of a secret, unless the same one is already there
+sidecar() {
+    if [ -e "$1" ]; then
+        IFS= read -r line <"$1" || true
+        [ "$line" = "$2" ] || fail "'$1' already exists with another checksum"
+    else
+        printf '%s\n' "$2" >"$1"
+    fi
+}
+
+[ $# -eq 1 ] || fail "usage: sh restore.sh /path/to/secrets"
+case $0 in
+*/*) SNAPSHOT=${0%/*} ;;
+*) SNAPSHOT=. ;;
+esac
+TARGET=$1
+[ -d "$TARGET" ] || fail "secrets directory '$TARGET' does not exist"
+
+# the manifest is only restored into a secrets directory that has none
+RESTORE_MANIFEST=no
+[ -e "$TARGET/.secrets-manifest" ] || RESTORE_MANIFEST=yes
+
+echo "Verifying source integrity..."
+(cd "$SNAPSHOT" && sha256sum -c --quiet sha256sums.txt) || fail "the snapshot is corrupted"
+"#;
+
+fn render_owner(entry: &Entry, file: &str, out: &mut String) {
+    let Some(owner) = &entry.owner else {
+        return;
+    };
+    match &entry.fallback_owner {
+        Some(ids) => out.push_str(&format!("own {} {} {file}\n", quote(owner), quote(ids))),
+        None => out.push_str(&format!("chown {} {file}\n", quote(owner))),
+    }
+}
+
+fn render_entry(entry: &Entry, out: &mut String) {
+    let file = in_target(&entry.path);
+    out.push_str(&format!(
+        "\necho {}\n",
+        quote(&format!("importing '{}'...", entry.path))
+    ));
+
+    if let Content::Command { name } = &entry.content {
+        out.push_str(&format!(
+            "# piped to command '{name}' of the host config: age -d \"$SNAPSHOT\"/{} | <command>\n",
+            quote(entry.stored.add_extension("age").as_str())
+        ));
+        out.push_str(&format!(
+            "echo {}\n",
+            quote(&format!("  skipped, restored through command '{name}'"))
+        ));
+        return;
+    }
+
+    if let Some(parent) = entry.path.parent() {
+        let mut ancestors: Vec<_> = parent
+            .ancestors()
+            .filter(|a| !a.as_str().is_empty())
+            .collect();
+        ancestors.reverse();
+        for ancestor in ancestors {
+            out.push_str(&format!("dir {}\n", in_target(&ancestor.to_path_buf())));
+        }
+    }
+
+    match &entry.content {
+        Content::Link { destination } => {
+            out.push_str(&format!("link {} {file}\n", quote(destination.as_str())));
+            if let Some(owner) = &entry.owner {
+                out.push_str(&format!("chown -h {} {file}\n", quote(owner)));
+            }
+        }
+        Content::File { digest, sidecar } => {
+            let stored = format!(
+                "\"$SNAPSHOT\"/{}",
+                quote(entry.stored.add_extension("age").as_str())
+            );
+            out.push_str(&format!("[ -e {file} ] || age -d -o {file} {stored}\n"));
+            out.push_str(&format!("check {digest} {file}\n"));
+            if let Some(mode) = entry.mode {
+                out.push_str(&format!("chmod {mode:04o} {file}\n"));
+            }
+            render_owner(entry, &file, out);
+
+            let sidecar_file = in_target(&entry.path.add_extension("sha256"));
+            out.push_str(&format!("sidecar {sidecar_file} {}\n", quote(sidecar)));
+            out.push_str(&format!("chmod 0600 {sidecar_file}\n"));
+            render_owner(entry, &sidecar_file, out);
+        }
+        Content::Command { .. } => unreachable!("handled above"),
+    }
+}
+
+pub fn render_script(entries: &[Entry], manifest_content: &str) -> String {
+    let mut out = SCRIPT_HEADER.to_string();
+    for entry in entries {
+        render_entry(entry, &mut out);
+    }
+
+    out.push_str(&format!(
+        "\nif [ \"$RESTORE_MANIFEST\" = yes ]; then\n    echo \"restoring manifest...\"\n    printf '%s' {} >\"$TARGET/{}\"\n    chmod 0600 \"$TARGET/{}\"\nfi\n",
+        quote(manifest_content),
+        manifest::MANIFEST_FILENAME,
+        manifest::MANIFEST_FILENAME
+    ));
+    out.push_str("\necho \"Import completed successfully!\"\n");
+
+    out
+}
+
+fn notes(entry: &Entry) -> String {
+    let mut notes = Vec::new();
+    match &entry.content {
+        Content::Link { destination } => notes.push(format!("symlink to `{destination}`")),
+        Content::Command { name } => notes.push(format!(
+            "piped to command `{name}` of the host config, not restored by restore.sh"
+        )),
+        Content::File { .. } => {}
+    }
+    if !entry.unrestored.is_empty() {
+        notes.push(format!(
+            "{} not restored by restore.sh",
+            entry.unrestored.join(" and ")
+        ));
+    }
+
+    notes.join(", ")
+}
+
+// The kit of a snapshot with a private layout must not tell which secrets it holds, so its
+// RECOVERY.md has no list of secrets
+pub fn render_readme(entries: &[Entry], sealed_checksums: bool, private_layout: bool) -> String {
+    let mut out = format!(
+        r#"# Recovering this snapshot
+
+This snapshot was exported by secs-man {version}, whose executable lies next to this file. From
+this directory, it restores the snapshot with
+
+    sudo ./secs-man import . /path/to/secrets
+
+Without it, `restore.sh` does what `import` does for this snapshot, with nothing but `sha256sum`,
+`age`, `chmod`, `chown` and `mkdir` (and `ln` and `readlink`, for symlinks)
+
+    sudo sh restore.sh /path/to/secrets
+
+The secrets directory must exist, and `age` asks for the passphrase once per secret. The script:
+
+1. checks every file of the snapshot against `sha256sums.txt`
+2. creates the missing parent directories of each secret (mode 755), decrypts the secret (created
+   with mode 600), checks it against the checksum it was exported with, gives it its mode and
+   owner, and writes its `.sha256` sidecar (mode 600, same owner)
+3. restores the manifest, if the secrets directory has none
+
+Secrets already in the secrets directory are left as they are, as long as their content matches.
+Secrets annotated with `at=` are restored into the secrets directory, as `import` does without
+`--deploy`.
+"#,
+        version = env!("CARGO_PKG_VERSION")
+    );
+
+    if sealed_checksums {
+        out.push_str(
+            r#"
+The checksums of the secrets of this snapshot are sealed, and the script holds them, so it is
+encrypted with the same passphrase as the secrets. Decrypt it first with
+
+    age -d -o restore.sh restore.sh.age
+"#,
+        );
+    }
+
+    if private_layout {
+        out.push_str(
+            r#"
+The layout of this snapshot is private: its secrets are stored under random names, and only the
+encrypted script tells which is which.
+"#,
+        );
+        return out;
+    }
+
+    out.push_str("\n## Secrets\n\n| path | mode | owner | notes |\n|---|---|---|---|\n");
+    for entry in entries {
+        let mode = entry.mode.map(|m| format!("{m:04o}")).unwrap_or_default();
+        let owner = match (&entry.owner, &entry.fallback_owner) {
+            (Some(owner), Some(ids)) => format!("{owner} (or {ids})"),
+            (Some(owner), None) => owner.clone(),
+            (None, _) => String::new(),
+        };
+        out.push_str(&format!(
+            "| `{}` | {mode} | {owner} | {} |\n",
+            entry.path,
+            notes(entry)
+        ));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        fs,
+        os::unix::fs::{MetadataExt, PermissionsExt, symlink},
+        process::Command,
+    };
+
+    use camino::Utf8PathBuf;
+
+    use crate::crypto;
+    use crate::export;
+    use crate::import;
+    use crate::test_support::{self, PASSPHRASE, TempDir};
+
+    // Stands in for `age -d [-o file] file`, with the plaintexts decrypted beforehand into $ORACLE
+    // under the digest of their ciphertext, as the real one would ask for the passphrase on a tty
+    const FAKE_AGE: &str = r#"#!/bin/sh
+[ "$1" = -d ] || exit 1
+shift
+out=
+if [ "$1" = -o ]; then
+    out=$2
+    shift 2
+fi
+digest=$(sha256sum "$1" | cut -d ' ' -f 1)
+if [ -n "$out" ]; then cat "$ORACLE/$digest" >"$out"; else cat "$ORACLE/$digest"; fi
+"#;
+
+    // Type, mode, owner and content (or link target) of every path under a directory
+    fn tree(root: &Utf8PathBuf) -> BTreeMap<Utf8PathBuf, (&'static str, String, u32, String)> {
+        let mut tree = BTreeMap::new();
+        let mut dirs = vec![root.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in dir.read_dir_utf8().unwrap() {
+                let path = entry.unwrap().path().to_path_buf();
+                let metadata = fs::symlink_metadata(&path).unwrap();
+                let (kind, content) = if metadata.is_symlink() {
+                    ("link", path.read_link_utf8().unwrap().to_string())
+                } else if metadata.is_dir() {
+                    dirs.push(path.clone());
+                    ("dir", String::new())
+                } else {
+                    ("file", fs::read_to_string(&path).unwrap())
+                };
+                let mode = format!("{:04o}", metadata.permissions().mode() & 0o7777);
+                let rel_path = path.strip_prefix(root).unwrap().to_path_buf();
+                tree.insert(rel_path, (kind, mode, metadata.uid(), content));
+            }
+        }
+
+        tree
+    }
+
+    // Decrypts every `.age` file of a snapshot into the oracle of the fake age
+    fn fill_oracle(dir: &Utf8PathBuf, oracle: &Utf8PathBuf) {
+        for entry in dir.read_dir_utf8().unwrap() {
+            let path = entry.unwrap().path().to_path_buf();
+            if path.is_dir() {
+                fill_oracle(&path, oracle);
+            } else if path.extension() == Some("age") {
+                let ciphertext = fs::read(&path).unwrap();
+                let plaintext = crypto::decrypt(&ciphertext, PASSPHRASE).unwrap();
+                fs::write(oracle.join(sha256::digest(&ciphertext)), plaintext).unwrap();
+            }
+        }
+    }
+
+    // restore.sh claims to do what import does: both restore the same snapshot into trees with
+    // the same paths, content, link targets, modes and owners, the manifest included
+    fn assert_restore_matches_import(options: impl FnOnce(&mut export::ExportOptions)) {
+        let tmp = TempDir::new();
+        let source = tmp.secrets_dir(
+            "source",
+            &[
+                ("a", "first\n"),
+                ("dir/b", "second\n"),
+                ("dir/deep/c", "third\n"),
+            ],
+        );
+        symlink("dir/b", source.join("link")).unwrap();
+        fs::write(
+            source.join(crate::manifest::MANIFEST_FILENAME),
+            "a mode=0640\ndir/b mode=0600\ndir/deep/c\nlink kind=symlink\n",
+        )
+        .unwrap();
+        let mut export_options = tmp.export_options();
+        options(&mut export_options);
+        let snapshot = test_support::export(&source, &tmp.dir("container"), export_options);
+
+        // imported first, as the script of a sealed snapshot is decrypted next to it
+        let imported = tmp.dir("imported");
+        import::import(
+            snapshot.to_string(),
+            imported.to_string(),
+            Vec::new(),
+            test_support::encrypted(),
+            tmp.import_options(),
+        )
+        .unwrap();
+
+        let script = snapshot.join(super::SCRIPT_FILENAME);
+        let encrypted_script = snapshot.join(super::ENCRYPTED_SCRIPT_FILENAME);
+        if encrypted_script.exists() {
+            let ciphertext = fs::read(&encrypted_script).unwrap();
+            fs::write(&script, crypto::decrypt(&ciphertext, PASSPHRASE).unwrap()).unwrap();
+        }
+
+        let bin = tmp.dir("bin");
+        let fake_age = bin.join("age");
+        fs::write(&fake_age, FAKE_AGE).unwrap();
+        fs::set_permissions(&fake_age, fs::Permissions::from_mode(0o755)).unwrap();
+        let oracle = tmp.dir("oracle");
+        fill_oracle(&snapshot, &oracle);
+
+        let restored = tmp.dir("restored");
+        let status = Command::new("sh")
+            .arg(&script)
+            .arg(&restored)
+            .env("PATH", format!("{bin}:{}", std::env::var("PATH").unwrap()))
+            .env("ORACLE", &oracle)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let imported = tree(&imported);
+        assert!(imported.contains_key(&Utf8PathBuf::from("dir/deep/c.sha256")));
+        assert_eq!(tree(&restored), imported);
+    }
+
+    #[test]
+    fn restore_script_matches_import() {
+        assert_restore_matches_import(|_| {});
+    }
+
+    #[test]
+    fn sealed_restore_script_matches_import() {
+        assert_restore_matches_import(|options| options.seal_checksums = true);
+    }
+
+    #[test]
+    fn private_layout_restore_script_matches_import() {
+        assert_restore_matches_import(|options| {
+            options.seal_checksums = true;
+            options.private_layout = true;
+        });
+    }
+}
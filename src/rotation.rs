@@ -0,0 +1,317 @@
+use std::{fs, time::UNIX_EPOCH};
+
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use crate::checksum;
+use crate::date;
+use crate::layout;
+use crate::manifest;
+use crate::sealed;
+use crate::snapshot;
+use crate::utf8path_ext::ExtraUtf8Path;
+
+#[derive(Error, Debug)]
+pub enum RotationError {
+    #[error("secrets path '{0}' does not exist")]
+    MissingSourcePath(Utf8PathBuf),
+    #[error("secrets path '{0}' is not a directory")]
+    SourceNotDir(Utf8PathBuf),
+
+    #[error("container path '{0}' does not exist")]
+    MissingContainerPath(Utf8PathBuf),
+    #[error("container path '{0}' is not a directory")]
+    ContainerNotDir(Utf8PathBuf),
+
+    #[error("'{0}' is not a valid warning window (expected e.g. 14d, 2w or 1m)")]
+    InvalidWindow(String),
+
+    #[error("failed to load manifest\n{0}")]
+    LoadManifest(manifest::ManifestError),
+
+    #[error("failed to list snapshots in container '{0}'\n{1}")]
+    ListSnapshots(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to read the current checksum of '{0}'\n{1}")]
+    ReadChecksum(Utf8PathBuf, checksum::ChecksumError),
+
+    #[error("failed to read modification time of '{0}'\n{1}")]
+    ReadMtime(Utf8PathBuf, std::io::Error),
+
+    #[error(
+        "secret '{0}' is read from a command, its rotation can only be tracked across the snapshots of a container (give one)"
+    )]
+    CommandWithoutSnapshots(Utf8PathBuf),
+
+    #[error(
+        "{overdue} secrets are overdue, {due_soon} are due soon and {unknown} were last rotated at an unknown date"
+    )]
+    Overdue {
+        overdue: usize,
+        due_soon: usize,
+        unknown: usize,
+    },
+}
+impl RotationError {
+    fn read_checksum(path: &Utf8PathBuf) -> impl Fn(checksum::ChecksumError) -> Self {
+        |e| Self::ReadChecksum(path.clone(), e)
+    }
+
+    fn list_snapshots(container: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::ListSnapshots(container.clone(), e)
+    }
+
+    fn read_mtime(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::ReadMtime(path.clone(), e)
+    }
+}
+
+#[derive(PartialEq, PartialOrd)]
+enum Status {
+    Overdue,
+    DueSoon,
+    Ok,
+}
+
+// When a secret was last rotated. `exact` is false when every snapshot of the container holds the
+// current content, so the rotation happened at some unknown point before the oldest of them
+struct LastRotation {
+    at: i64,
+    exact: bool,
+}
+
+fn mtime(path: &Utf8PathBuf) -> Result<i64, RotationError> {
+    let modified = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(RotationError::read_mtime(path))?;
+
+    Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0))
+}
+
+// Reads the digest of a secret as exported in a snapshot, directory or archive
+fn exported_digest(
+    snapshot: &Utf8PathBuf,
+    secret: &manifest::Secret,
+) -> Result<String, checksum::ChecksumError> {
+    let sha_rel_path = secret.path.add_extension("sha256");
+    let sha_path = snapshot.join(&sha_rel_path);
+    let sha_content = snapshot::read_file(snapshot, &sha_rel_path)
+        .map_err(|e| checksum::ChecksumError::ReadChecksum(sha_path.clone(), e))?;
+
+    checksum::parse_digest(&String::from_utf8_lossy(&sha_content), &sha_path)
+}
+
+// Finds the oldest snapshot of the trailing run of snapshots that hold the current content of the
+// secret. If the newest snapshot already differs, the secret was rotated after it and the
+// modification time of its sidecar is the best estimate available. Secrets read from a command have
+// no sidecar in the secrets directory, their current content is the one of the newest snapshot
+fn last_rotation(
+    source: &Utf8PathBuf,
+    container: Option<&Utf8PathBuf>,
+    snapshots: &[(i64, Utf8PathBuf)],
+    secret: &manifest::Secret,
+) -> Result<LastRotation, RotationError> {
+    let (current, sidecar_rotation) = match (&secret.from_cmd, container, snapshots.last()) {
+        (None, _, _) => {
+            let sha_source = source.join(&secret.path).add_extension("sha256");
+            let current = checksum::read_digest(&sha_source)
+                .map_err(RotationError::read_checksum(&sha_source))?;
+            let at = mtime(&sha_source)?;
+            (current, LastRotation { at, exact: true })
+        }
+        (Some(_), Some(container), Some((at, newest))) => {
+            let snapshot = container.join(newest);
+            let current = exported_digest(&snapshot, secret)
+                .map_err(RotationError::read_checksum(&snapshot))?;
+            (
+                current,
+                LastRotation {
+                    at: *at,
+                    exact: true,
+                },
+            )
+        }
+        (Some(_), _, _) => return Err(RotationError::CommandWithoutSnapshots(secret.path.clone())),
+    };
+
+    let Some(container) = container else {
+        return Ok(sidecar_rotation);
+    };
+
+    let mut oldest_matching = None;
+    for (at, name) in snapshots.iter().rev() {
+        match exported_digest(&container.join(name), secret) {
+            Ok(digest) if digest == current => oldest_matching = Some(*at),
+            _ => {
+                return Ok(match oldest_matching {
+                    Some(at) => LastRotation { at, exact: true },
+                    None => sidecar_rotation,
+                });
+            }
+        }
+    }
+
+    Ok(match oldest_matching {
+        Some(at) => LastRotation { at, exact: false },
+        None => sidecar_rotation,
+    })
+}
+
+pub fn rotation_status(
+    source: String,
+    container: Option<String>,
+    within: String,
+    strict: bool,
+) -> Result<(), RotationError> {
+    let source = {
+        let path = Utf8PathBuf::from(&source);
+        if !path.exists() {
+            return Err(RotationError::MissingSourcePath(path));
+        } else if !path.is_dir() {
+            return Err(RotationError::SourceNotDir(path));
+        }
+        path
+    };
+
+    let container = match container {
+        None => None,
+        Some(container) => {
+            let path = Utf8PathBuf::from(&container);
+            if !path.exists() {
+                return Err(RotationError::MissingContainerPath(path));
+            } else if !path.is_dir() {
+                return Err(RotationError::ContainerNotDir(path));
+            }
+            Some(path)
+        }
+    };
+
+    let window = date::parse_days(&within).ok_or(RotationError::InvalidWindow(within))? * 86400;
+
+    let secrets = manifest::load(&source).map_err(RotationError::LoadManifest)?;
+
+    let mut snapshots = Vec::new();
+    let mut skipped = 0;
+    if let Some(container) = &container {
+        for name in
+            snapshot::list_snapshots(container).map_err(RotationError::list_snapshots(container))?
+        {
+            // their digests (and the names their secrets are stored under) can't be read without
+            // the passphrase
+            let snapshot = container.join(&name);
+            if sealed::is_sealed(&snapshot) || layout::is_private(&snapshot) {
+                skipped += 1;
+                continue;
+            }
+            if let Some(at) = snapshot::timestamp(name.as_str()) {
+                snapshots.push((at, name));
+            }
+        }
+        snapshots.sort();
+    }
+    if skipped > 0 {
+        println!(
+            "Note: {skipped} snapshots with sealed checksums or a private layout are not taken into account"
+        );
+        println!();
+    }
+
+    let now = date::now();
+    let mut rows = Vec::new();
+    // secrets whose last rotation can't be told, reported on their own rather than failing the
+    // whole report
+    let mut unknown = Vec::new();
+    for secret in &secrets {
+        if secret.rotate_every.is_none() && secret.expires.is_none() {
+            continue;
+        }
+
+        let mut deadlines = Vec::new();
+        let mut last = None;
+        if let Some(days) = secret.rotate_every {
+            let rotation = match last_rotation(&source, container.as_ref(), &snapshots, secret) {
+                Ok(rotation) => rotation,
+                Err(e) => {
+                    unknown.push((secret.path.clone(), e));
+                    continue;
+                }
+            };
+            deadlines.push((rotation.at + days * 86400, "rotation due"));
+            last = Some(rotation);
+        }
+        if let Some(expires) = secret.expires {
+            deadlines.push((expires, "expires"));
+        }
+        let (deadline, reason) = deadlines.into_iter().min().expect("at least one deadline");
+
+        let status = if deadline <= now {
+            Status::Overdue
+        } else if deadline <= now + window {
+            Status::DueSoon
+        } else {
+            Status::Ok
+        };
+
+        rows.push((status, deadline, reason, secret.path.clone(), last));
+    }
+    rows.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap().then(a.1.cmp(&b.1)));
+
+    if rows.is_empty() && unknown.is_empty() {
+        println!("No secret in the manifest has a rotate-every= or expires= annotation.");
+        return Ok(());
+    }
+
+    let width = rows
+        .iter()
+        .map(|r| &r.3)
+        .chain(unknown.iter().map(|(path, _)| path))
+        .map(|path| path.as_str().len())
+        .max()
+        .unwrap_or(0);
+    for (status, deadline, reason, path, last) in &rows {
+        let status = match status {
+            Status::Overdue => "OVERDUE ",
+            Status::DueSoon => "due soon",
+            Status::Ok => "ok      ",
+        };
+        let last = match last {
+            Some(LastRotation { at, exact: true }) => {
+                format!(", last rotated {}", date::format_date(*at))
+            }
+            Some(LastRotation { at, exact: false }) => {
+                format!(", last rotated {} or earlier", date::format_date(*at))
+            }
+            None => String::new(),
+        };
+        println!(
+            "{status}  {:width$}  {reason} {}{last}",
+            path.as_str(),
+            date::format_date(*deadline)
+        );
+    }
+    for (path, e) in &unknown {
+        println!("unknown   {:width$}  last rotation unknown", path.as_str());
+        for line in e.to_string().lines() {
+            println!("    {line}");
+        }
+    }
+    println!();
+
+    let overdue = rows.iter().filter(|r| r.0 == Status::Overdue).count();
+    let due_soon = rows.iter().filter(|r| r.0 == Status::DueSoon).count();
+    let unknown = unknown.len();
+    if overdue > 0 || (strict && (due_soon > 0 || unknown > 0)) {
+        return Err(RotationError::Overdue {
+            overdue,
+            due_soon,
+            unknown,
+        });
+    }
+
+    println!("{overdue} secrets overdue, {due_soon} due soon, {unknown} unknown.");
+
+    Ok(())
+}
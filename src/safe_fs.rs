@@ -1,7 +1,7 @@
 use std::{
     fs::{self, OpenOptions},
     io::Write,
-    os::unix::fs::OpenOptionsExt,
+    os::unix::fs::{OpenOptionsExt, symlink},
 };
 
 use thiserror::Error;
@@ -10,6 +10,14 @@ use camino::Utf8PathBuf;
 
 use crate::utf8path_ext::ExtraUtf8Path;
 
+// What a safe write did: since differing content is refused, a path is either created or left as it
+// already was
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Written {
+    Created,
+    Unchanged,
+}
+
 #[derive(Error, Debug)]
 pub enum SafeFsError {
     #[error(
@@ -24,6 +32,17 @@ pub enum SafeFsError {
 
     #[error("failed to write content to file at '{0}'\n{1}")]
     Write(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to read '{0}' to check if it is already the symlink meant to be created\n{1}")]
+    ReadExistingLink(Utf8PathBuf, std::io::Error),
+
+    #[error(
+        "'{0}' already exists and is not a symlink to '{1}'. Refusing to override it for safety measures"
+    )]
+    LinkMismatch(Utf8PathBuf, Utf8PathBuf),
+
+    #[error("failed to create symlink at '{0}'\n{1}")]
+    Symlink(Utf8PathBuf, std::io::Error),
 }
 impl SafeFsError {
     fn read_existing(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
@@ -37,22 +56,42 @@ impl SafeFsError {
     fn write(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
         |e| Self::Write(path.clone(), e)
     }
+
+    fn read_existing_link(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::ReadExistingLink(path.clone(), e)
+    }
+
+    fn symlink(path: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::Symlink(path.clone(), e)
+    }
+}
+
+// What safe_write would do, without writing anything. The existing content is compared through
+// `matches`, so that a dry run can check it against a digest rather than the content itself
+pub fn check_write(
+    path: &Utf8PathBuf,
+    matches: impl FnOnce(&[u8]) -> bool,
+) -> Result<Written, SafeFsError> {
+    if !path.exists() {
+        return Ok(Written::Created);
+    }
+
+    let actual_content = fs::read(path).map_err(SafeFsError::read_existing(path))?;
+    if !matches(&actual_content) {
+        return Err(SafeFsError::content_mismatch(path));
+    }
+
+    Ok(Written::Unchanged)
 }
 
-pub fn safe_write<C>(path: &Utf8PathBuf, content: C) -> Result<(), SafeFsError>
+pub fn safe_write<C>(path: &Utf8PathBuf, content: C) -> Result<Written, SafeFsError>
 where
     C: AsRef<[u8]>,
 {
     let content = content.as_ref();
 
-    if path.exists() {
-        let actual_content = fs::read(path).map_err(SafeFsError::read_existing(path))?;
-
-        if content != actual_content {
-            return Err(SafeFsError::content_mismatch(path));
-        }
-
-        return Ok(());
+    if check_write(path, |actual_content| actual_content == content)? == Written::Unchanged {
+        return Ok(Written::Unchanged);
     }
 
     let tmp = path.add_extension("partial-import");
@@ -73,7 +112,50 @@ where
         drop(file);
 
         fs::rename(&tmp, path).map_err(SafeFsError::write(path))?;
-        Ok(())
+        Ok(Written::Created)
+    };
+
+    commit().inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+// What safe_symlink would do, without creating anything
+pub fn check_symlink(path: &Utf8PathBuf, target: &Utf8PathBuf) -> Result<Written, SafeFsError> {
+    // `exists` follows symlinks, so a dangling link would be missed
+    if path.symlink_metadata().is_err() {
+        return Ok(Written::Created);
+    }
+
+    let actual_target = if path.is_symlink() {
+        Some(
+            path.read_link_utf8()
+                .map_err(SafeFsError::read_existing_link(path))?,
+        )
+    } else {
+        None
+    };
+    if actual_target.as_ref() != Some(target) {
+        return Err(SafeFsError::LinkMismatch(path.clone(), target.clone()));
+    }
+
+    Ok(Written::Unchanged)
+}
+
+pub fn safe_symlink(path: &Utf8PathBuf, target: &Utf8PathBuf) -> Result<Written, SafeFsError> {
+    if check_symlink(path, target)? == Written::Unchanged {
+        return Ok(Written::Unchanged);
+    }
+
+    let tmp = path.add_extension("partial-import");
+    if tmp.symlink_metadata().is_ok() {
+        fs::remove_file(&tmp).map_err(SafeFsError::write(&tmp))?;
+    }
+
+    let commit = || {
+        symlink(target, &tmp).map_err(SafeFsError::symlink(&tmp))?;
+        fs::rename(&tmp, path).map_err(SafeFsError::symlink(path))?;
+        Ok(Written::Created)
     };
 
     commit().inspect_err(|_| {
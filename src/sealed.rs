@@ -0,0 +1,79 @@
+use std::io;
+
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use crate::crypto;
+use crate::snapshot;
+
+// Plaintext digests of the secrets of a snapshot exported with --seal-checksums, encrypted together
+// with the passphrase instead of lying next to each secret as `.sha256` sidecars, where the digest
+// of a short secret could be brute-forced without the passphrase. Its content is in the format of
+// `sha256sum`, with paths relative to the snapshot
+pub const SEALED_FILENAME: &str = "checksums.age";
+
+#[derive(Error, Debug)]
+pub enum SealedError {
+    #[error("failed to read the sealed checksums of snapshot '{0}'\n{1}")]
+    Read(Utf8PathBuf, io::Error),
+
+    #[error("failed to decrypt the sealed checksums of snapshot '{0}'\n{1}")]
+    Decrypt(Utf8PathBuf, age::DecryptError),
+
+    #[error("the sealed checksums of snapshot '{0}' are ill-formatted")]
+    IllFormatted(Utf8PathBuf),
+}
+
+// The digests of a sealed snapshot, by secret path
+pub struct Checksums(Vec<(Utf8PathBuf, String)>);
+impl Checksums {
+    pub fn digest(&self, path: &Utf8PathBuf) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, digest)| digest.as_str())
+    }
+
+    // The `.sha256` sidecar the secret would have had, had the snapshot not been sealed
+    pub fn sidecar(&self, path: &Utf8PathBuf) -> Option<String> {
+        let file_name = path.file_name()?;
+        self.digest(path)
+            .map(|digest| format!("{digest}  {file_name}\n"))
+    }
+}
+
+pub fn render(digests: &[(Utf8PathBuf, String)]) -> String {
+    digests
+        .iter()
+        .map(|(path, digest)| format!("{digest}  {path}\n"))
+        .collect()
+}
+
+pub fn is_sealed(snapshot: &Utf8PathBuf) -> bool {
+    snapshot::read_file(snapshot, &Utf8PathBuf::from(SEALED_FILENAME)).is_ok()
+}
+
+// Decrypts the digests of a snapshot, directory or archive. Snapshots that were not sealed have
+// no sealed checksums, which reads as None
+pub fn read(snapshot: &Utf8PathBuf, passphrase: &str) -> Result<Option<Checksums>, SealedError> {
+    let encrypted = match snapshot::read_file(snapshot, &Utf8PathBuf::from(SEALED_FILENAME)) {
+        Ok(encrypted) => encrypted,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(SealedError::Read(snapshot.clone(), e)),
+    };
+    let content = crypto::decrypt(encrypted, passphrase)
+        .map_err(|e| SealedError::Decrypt(snapshot.clone(), e))?;
+
+    let invalid = || SealedError::IllFormatted(snapshot.clone());
+    let content = String::from_utf8(content).map_err(|_| invalid())?;
+    let mut digests = Vec::new();
+    for line in content.lines() {
+        let (digest, path) = line.split_once("  ").ok_or_else(invalid)?;
+        if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        digests.push((Utf8PathBuf::from(path), digest.to_lowercase()));
+    }
+
+    Ok(Some(Checksums(digests)))
+}
@@ -1,28 +1,38 @@
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::{fs, io};
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    os::unix::fs::DirBuilderExt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use camino::Utf8PathBuf;
 use regex::Regex;
+use thiserror::Error;
 
+use crate::checksum;
+use crate::date;
+use crate::layout;
 use crate::manifest;
+use crate::utf8path_ext::ExtraUtf8Path;
 
 const PARTIAL_PREFIX: &str = ".partial-";
 
-// Dependency-less conversion from "days since 1970" into (year, month, day), using
-// Hinnant's civil_from_days: https://howardhinnant.github.io/date_algorithms.html
-fn civil_from_days(days: i64) -> (i64, i64, i64) {
-    let z = days + 719468;
-    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
-    let doe = z - era * 146097;
-    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
-    let y = yoe + era * 400;
-    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
-    let mp = (5 * doy + 2) / 153;
-    let d = doy - (153 * mp + 2) / 5 + 1;
-    let m = if mp < 10 { mp + 3 } else { mp - 9 };
-    let y = if m <= 2 { y + 1 } else { y };
+// How export writes a snapshot: as a directory, or as a single `export-<timestamp>.tar` archive of
+// that same directory, with an outer `.sha256`
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    Dir,
+    Tar,
+}
+
+#[derive(Error, Debug)]
+pub enum OpenError {
+    #[error("failed to verify the integrity of snapshot archive '{0}'\n{1}")]
+    Verify(Utf8PathBuf, checksum::ChecksumError),
 
-    (y, m, d)
+    #[error("failed to extract snapshot archive '{0}'\n{1}")]
+    Extract(Utf8PathBuf, io::Error),
 }
 
 fn utc_timestamp() -> String {
@@ -32,7 +42,7 @@ fn utc_timestamp() -> String {
         .as_secs();
 
     let days_since_epoch = (seconds_since_epoch / 86400) as i64;
-    let (year, month, day) = civil_from_days(days_since_epoch);
+    let (year, month, day) = date::civil_from_days(days_since_epoch);
 
     let time_of_day = seconds_since_epoch % 86400;
     let hour = time_of_day / 3600;
@@ -60,14 +70,39 @@ fn is_snapshot_name(name: &str) -> bool {
     re.is_match(name)
 }
 
+// Whether a path is a snapshot archive written by `export --format tar`
+pub fn is_tar(path: &Utf8PathBuf) -> bool {
+    path.is_file()
+        && path
+            .file_name()
+            .and_then(|name| name.strip_suffix(".tar"))
+            .is_some_and(is_snapshot_name)
+}
+
+// Seconds since the epoch at which the snapshot with the given name (directory or archive) was taken
+pub fn timestamp(name: &str) -> Option<i64> {
+    let re =
+        Regex::new(r"^export-(\d{4})-(\d{2})-(\d{2})_(\d{2})-(\d{2})-(\d{2})Z(\.tar)?$").unwrap();
+    let caps = re.captures(name)?;
+    let fields = [1, 2, 3, 4, 5, 6].map(|i| caps.get(i).unwrap().as_str());
+    let [year, month, day, hour, minute, second] = fields.map(|f| f.parse::<i64>().unwrap());
+
+    Some(date::days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
 pub enum SourceKind {
     Snapshot,
     Container,
     Neither,
 }
 
+// Snapshots with a private layout hold an encrypted index instead of a manifest
+fn has_manifest(dir: &Utf8PathBuf) -> bool {
+    dir.join(manifest::MANIFEST_FILENAME).exists() || dir.join(layout::INDEX_FILENAME).exists()
+}
+
 pub fn classify(path: &Utf8PathBuf) -> SourceKind {
-    if path.join(manifest::MANIFEST_FILENAME).exists() {
+    if has_manifest(path) || is_tar(path) {
         SourceKind::Snapshot
     } else if list_snapshots(path).is_ok_and(|snapshots| !snapshots.is_empty()) {
         SourceKind::Container
@@ -83,13 +118,12 @@ pub fn list_snapshots(container: &Utf8PathBuf) -> io::Result<Vec<Utf8PathBuf>> {
         let Ok(path) = Utf8PathBuf::from_path_buf(entry.path()) else {
             continue;
         };
-        if !entry.file_type()?.is_dir() {
-            continue;
-        }
         let Some(name) = path.file_name() else {
             continue;
         };
-        if is_snapshot_name(name) && path.join(manifest::MANIFEST_FILENAME).exists() {
+        let is_dir_snapshot =
+            entry.file_type()?.is_dir() && is_snapshot_name(name) && has_manifest(&path);
+        if is_dir_snapshot || is_tar(&path) {
             snapshots.push(Utf8PathBuf::from(name));
         }
     }
@@ -97,6 +131,144 @@ pub fn list_snapshots(container: &Utf8PathBuf) -> io::Result<Vec<Utf8PathBuf>> {
     Ok(snapshots)
 }
 
+// Name of the directory a snapshot archive holds, which is the archive name without `.tar`
+fn tar_root(archive: &Utf8PathBuf) -> &str {
+    let name = archive.file_name().expect("archives have a file name");
+    name.strip_suffix(".tar").unwrap_or(name)
+}
+
+// A snapshot opened for reading, as a directory. Archives are checked against their outer
+// `.sha256` and extracted to a private temporary directory, removed once done
+pub struct Opened {
+    pub dir: Utf8PathBuf,
+    extracted: Option<Utf8PathBuf>,
+}
+impl Drop for Opened {
+    fn drop(&mut self) {
+        if let Some(extracted) = &self.extracted {
+            let _ = fs::remove_dir_all(extracted);
+        }
+    }
+}
+
+pub fn open(snapshot: &Utf8PathBuf) -> Result<Opened, OpenError> {
+    if !is_tar(snapshot) {
+        return Ok(Opened {
+            dir: snapshot.clone(),
+            extracted: None,
+        });
+    }
+
+    checksum::verify_file_checksum(snapshot).map_err(|e| OpenError::Verify(snapshot.clone(), e))?;
+
+    let extract_error = |e| OpenError::Extract(snapshot.clone(), e);
+    let temp_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+        .map_err(|_| extract_error(io::Error::other("temporary directory is not utf8")))?;
+    // numbered, so that an archive opened twice at the same time gets extracted twice
+    static OPENED: AtomicUsize = AtomicUsize::new(0);
+    let extracted = temp_dir.join(format!(
+        "secs-man-{}-{}-{}",
+        std::process::id(),
+        OPENED.fetch_add(1, Ordering::SeqCst),
+        tar_root(snapshot)
+    ));
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&extracted)
+        .map_err(extract_error)?;
+    let opened = Opened {
+        dir: extracted.join(tar_root(snapshot)),
+        extracted: Some(extracted.clone()),
+    };
+
+    let file = File::open(snapshot).map_err(extract_error)?;
+    tar::Archive::new(file)
+        .unpack(&extracted)
+        .map_err(extract_error)?;
+
+    Ok(opened)
+}
+
+// Reads a single file of a snapshot, without extracting the whole archive. The outer `.sha256` of
+// an archive is not checked, the caller only looks at plaintext metadata of the snapshot
+pub fn read_file(snapshot: &Utf8PathBuf, rel_path: &Utf8PathBuf) -> io::Result<Vec<u8>> {
+    if !is_tar(snapshot) {
+        return fs::read(snapshot.join(rel_path));
+    }
+
+    let wanted = Utf8PathBuf::from(tar_root(snapshot)).join(rel_path);
+    let mut archive = tar::Archive::new(File::open(snapshot)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_ref() == wanted.as_std_path() {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            return Ok(content);
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("'{rel_path}' is not in archive '{snapshot}'"),
+    ))
+}
+
+// Packs a snapshot directory into an archive next to it, holding the directory under the name of
+// the archive, and writes its outer `.sha256`. The archive is written under a partial name first
+pub fn pack(dir: &Utf8PathBuf, archive: &Utf8PathBuf) -> io::Result<()> {
+    let container = archive.parent().expect("archives are inside a container");
+    let name = archive.file_name().expect("archives have a file name");
+    let partial = container.join(to_partial(name));
+
+    let mut builder = tar::Builder::new(File::create(&partial)?);
+    builder.mode(tar::HeaderMode::Deterministic);
+    builder.append_dir_all(tar_root(archive), dir)?;
+    builder.into_inner()?.sync_all()?;
+
+    let digest = sha256::digest(fs::read(&partial)?);
+    fs::rename(&partial, archive)?;
+    // only once the archive is in place, so that an interrupted export never leaves a sidecar
+    // without its archive
+    fs::write(
+        archive.add_extension("sha256"),
+        format!("{digest}  {name}\n"),
+    )?;
+
+    Ok(())
+}
+
+// Snapshots left partial by interrupted exports (directories, or archives being packed)
+pub fn list_partials(container: &Utf8PathBuf) -> io::Result<Vec<Utf8PathBuf>> {
+    let mut partials = Vec::new();
+    for entry in fs::read_dir(container)? {
+        let entry = entry?;
+        let Ok(path) = Utf8PathBuf::from_path_buf(entry.path()) else {
+            continue;
+        };
+        if path.file_name().is_some_and(is_partial) {
+            partials.push(path);
+        }
+    }
+    partials.sort();
+
+    Ok(partials)
+}
+
+// Removes a snapshot, partial or not, along with the outer `.sha256` of an archive
+pub fn remove(path: &Utf8PathBuf) -> io::Result<()> {
+    if path.is_dir() {
+        return fs::remove_dir_all(path);
+    }
+
+    fs::remove_file(path)?;
+    let sha_path = path.add_extension("sha256");
+    if is_tar(path) || sha_path.exists() {
+        fs::remove_file(sha_path)?;
+    }
+
+    Ok(())
+}
+
 pub fn newest(container: &Utf8PathBuf) -> io::Result<Option<Utf8PathBuf>> {
     Ok(list_snapshots(container)?.into_iter().max())
 }
@@ -0,0 +1,132 @@
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use camino::Utf8PathBuf;
+
+use crate::export;
+use crate::hooks;
+use crate::import;
+use crate::manifest;
+use crate::snapshot;
+
+pub const PASSPHRASE: &str = "correct horse battery staple";
+
+// A directory of its own for each test, removed once the test is over. It holds an empty config,
+// so that the config of the host running the tests is never used
+pub struct TempDir {
+    path: Utf8PathBuf,
+}
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "secs-man-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        let path = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .expect("the temp directory is valid UTF-8")
+            .join(name);
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o700)).unwrap();
+        fs::write(path.join("config"), "").unwrap();
+
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Utf8PathBuf {
+        &self.path
+    }
+
+    // A new empty directory inside of it
+    pub fn dir(&self, name: &str) -> Utf8PathBuf {
+        let dir = self.path.join(name);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    // A new secrets directory holding the given files, each listed in its manifest (export
+    // generates their sidecars)
+    pub fn secrets_dir(&self, name: &str, files: &[(&str, &str)]) -> Utf8PathBuf {
+        let dir = self.dir(name);
+        let mut manifest = String::new();
+        for (path, content) in files {
+            let file = dir.join(path);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(&file, content).unwrap();
+            manifest.push_str(&format!("{path}\n"));
+        }
+        fs::write(dir.join(manifest::MANIFEST_FILENAME), manifest).unwrap();
+
+        dir
+    }
+
+    pub fn export_options(&self) -> export::ExportOptions {
+        export::ExportOptions {
+            skip_validation: false,
+            capture_metadata: false,
+            capture_xattrs: false,
+            config: Some(self.path.join("config").to_string()),
+            no_hooks: true,
+            dedup: false,
+            format: snapshot::Format::Dir,
+            seal_checksums: false,
+            private_layout: false,
+            label: None,
+            note: None,
+            best_effort: false,
+            dry_run: false,
+        }
+    }
+
+    pub fn import_options(&self) -> import::ImportOptions {
+        import::ImportOptions {
+            skip_chown_chmod: false,
+            skip_validation: false,
+            map_owner: Vec::new(),
+            owner_map: None,
+            deploy: false,
+            config: Some(self.path.join("config").to_string()),
+            no_hooks: true,
+            dry_run_hooks: false,
+            hook_failure: hooks::FailurePolicy::Abort,
+            dry_run: false,
+            label: None,
+        }
+    }
+}
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+pub fn encrypted() -> import::SourceType {
+    import::SourceType::Encrypted {
+        passphrase: PASSPHRASE.to_string(),
+    }
+}
+
+// Exports a secrets directory to a container, returning the path of the snapshot written
+pub fn export(
+    source: &Utf8PathBuf,
+    container: &Utf8PathBuf,
+    options: export::ExportOptions,
+) -> Utf8PathBuf {
+    export::export(
+        source.to_string(),
+        vec![container.to_string()],
+        PASSPHRASE.to_string(),
+        options,
+    )
+    .unwrap();
+
+    let name = snapshot::newest(container)
+        .unwrap()
+        .expect("a snapshot was written");
+    container.join(name)
+}
@@ -0,0 +1,111 @@
+use std::{fs, io};
+
+const PASSWD_PATH: &str = "/etc/passwd";
+const GROUP_PATH: &str = "/etc/group";
+
+// Reads the name, the numeric id (third field) and the fourth field of every entry of a
+// colon-separated database file, such as /etc/passwd and /etc/group
+fn read_entries(path: &str) -> io::Result<Vec<(String, u32, String)>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let _password = fields.next()?;
+            let id = fields.next()?.parse().ok()?;
+            let fourth = fields.next().unwrap_or("");
+            Some((name.to_string(), id, fourth.to_string()))
+        })
+        .collect())
+}
+
+// Users and groups of this host, read straight from /etc/passwd and /etc/group. Entries provided
+// only through NSS (LDAP, systemd-homed, ...) are not visible here
+#[derive(Default)]
+pub struct Database {
+    // name, uid and login gid
+    users: Vec<(String, u32, Option<u32>)>,
+    groups: Vec<(String, u32)>,
+}
+impl Database {
+    pub fn load() -> io::Result<Self> {
+        Ok(Self {
+            users: read_entries(PASSWD_PATH)?
+                .into_iter()
+                .map(|(name, uid, gid)| (name, uid, gid.parse().ok()))
+                .collect(),
+            groups: read_entries(GROUP_PATH)?
+                .into_iter()
+                .map(|(name, gid, _members)| (name, gid))
+                .collect(),
+        })
+    }
+
+    pub fn has_user(&self, name: &str) -> bool {
+        self.users.iter().any(|(n, _, _)| n == name)
+    }
+
+    pub fn has_group(&self, name: &str) -> bool {
+        self.groups.iter().any(|(n, _)| n == name)
+    }
+
+    pub fn uid(&self, name: &str) -> Option<u32> {
+        self.users
+            .iter()
+            .find(|(n, _, _)| n == name)
+            .map(|(_, id, _)| *id)
+    }
+
+    pub fn login_gid(&self, uid: u32) -> Option<u32> {
+        self.users
+            .iter()
+            .find(|(_, id, _)| *id == uid)
+            .and_then(|(_, _, gid)| *gid)
+    }
+
+    pub fn gid(&self, name: &str) -> Option<u32> {
+        self.groups
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, id)| *id)
+    }
+
+    pub fn user_name(&self, uid: u32) -> Option<&str> {
+        self.users
+            .iter()
+            .find(|(_, id, _)| *id == uid)
+            .map(|(n, _, _)| n.as_str())
+    }
+
+    pub fn group_name(&self, gid: u32) -> Option<&str> {
+        self.groups
+            .iter()
+            .find(|(_, id)| *id == gid)
+            .map(|(n, _)| n.as_str())
+    }
+
+    // Formats a uid/gid pair as a chown spec, preferring names and falling back to numeric ids
+    // for the ones that have no entry on this host, or a name that a chown spec can't hold (one
+    // starting with `-`, which the system allows)
+    pub fn owner_spec(&self, uid: u32, gid: u32) -> String {
+        let user = self
+            .user_name(uid)
+            .filter(|name| fits_spec(name))
+            .map(String::from)
+            .unwrap_or(uid.to_string());
+        let group = self
+            .group_name(gid)
+            .filter(|name| fits_spec(name))
+            .map(String::from)
+            .unwrap_or(gid.to_string());
+        format!("{user}:{group}")
+    }
+}
+
+fn fits_spec(name: &str) -> bool {
+    !name.starts_with('-') && !name.contains(':') && !name.contains(char::is_whitespace)
+}
+
+pub fn is_numeric_id(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit())
+}
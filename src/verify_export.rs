@@ -1,10 +1,33 @@
-use std::io::Write;
+use std::{
+    fs,
+    io::{Read, Write},
+};
 
 use camino::Utf8PathBuf;
 use thiserror::Error;
 
+use crate::cert;
+use crate::cert_expiry;
 use crate::checksum;
+use crate::config;
+use crate::crypto;
+use crate::hooks;
+use crate::kind;
+use crate::layout;
+use crate::manifest;
+use crate::objects;
+use crate::recovery;
+use crate::sealed;
 use crate::snapshot;
+use crate::utf8path_ext::ExtraUtf8Path;
+
+// Deep verification: the encrypted files are decrypted in memory and their plaintext checked
+// against the checksums it was exported with
+pub struct Decrypt {
+    pub passphrase: String,
+    // only decrypt this many files, drawn at random across the snapshots
+    pub sample: Option<usize>,
+}
 
 #[derive(Error, Debug)]
 pub enum VerifyExportError {
@@ -22,32 +45,345 @@ pub enum VerifyExportError {
     #[error("source '{0}' is neither a snapshot nor a container of snapshots")]
     NotSnapshotOrContainer(Utf8PathBuf),
 
+    #[error(transparent)]
+    CertWindow(cert_expiry::CertExpiryError),
+
+    #[error("failed to read the certificate expiries recorded in snapshot '{0}'\n{1}")]
+    ReadCertExpiries(Utf8PathBuf, std::io::Error),
+
     #[error("{failed} of {total} snapshots failed verification")]
     SnapshotsFailed { failed: usize, total: usize },
 
+    #[error("failed to verify the shared objects of container '{0}'\n{1}")]
+    VerifyObjects(Utf8PathBuf, std::io::Error),
+
+    #[error("{0} shared objects are corrupted")]
+    ObjectsCorrupted(usize),
+
     #[error(transparent)]
     VerifySource(checksum::ChecksumError),
+
+    #[error(transparent)]
+    OpenSnapshot(snapshot::OpenError),
+
+    #[error("failed to load config\n{0}")]
+    LoadConfig(config::ConfigError),
+
+    #[error("the export was verified, but the post-verify hook failed\n{0}")]
+    PostHook(hooks::HookError),
+
+    #[error(transparent)]
+    Unseal(sealed::SealedError),
+
+    #[error(transparent)]
+    ReadIndex(layout::IndexError),
+
+    #[error("failed to load the manifest of snapshot '{0}'\n{1}")]
+    LoadManifest(Utf8PathBuf, manifest::ManifestError),
+
+    #[error("failed to draw the files to decrypt\n{0}")]
+    Sample(std::io::Error),
+
+    #[error("{failed} of {total} encrypted files failed to decrypt to their checksum")]
+    FilesFailed { failed: usize, total: usize },
 }
 impl VerifyExportError {
     fn list_snapshots(container: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
         |e| Self::ListSnapshots(container.clone(), e)
     }
+
+    fn read_cert_expiries(snapshot: &Utf8PathBuf) -> impl Fn(std::io::Error) -> Self {
+        |e| Self::ReadCertExpiries(snapshot.clone(), e)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum DecryptFileError {
+    #[error("failed to read '{0}'\n{1}")]
+    Read(Utf8PathBuf, std::io::Error),
+
+    #[error("failed to decrypt '{0}'\n{1}")]
+    Decrypt(Utf8PathBuf, age::DecryptError),
+
+    #[error("the sealed checksums of the snapshot have no checksum for '{0}'")]
+    MissingSealedChecksum(Utf8PathBuf),
+
+    #[error(transparent)]
+    Verify(checksum::ChecksumError),
+}
+
+// What the plaintext of an encrypted file is checked against, the paths being relative to the
+// snapshot
+enum Expected {
+    Sidecar(Utf8PathBuf),
+    // the digest in the sealed checksums at the path, if they have one
+    Sealed(Utf8PathBuf, Option<String>),
+    // the link targets of a private layout and the recovery script have no checksum, decrypting
+    // them is all there is to check
+    Nothing,
+}
+
+// An encrypted file of a snapshot, named by the secret it holds. Only the paths are kept, so that
+// the snapshot doesn't need to stay open until the file is decrypted
+struct Encrypted {
+    // the index of the snapshot in the ones verified
+    snapshot: usize,
+    path: Utf8PathBuf,
+    // relative to the snapshot
+    file: Utf8PathBuf,
+    expected: Expected,
+}
+
+// The encrypted files of an opened snapshot. The manifest of a private layout and the checksums
+// of a sealed snapshot are decrypted to find them, so a wrong passphrase fails here
+fn encrypted_files(
+    index: usize,
+    snapshot: &Utf8PathBuf,
+    dir: &Utf8PathBuf,
+    passphrase: &str,
+) -> Result<Vec<Encrypted>, VerifyExportError> {
+    let sealed = sealed::read(dir, passphrase).map_err(VerifyExportError::Unseal)?;
+    let secrets = match layout::is_private(dir) {
+        true => {
+            let index =
+                layout::read_index(dir, passphrase).map_err(VerifyExportError::ReadIndex)?;
+            manifest::parse(&dir.join(layout::INDEX_FILENAME), &index)
+        }
+        false => manifest::load_snapshot(dir),
+    }
+    .map_err(|e| VerifyExportError::LoadManifest(snapshot.clone(), e))?;
+
+    let mut files = Vec::new();
+    for secret in secrets {
+        let is_link = secret.kind == Some(kind::Kind::Symlink);
+        // the target of a link is only encrypted in a private layout
+        if is_link && secret.captured.stored.is_none() {
+            continue;
+        }
+        let expected = match &sealed {
+            _ if is_link => Expected::Nothing,
+            Some(sealed) => Expected::Sealed(
+                sealed::SEALED_FILENAME.into(),
+                sealed.digest(&secret.path).map(str::to_string),
+            ),
+            None => Expected::Sidecar(secret.path.clone().add_extension("sha256")),
+        };
+        files.push(Encrypted {
+            snapshot: index,
+            file: secret.stored_path().add_extension("age"),
+            path: secret.path,
+            expected,
+        });
+    }
+
+    let script = Utf8PathBuf::from(recovery::ENCRYPTED_SCRIPT_FILENAME);
+    if dir.join(&script).exists() {
+        files.push(Encrypted {
+            snapshot: index,
+            file: script.clone(),
+            path: script,
+            expected: Expected::Nothing,
+        });
+    }
+
+    Ok(files)
+}
+
+// Decrypts a file of the snapshot opened at dir
+fn decrypt_file(
+    file: &Encrypted,
+    dir: &Utf8PathBuf,
+    passphrase: &str,
+) -> Result<(), DecryptFileError> {
+    let path = dir.join(&file.file);
+    let encrypted = fs::read(&path).map_err(|e| DecryptFileError::Read(path.clone(), e))?;
+    let content = crypto::decrypt(encrypted, passphrase)
+        .map_err(|e| DecryptFileError::Decrypt(path.clone(), e))?;
+
+    match &file.expected {
+        Expected::Sidecar(sha_path) => {
+            checksum::verify_content(&content, &file.path, &dir.join(sha_path))
+        }
+        Expected::Sealed(sha_path, Some(digest)) => {
+            checksum::verify_digest(&content, digest, &file.path, &dir.join(sha_path))
+        }
+        Expected::Sealed(_, None) => {
+            return Err(DecryptFileError::MissingSealedChecksum(file.path.clone()));
+        }
+        Expected::Nothing => Ok(()),
+    }
+    .map_err(DecryptFileError::Verify)
+}
+
+// Draws n of the items at random, keeping their order
+fn sample<T>(items: Vec<T>, n: usize) -> std::io::Result<Vec<T>> {
+    let mut urandom = fs::File::open("/dev/urandom")?;
+    let mut indexes: Vec<usize> = (0..items.len()).collect();
+    let n = n.min(items.len());
+    for i in 0..n {
+        let mut bytes = [0u8; 8];
+        urandom.read_exact(&mut bytes)?;
+        let j = i + (u64::from_le_bytes(bytes) % (items.len() - i) as u64) as usize;
+        indexes.swap(i, j);
+    }
+    let mut drawn = indexes[..n].to_vec();
+    drawn.sort();
+
+    Ok(items
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| drawn.binary_search(i).is_ok())
+        .map(|(_, item)| item)
+        .collect())
+}
+
+// Decrypts the encrypted files of the snapshots (or a sample of them) in memory, their plaintext
+// never being written to disk
+fn verify_decryption(
+    snapshots: &[Utf8PathBuf],
+    decrypt: &Decrypt,
+) -> Result<(), VerifyExportError> {
+    // an archive is extracted to a temporary directory, opening one at a time keeps a single one
+    // there
+    let mut files = Vec::new();
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        let opened = snapshot::open(snapshot).map_err(VerifyExportError::OpenSnapshot)?;
+        files.extend(encrypted_files(
+            index,
+            snapshot,
+            &opened.dir,
+            &decrypt.passphrase,
+        )?);
+    }
+
+    let available = files.len();
+    let files = match decrypt.sample {
+        Some(n) => {
+            let files = sample(files, n).map_err(VerifyExportError::Sample)?;
+            println!(
+                "Decrypting a random sample of {} of {available} encrypted files...",
+                files.len()
+            );
+            files
+        }
+        None => {
+            println!("Decrypting {available} encrypted files...");
+            files
+        }
+    };
+
+    let total = files.len();
+    let mut failed = 0;
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        let in_snapshot: Vec<_> = files.iter().filter(|f| f.snapshot == index).collect();
+        if in_snapshot.is_empty() {
+            continue;
+        }
+        let opened = snapshot::open(snapshot).map_err(VerifyExportError::OpenSnapshot)?;
+        let name = snapshot.file_name().unwrap_or(snapshot.as_str());
+        for file in in_snapshot {
+            print!("decrypting '{}' of {name}... ", file.path);
+            std::io::stdout().flush().unwrap();
+            match decrypt_file(file, &opened.dir, &decrypt.passphrase) {
+                Ok(()) => println!("ok"),
+                Err(e) => {
+                    println!("FAILED");
+                    println!("  {e}");
+                    failed += 1;
+                }
+            }
+        }
+    }
+    println!();
+
+    if failed > 0 {
+        return Err(VerifyExportError::FilesFailed { failed, total });
+    }
+    println!("All {total} encrypted files decrypted to their checksum!");
+    println!();
+
+    Ok(())
 }
 
-fn verify_snapshot(snapshot: &Utf8PathBuf) -> Result<(), VerifyExportError> {
+fn warn_expiring_certs(snapshot: &Utf8PathBuf, cert_window: i64) -> Result<(), VerifyExportError> {
+    let expiries =
+        cert::read_expiries(snapshot).map_err(VerifyExportError::read_cert_expiries(snapshot))?;
+    cert_expiry::warn_expiring(&expiries, cert_window);
+
+    Ok(())
+}
+
+// Checks the outer `.sha256` of an archive, then the checksums of the files it holds, which are the
+// same as the ones of a directory snapshot
+fn verify_checksums(snapshot: &Utf8PathBuf) -> Result<(), VerifyExportError> {
+    let opened = snapshot::open(snapshot).map_err(VerifyExportError::OpenSnapshot)?;
+    checksum::verify_checksums(&opened.dir).map_err(VerifyExportError::VerifySource)
+}
+
+fn verify_snapshot(
+    snapshot: &Utf8PathBuf,
+    cert_window: i64,
+    decrypt: Option<&Decrypt>,
+) -> Result<(), VerifyExportError> {
     print!("Verifying export integrity... ");
     std::io::stdout().flush().unwrap();
-    checksum::verify_checksums(snapshot)
-        .map_err(VerifyExportError::VerifySource)
-        .inspect_err(|_| println!("error"))?;
+    verify_checksums(snapshot).inspect_err(|_| println!("error"))?;
     println!("ok");
     println!();
+
+    if let Some(decrypt) = decrypt {
+        verify_decryption(std::slice::from_ref(snapshot), decrypt)?;
+    }
+
+    warn_expiring_certs(snapshot, cert_window)?;
     println!("Export integrity verified successfully!");
 
     Ok(())
 }
 
-fn verify_container(container: &Utf8PathBuf) -> Result<(), VerifyExportError> {
+// Checks the objects of a deduplicated container. A corrupted object breaks every snapshot that
+// shares it, so they are reported with it (the checksums of a snapshot name the objects it holds,
+// an object being named by the checksum of its content)
+fn verify_objects(
+    container: &Utf8PathBuf,
+    snapshots: &[Utf8PathBuf],
+) -> Result<usize, VerifyExportError> {
+    print!("Verifying shared objects... ");
+    std::io::stdout().flush().unwrap();
+    let corrupted = objects::corrupted(container)
+        .map_err(|e| VerifyExportError::VerifyObjects(container.clone(), e))
+        .inspect_err(|_| println!("error"))?;
+    if corrupted.is_empty() {
+        println!("ok");
+        println!();
+        return Ok(0);
+    }
+
+    println!("FAILED");
+    for object in &corrupted {
+        let sharing: Vec<&str> = snapshots
+            .iter()
+            .filter(|name| {
+                fs::read_to_string(container.join(name).join("sha256sums.txt"))
+                    .is_ok_and(|sums| sums.lines().any(|line| line.starts_with(object.as_str())))
+            })
+            .map(|name| name.as_str())
+            .collect();
+        println!("  object '{object}' is corrupted, shared by:");
+        for name in sharing {
+            println!("    - {name}");
+        }
+    }
+    println!();
+
+    Ok(corrupted.len())
+}
+
+fn verify_container(
+    container: &Utf8PathBuf,
+    cert_window: i64,
+    decrypt: Option<&Decrypt>,
+) -> Result<(), VerifyExportError> {
     let mut snapshots = snapshot::list_snapshots(container)
         .map_err(VerifyExportError::list_snapshots(container))?;
     if snapshots.is_empty() {
@@ -55,12 +391,17 @@ fn verify_container(container: &Utf8PathBuf) -> Result<(), VerifyExportError> {
     }
     snapshots.sort();
 
+    let corrupted = match objects::is_enabled(container) {
+        true => verify_objects(container, &snapshots)?,
+        false => 0,
+    };
+
     let total = snapshots.len();
     let mut failed = 0;
     for name in &snapshots {
         print!("Verifying {name}... ");
         std::io::stdout().flush().unwrap();
-        match checksum::verify_checksums(&container.join(name)) {
+        match verify_checksums(&container.join(name)) {
             Ok(()) => println!("ok"),
             Err(e) => {
                 println!("FAILED");
@@ -74,26 +415,69 @@ fn verify_container(container: &Utf8PathBuf) -> Result<(), VerifyExportError> {
     if failed > 0 {
         return Err(VerifyExportError::SnapshotsFailed { failed, total });
     }
+    if corrupted > 0 {
+        return Err(VerifyExportError::ObjectsCorrupted(corrupted));
+    }
+
+    if let Some(decrypt) = decrypt {
+        let paths: Vec<_> = snapshots.iter().map(|name| container.join(name)).collect();
+        verify_decryption(&paths, decrypt)?;
+    }
+
+    // only the newest snapshot holds the certificates that are actually deployed
+    if let Some(newest) = snapshots.last() {
+        warn_expiring_certs(&container.join(newest), cert_window)?;
+    }
 
     println!("All {total} snapshots verified successfully!");
 
     Ok(())
 }
 
-pub fn verify_export(source: String) -> Result<(), VerifyExportError> {
+pub fn verify_export(
+    source: String,
+    cert_window: String,
+    config: Option<String>,
+    no_hooks: bool,
+    decrypt: Option<Decrypt>,
+) -> Result<(), VerifyExportError> {
     let source = {
         let path = Utf8PathBuf::from(&source);
         if !path.exists() {
             return Err(VerifyExportError::MissingSourcePath(path));
-        } else if !path.is_dir() {
+        } else if !path.is_dir() && !snapshot::is_tar(&path) {
             return Err(VerifyExportError::SourceNotDir(path));
         }
         path
     };
 
-    match snapshot::classify(&source) {
-        snapshot::SourceKind::Snapshot => verify_snapshot(&source),
-        snapshot::SourceKind::Container => verify_container(&source),
-        snapshot::SourceKind::Neither => Err(VerifyExportError::NotSnapshotOrContainer(source)),
-    }
+    let cert_window =
+        cert_expiry::parse_window(cert_window).map_err(VerifyExportError::CertWindow)?;
+
+    let config = match no_hooks {
+        true => config::Config::default(),
+        false => config::load(config.as_deref()).map_err(VerifyExportError::LoadConfig)?,
+    };
+
+    let (result, env) = match snapshot::classify(&source) {
+        snapshot::SourceKind::Snapshot => (
+            verify_snapshot(&source, cert_window, decrypt.as_ref()),
+            vec![("SECS_MAN_SNAPSHOT", source.to_string())],
+        ),
+        snapshot::SourceKind::Container => (
+            verify_container(&source, cert_window, decrypt.as_ref()),
+            vec![("SECS_MAN_CONTAINER", source.to_string())],
+        ),
+        snapshot::SourceKind::Neither => {
+            return Err(VerifyExportError::NotSnapshotOrContainer(source));
+        }
+    };
+
+    hooks::run_post_hook(
+        &config,
+        hooks::Hook::PostVerify,
+        env,
+        result,
+        VerifyExportError::PostHook,
+    )
 }
@@ -0,0 +1,99 @@
+use std::{ffi::CString, io, os::unix::ffi::OsStrExt};
+
+use camino::Utf8PathBuf;
+
+// The access ACL of a file is stored by the kernel as this extended attribute, so restoring the
+// attribute restores the ACL
+const ACL_ACCESS: &str = "system.posix_acl_access";
+
+// Whether an attribute is worth carrying across hosts: `user.*` attributes and the access ACL.
+// `security.*` and `trusted.*` ones are host policy (SELinux labels, capabilities) and are left out
+pub fn is_captured(name: &str) -> bool {
+    name.starts_with("user.") || name == ACL_ACCESS
+}
+
+fn c_path(path: &Utf8PathBuf) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)
+}
+
+fn c_name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(io::Error::other)
+}
+
+// Calls a size-querying libc function twice: once to get the required size, once to fill a
+// buffer of that size
+fn read_sized(mut call: impl FnMut(*mut libc::c_void, usize) -> isize) -> io::Result<Vec<u8>> {
+    loop {
+        let size = call(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buffer = vec![0u8; size as usize];
+        let read = call(buffer.as_mut_ptr().cast(), buffer.len());
+        if read >= 0 {
+            buffer.truncate(read as usize);
+            return Ok(buffer);
+        }
+
+        // the attribute grew between the two calls, try again
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::ERANGE) {
+            return Err(error);
+        }
+    }
+}
+
+// Reads the captured extended attributes of a file (see is_captured), sorted by name. Attribute
+// names that are not utf8 or that contain whitespace or '=' can't be written in the manifest and
+// are skipped
+pub fn read_all(path: &Utf8PathBuf) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let c_path = c_path(path)?;
+
+    let names = match read_sized(|buffer, size| unsafe {
+        libc::listxattr(c_path.as_ptr(), buffer.cast(), size)
+    }) {
+        Ok(names) => names,
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut xattrs = Vec::new();
+    for name in names.split(|b| *b == 0).filter(|n| !n.is_empty()) {
+        let Ok(name) = std::str::from_utf8(name) else {
+            continue;
+        };
+        if !is_captured(name) || name.contains(|c: char| c.is_whitespace() || c == '=') {
+            continue;
+        }
+
+        let c_name = c_name(name)?;
+        let value = read_sized(|buffer, size| unsafe {
+            libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), buffer, size)
+        })?;
+        xattrs.push((name.to_string(), value));
+    }
+    xattrs.sort();
+
+    Ok(xattrs)
+}
+
+pub fn set(path: &Utf8PathBuf, name: &str, value: &[u8]) -> io::Result<()> {
+    let c_path = c_path(path)?;
+    let c_name = c_name(name)?;
+
+    let result = unsafe {
+        libc::setxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}